@@ -0,0 +1,21 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use lapin::bench::ConsumeBench;
+
+fn consume(c: &mut Criterion) {
+    let mut group = c.benchmark_group("consume");
+    for payload_size in &[16, 4096] {
+        // Payloads copied out of the received data, as with external io drivers, or views on it
+        for (shared, label) in &[(false, ""), (true, " shared")] {
+            let bench = ConsumeBench::new(1000, *payload_size, *shared);
+            // Reported as ns per frame
+            group.throughput(Throughput::Elements(bench.frames() as u64));
+            group.bench_function(format!("{} bytes{} payloads", payload_size, label), |b| {
+                b.iter(|| bench.run())
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, consume);
+criterion_main!(benches);
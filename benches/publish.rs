@@ -0,0 +1,21 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use lapin::bench::PublishBench;
+
+fn publish(c: &mut Criterion) {
+    let mut group = c.benchmark_group("publish");
+    for payload_size in &[16, 4096, 1024 * 1024] {
+        // A copy of the payload per message, as when publishing from a Vec, or a shared one
+        for (shared, label) in &[(false, ""), (true, " shared")] {
+            let mut bench = PublishBench::new(100, *payload_size, 131_072, *shared);
+            // Reported as ns per message
+            group.throughput(Throughput::Elements(bench.messages()));
+            group.bench_function(format!("{} bytes{} payloads", payload_size, label), |b| {
+                b.iter(|| bench.run())
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, publish);
+criterion_main!(benches);
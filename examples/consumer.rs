@@ -44,8 +44,8 @@ fn main() {
     for delivery in consumer {
         info!("received message: {:?}", delivery);
         if let Ok(delivery) = delivery {
-            channel
-                .basic_ack(delivery.delivery_tag, BasicAckOptions::default())
+            delivery
+                .ack(&channel, BasicAckOptions::default())
                 .wait()
                 .expect("basic_ack");
         }
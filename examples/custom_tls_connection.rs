@@ -21,7 +21,8 @@ fn connect() -> Result<Connection> {
     let conn = addr
         .connect(|stream, uri, poll| {
             let tls_builder = NativeTlsConnector::builder();
-            // Perform here your custom tls setup, with tls_builder.identity or whatever else you need
+            // Perform here your custom tls setup, with tls_builder.identity for a client certificate,
+            // tls_builder.add_root_certificate for a custom CA bundle or whatever else you need
             let mut res = stream.into_native_tls(
                 tls_builder.build().expect("TLS configuration failed"),
                 &uri.authority.host,
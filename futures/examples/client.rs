@@ -1,7 +1,7 @@
 use crate::lapin::options::{
-    BasicConsumeOptions, BasicGetOptions, BasicPublishOptions, ExchangeBindOptions,
-    ExchangeDeclareOptions, ExchangeDeleteOptions, ExchangeUnbindOptions, QueueBindOptions,
-    QueueDeclareOptions,
+    BasicAckOptions, BasicConsumeOptions, BasicGetOptions, BasicPublishOptions,
+    ExchangeBindOptions, ExchangeDeclareOptions, ExchangeDeleteOptions, ExchangeUnbindOptions,
+    QueueBindOptions, QueueDeclareOptions,
 };
 use crate::lapin::types::FieldTable;
 use crate::lapin::{BasicProperties, Client, ConnectionProperties, ExchangeKind};
@@ -58,7 +58,7 @@ fn main() {
                         info!("got message: {:?}", message);
                         let message = message.unwrap();
                         info!("decoded message: {:?}", std::str::from_utf8(&message.delivery.data).unwrap());
-                        channel.basic_ack(message.delivery.delivery_tag, false)
+                        channel.basic_ack(message.delivery.delivery_tag, BasicAckOptions::default())
                     }).and_then(move |_| {
                         ch.basic_consume(&queue, "my_consumer", BasicConsumeOptions::default(), FieldTable::default())
                     })
@@ -68,7 +68,7 @@ fn main() {
                     stream.for_each(move |message| {
                         debug!("got message: {:?}", message);
                         info!("decoded message: {:?}", std::str::from_utf8(&message.data).unwrap());
-                        c.basic_ack(message.delivery_tag, false)
+                        c.basic_ack(message.delivery_tag, BasicAckOptions::default())
                     })
                 })
             })
@@ -1,4 +1,6 @@
-use crate::lapin::options::{BasicConsumeOptions, BasicPublishOptions, QueueDeclareOptions};
+use crate::lapin::options::{
+    BasicAckOptions, BasicConsumeOptions, BasicPublishOptions, QueueDeclareOptions,
+};
 use crate::lapin::types::FieldTable;
 use crate::lapin::{BasicProperties, Client, ConnectionProperties};
 use futures::{Future, Stream};
@@ -44,7 +46,7 @@ fn create_consumer(client: &Client, n: u8) -> impl Future<Item = (), Error = ()>
                     n,
                     std::str::from_utf8(&message.data).unwrap()
                 );
-                channel.basic_ack(message.delivery_tag, false)
+                channel.basic_ack(message.delivery_tag, BasicAckOptions::default())
             })
         })
         .map(|_| ())
@@ -1,4 +1,6 @@
-use crate::lapin::options::{BasicConsumeOptions, BasicPublishOptions, QueueDeclareOptions};
+use crate::lapin::options::{
+    BasicAckOptions, BasicConsumeOptions, BasicPublishOptions, QueueDeclareOptions,
+};
 use crate::lapin::types::FieldTable;
 use crate::lapin::{BasicProperties, Client, ConnectionProperties};
 use futures::{Future, Stream};
@@ -77,7 +79,7 @@ fn main() {
                                     "decoded message: {:?}",
                                     std::str::from_utf8(&message.data).unwrap()
                                 );
-                                ch.basic_ack(message.delivery_tag, false)
+                                ch.basic_ack(message.delivery_tag, BasicAckOptions::default())
                             })
                         })
                 })
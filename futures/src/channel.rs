@@ -1,23 +1,33 @@
+#[cfg(feature = "streaming-publish")]
+use crate::StreamingPublishFuture;
 use crate::{
-    message::{BasicGetMessage, BasicReturnMessage},
+    message::{BasicGetMessage, BasicReturnMessage, UnconfirmedMessages},
     options::*,
+    publish::DelayedPublishes,
     types::{Boolean, FieldTable, LongUInt, ShortUInt},
-    BasicProperties, ConfirmationFuture, Consumer, Error, ExchangeKind, Queue,
+    BasicProperties, CloseReason, ConfirmationFuture, Consumer, Error, ExchangeKind, Payload,
+    PublishFuture, Qos, Queue, TemporaryQueue,
 };
 use futures::Future;
 use lapin::{Channel as InnerChannel, Connection};
+#[cfg(feature = "streaming-publish")]
+use tokio_io::AsyncRead;
 
 /// `Channel` provides methods to act on a channel, such as managing queues
 #[derive(Clone)]
 pub struct Channel {
     inner: InnerChannel,
+    delayed_publishes: DelayedPublishes,
 }
 
 impl Channel {
     /// create a channel
     pub fn create(conn: &Connection) -> impl Future<Item = Self, Error = Error> {
         let confirmation: ConfirmationFuture<InnerChannel> = conn.create_channel().into();
-        confirmation.map(|inner| Channel { inner })
+        confirmation.map(|inner| Channel {
+            inner,
+            delayed_publishes: DelayedPublishes::default(),
+        })
     }
 
     pub fn id(&self) -> u16 {
@@ -50,6 +60,13 @@ impl Channel {
             .into()
     }
 
+    /// checks that an exchange exists without creating it
+    ///
+    /// returns a future that fails if it doesn't, see `Error::not_found`
+    pub fn exchange_declare_passive(&self, name: &str) -> ConfirmationFuture<()> {
+        self.inner.exchange_declare_passive(name).into()
+    }
+
     /// deletes an exchange
     ///
     /// returns a future that resolves once the exchange is deleted
@@ -95,7 +112,8 @@ impl Channel {
 
     /// declares a queue
     ///
-    /// returns a future that resolves once the queue is available
+    /// returns a future that resolves once the queue is available, carrying the messages and
+    /// consumers counts reported by the server, even for a passive declare
     ///
     /// the `mandatory` and `ìmmediate` options can be set to true,
     /// but the return message will not be handled
@@ -108,6 +126,36 @@ impl Channel {
         self.inner.queue_declare(name, options, arguments).into()
     }
 
+    /// checks that a queue exists without creating it
+    ///
+    /// returns a future that resolves with the messages and consumers counts of the queue, or
+    /// fails if it doesn't exist, see `Error::not_found`
+    pub fn queue_declare_passive(&self, name: &str) -> ConfirmationFuture<Queue> {
+        self.inner.queue_declare_passive(name).into()
+    }
+
+    /// declares a durable quorum queue, setting `x-queue-type` in `arguments`
+    ///
+    /// returns a future that fails with `Error::InvalidQueueOptions` if `options` asks for an
+    /// exclusive or auto-delete queue, which quorum queues cannot be
+    pub fn queue_declare_quorum(
+        &self,
+        name: &str,
+        options: QueueDeclareOptions,
+        arguments: FieldTable,
+    ) -> ConfirmationFuture<Queue> {
+        self.inner
+            .queue_declare_quorum(name, options, arguments)
+            .into()
+    }
+
+    /// declares an exclusive, auto-delete queue named by the server
+    ///
+    /// returns a future that resolves to a `TemporaryQueue`, deleting the queue once dropped
+    pub fn declare_temporary_queue(&self) -> ConfirmationFuture<TemporaryQueue, Queue> {
+        self.inner.declare_temporary_queue().into()
+    }
+
     /// binds a queue to an exchange
     ///
     /// returns a future that resolves once the queue is bound to the exchange
@@ -124,6 +172,23 @@ impl Channel {
             .into()
     }
 
+    /// binds a queue to a topic exchange
+    ///
+    /// returns a future that fails with `Error::InvalidRoutingKey` if `pattern` is not a valid
+    /// topic pattern
+    pub fn queue_bind_topic(
+        &self,
+        name: &str,
+        exchange: &str,
+        pattern: &str,
+        options: QueueBindOptions,
+        arguments: FieldTable,
+    ) -> ConfirmationFuture<()> {
+        self.inner
+            .queue_bind_topic(name, exchange, pattern, options, arguments)
+            .into()
+    }
+
     /// unbinds a queue from the exchange
     ///
     /// returns a future that resolves once the queue is unbound from the exchange
@@ -145,6 +210,13 @@ impl Channel {
     }
 
     /// specifies quality of service for a channel
+    ///
+    /// `prefetch_count` limits the number of unacked deliveries. With RabbitMQ, this limit
+    /// applies to each new consumer on the channel, unless `options.global` is set, in which case
+    /// it is shared by all the consumers of the channel. `prefetch_size` is always sent as 0
+    /// (no limit) as the server doesn't implement it.
+    ///
+    /// returns a future that resolves once the server replied with `basic.qos-ok`
     pub fn basic_qos(
         &self,
         prefetch_count: ShortUInt,
@@ -153,18 +225,68 @@ impl Channel {
         self.inner.basic_qos(prefetch_count, options).into()
     }
 
+    /// the last quality of service accepted by the server, if any
+    pub fn qos(&self) -> Option<Qos> {
+        self.inner.status().qos()
+    }
+
     /// publishes a message on a queue
-    pub fn basic_publish(
+    ///
+    /// if publisher confirms are enabled, the returned future resolves once the message got
+    /// acked by the server, or fails with `Error::PublishNacked`
+    ///
+    /// the message is queued right away, unless the outgoing frames queue is full, in which case
+    /// the returned future waits for some space to be freed before publishing, without ever
+    /// blocking, whatever the `SendQueueFullPolicy`
+    ///
+    /// the payload is either a `Vec<u8>`, taken over without being copied, or a `Payload`
+    /// which can be shared with other publishes
+    pub fn basic_publish<P: Into<Payload>>(
         &self,
         exchange: &str,
         routing_key: &str,
-        payload: Vec<u8>,
+        payload: P,
         options: BasicPublishOptions,
         properties: BasicProperties,
-    ) -> ConfirmationFuture<()> {
-        self.inner
-            .basic_publish(exchange, routing_key, options, payload, properties)
-            .into()
+    ) -> PublishFuture {
+        PublishFuture::new(
+            self.inner.clone(),
+            self.delayed_publishes.clone(),
+            exchange,
+            routing_key,
+            payload.into(),
+            options,
+            properties,
+        )
+    }
+
+    /// publishes a message whose body is read from `body`
+    ///
+    /// `body_size` is announced to the server before reading the body; if `body` yields a
+    /// different number of bytes, the returned future fails with `Error::BodySizeMismatch` and
+    /// the channel cannot be used anymore
+    ///
+    /// Requires the `streaming-publish` feature, `body` being a tokio-io `AsyncRead`
+    #[cfg(feature = "streaming-publish")]
+    pub fn basic_publish_streaming<R: AsyncRead>(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        body: R,
+        body_size: u64,
+        options: BasicPublishOptions,
+        properties: BasicProperties,
+    ) -> StreamingPublishFuture<R> {
+        StreamingPublishFuture::new(
+            self.inner.start_streaming_publish(
+                exchange,
+                routing_key,
+                options,
+                body_size,
+                properties,
+            ),
+            body,
+        )
     }
 
     /// creates a consumer stream
@@ -172,7 +294,12 @@ impl Channel {
     /// returns a future of a `Consumer` that resolves once the method succeeds
     ///
     /// `Consumer` implements `futures::Stream`, so it can be used with any of
-    /// the usual combinators
+    /// the usual combinators. The stream ends once the consumer gets cancelled, by us or by the
+    /// server, and fails if the channel or the connection does.
+    ///
+    /// The deliveries are buffered until the stream is polled. To keep a slow consumer from
+    /// buffering the whole queue, limit the number of unacked deliveries with `basic_qos`
+    /// beforehand, the server then waiting for acks before sending more.
     pub fn basic_consume(
         &self,
         queue: &Queue,
@@ -195,31 +322,31 @@ impl Channel {
         self.inner.basic_cancel(consumer_tag, options).into()
     }
 
+    /// asks the server to deliver the unacknowledged messages again, with new delivery tags
+    ///
+    /// returns a future that resolves once the server replied, the deliveries received so far
+    /// then no longer having to be acknowledged
     pub fn basic_recover(&self, options: BasicRecoverOptions) -> ConfirmationFuture<()> {
         self.inner.basic_recover(options).into()
     }
 
+    /// same as `basic_recover`, without waiting for the server to reply
     pub fn basic_recover_async(&self, options: BasicRecoverAsyncOptions) -> ConfirmationFuture<()> {
         self.inner.basic_recover_async(options).into()
     }
 
     /// acks a message
-    pub fn basic_ack(&self, delivery_tag: u64, multiple: bool) -> ConfirmationFuture<()> {
-        self.inner
-            .basic_ack(delivery_tag, BasicAckOptions { multiple })
-            .into()
+    pub fn basic_ack(&self, delivery_tag: u64, options: BasicAckOptions) -> ConfirmationFuture<()> {
+        self.inner.basic_ack(delivery_tag, options).into()
     }
 
     /// nacks a message
     pub fn basic_nack(
         &self,
         delivery_tag: u64,
-        multiple: bool,
-        requeue: bool,
+        options: BasicNackOptions,
     ) -> ConfirmationFuture<()> {
-        self.inner
-            .basic_nack(delivery_tag, BasicNackOptions { multiple, requeue })
-            .into()
+        self.inner.basic_nack(delivery_tag, options).into()
     }
 
     /// rejects a message
@@ -232,6 +359,9 @@ impl Channel {
     }
 
     /// gets a message
+    ///
+    /// returns a future that resolves to `None` if the queue is empty, and to the message
+    /// once its content is received otherwise, `Delivery::from` dropping the remaining count
     pub fn basic_get(
         &self,
         queue: &str,
@@ -268,31 +398,70 @@ impl Channel {
         self.inner.queue_delete(queue_name, options).into()
     }
 
-    /// closes the channel
+    /// Return a future that resolves once everything queued on the connection so far has been
+    /// serialized, not only the frames of this channel
+    pub fn flush(&self) -> ConfirmationFuture<()> {
+        self.inner.flush().into()
+    }
+
+    /// closes the channel, resolving once the server replied with channel.close-ok
+    ///
+    /// The operations attempted on the channel afterwards fail with `Error::ChannelClosed`.
     pub fn close(&self, code: u16, message: &str) -> ConfirmationFuture<()> {
         self.inner.close(code, message).into()
     }
 
+    /// whether the server lets us publish on this channel, the publishes being kept until it
+    /// resumes them otherwise
+    pub fn flow_status(&self) -> bool {
+        self.inner.status().flow()
+    }
+
     /// update a channel flow
     pub fn channel_flow(&self, options: ChannelFlowOptions) -> ConfirmationFuture<Boolean> {
         self.inner.channel_flow(options).into()
     }
 
+    /// puts the channel in transactional mode
+    ///
+    /// returns a future that fails with `Error::ChannelModeConflict` if publisher confirms are
+    /// enabled on the channel
     pub fn tx_select(&self) -> ConfirmationFuture<()> {
         self.inner.tx_select().into()
     }
 
+    /// commits the current transaction
+    ///
+    /// returns a future that fails with `Error::NotTransactional` if `tx_select` wasn't called
     pub fn tx_commit(&self) -> ConfirmationFuture<()> {
         self.inner.tx_commit().into()
     }
 
+    /// rolls back the current transaction
+    ///
+    /// returns a future that fails with `Error::NotTransactional` if `tx_select` wasn't called
     pub fn tx_rollback(&self) -> ConfirmationFuture<()> {
         self.inner.tx_rollback().into()
     }
 
     /// When publishers confirm is enabled, wait for pending confirmations and return the nacked
     /// messages
-    pub fn wait_for_confirms(&self) -> ConfirmationFuture<Vec<BasicReturnMessage>> {
+    pub fn wait_for_confirms(&self) -> ConfirmationFuture<UnconfirmedMessages> {
         self.inner.wait_for_confirms().into()
     }
+
+    /// registers a handler which will be called if the server closes this channel
+    pub fn on_error<E: Fn(CloseReason) + Send + 'static>(&self, handler: Box<E>) {
+        self.inner.on_error(handler);
+    }
+
+    /// the number of deliveries received on this channel which weren't acknowledged yet
+    pub fn unacked_count(&self) -> usize {
+        self.inner.unacked_count()
+    }
+
+    /// Take the messages returned by the server since the last call
+    pub fn drain_returned_messages(&self) -> Vec<BasicReturnMessage> {
+        self.inner.drain_returned_messages()
+    }
 }
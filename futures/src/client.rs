@@ -1,17 +1,30 @@
 use futures::{Future, Poll};
 use lapin::{confirmation::Confirmation, Connect as LapinConnect, Connection};
+use std::time::{Duration, Instant};
+#[cfg(feature = "tokio-driver")]
+use tokio_io::{AsyncRead, AsyncWrite};
 
+#[cfg(feature = "tokio-driver")]
+use crate::driver::IoDriver;
 use crate::{
-    tcp::Identity, uri::AMQPUri, Channel, ConfirmationFuture, ConnectionProperties, Error,
+    tcp::Identity, uri::AMQPUri, Channel, ConfirmationFuture, ConnectionMetrics,
+    ConnectionProperties, ConnectionStats, Error, FrameObserver, StateDump,
 };
 
 /// Connect to a server and create channels
+///
+/// The clones share the same connection, and can be used from different tasks or threads.
 #[derive(Clone)]
 pub struct Client {
     conn: Connection,
 }
 
 impl Client {
+    /// Check that an AMQP URI is valid without connecting
+    pub fn validate_uri(uri: &str) -> Result<AMQPUri, Error> {
+        Connection::validate_uri(uri)
+    }
+
     /// Connect to an AMQP Server
     pub fn connect(uri: &str, options: ConnectionProperties) -> ClientFuture {
         Connect::connect(uri, options, None)
@@ -40,13 +53,44 @@ impl Client {
         Connect::connect(uri, options, Some(identity))
     }
 
+    /// Connect to an AMQP Server over an already established stream, driven by the returned
+    /// `IoDriver` instead of lapin's io loop thread
+    ///
+    /// The `IoDriver` must be spawned on the tokio runtime for the `ClientFuture` to resolve.
+    #[cfg(feature = "tokio-driver")]
+    pub fn connect_stream<S: AsyncRead + AsyncWrite>(
+        stream: S,
+        uri: AMQPUri,
+        options: ConnectionProperties,
+    ) -> Result<(ClientFuture, IoDriver<S>), Error> {
+        let (conn, confirmation) = Connection::handshake(uri, options)?;
+        Ok((confirmation.into(), IoDriver::new(conn, stream)))
+    }
+
     /// Return a future that resolves to a `Channel` once the method succeeds
     pub fn create_channel(&self) -> impl Future<Item = Channel, Error = Error> + Send + 'static {
         Channel::create(&self.conn)
     }
 
+    /// Close the connection
+    pub fn close(&self, reply_code: u16, reply_text: &str) -> ConfirmationFuture<()> {
+        self.conn.close(reply_code, reply_text).into()
+    }
+
+    /// Close the connection once everything queued so far has been sent, or after `timeout`
+    pub fn drain_and_close(
+        &self,
+        reply_code: u16,
+        reply_text: &str,
+        timeout: Duration,
+    ) -> ConfirmationFuture<()> {
+        self.conn
+            .drain_and_close(reply_code, reply_text, timeout)
+            .into()
+    }
+
     /// Update the secret used by some authentication module such as oauth2
-    pub fn update_secret(&self, new_secret: &str, reason: &str) -> ConfirmationFuture<()> {
+    pub fn update_secret(&self, new_secret: String, reason: &str) -> ConfirmationFuture<()> {
         self.conn.update_secret(new_secret, reason).into()
     }
 
@@ -60,10 +104,66 @@ impl Client {
         self.conn.unblock().into()
     }
 
+    /// Return a future that resolves once everything queued so far has been serialized
+    pub fn flush(&self) -> ConfirmationFuture<()> {
+        self.conn.drain().into()
+    }
+
+    /// The heartbeat interval negotiated with the server, `None` if heartbeats are disabled
+    pub fn heartbeat_interval(&self) -> Option<Duration> {
+        self.conn.heartbeat_interval()
+    }
+
+    /// Queue a heartbeat, on top of the ones sent at `heartbeat_interval` by the io loop or the
+    /// `IoDriver`
+    pub fn send_heartbeat(&self) -> Result<(), Error> {
+        self.conn.send_heartbeat()
+    }
+
+    /// The number of frames queued and not sent yet, to apply backpressure before publishing
+    pub fn pending_frame_count(&self) -> usize {
+        self.conn.pending_frame_count()
+    }
+
+    /// The ids of the channels opened, or being opened or closed, sorted
+    pub fn open_channels(&self) -> Vec<u16> {
+        self.conn.open_channels()
+    }
+
+    /// The number of channels opened, or being opened or closed
+    pub fn open_channel_count(&self) -> usize {
+        self.conn.open_channel_count()
+    }
+
+    /// Take a snapshot of the connection state, to be attached to bug reports
+    pub fn dump_state(&self) -> StateDump {
+        self.conn.dump_state()
+    }
+
     /// Register an error handler which will be called when connection reaches an Error state
     pub fn on_error<E: Fn() + Send + 'static>(&self, handler: Box<E>) {
         self.conn.on_error(handler);
     }
+
+    /// Register a callback called for every frame received or taken from the send queue
+    pub fn set_frame_observer(&self, observer: FrameObserver) {
+        self.conn.set_frame_observer(observer);
+    }
+
+    /// A snapshot of the number of frames, bytes and some methods sent and received so far
+    pub fn stats(&self) -> ConnectionStats {
+        self.conn.stats()
+    }
+
+    /// When a complete frame was last received, or the handshake started if none was since
+    pub fn last_progress(&self) -> Instant {
+        self.conn.last_progress()
+    }
+
+    /// Register hooks called for each frame sent or received and each socket read or write
+    pub fn set_metrics<M: ConnectionMetrics + 'static>(&self, metrics: M) {
+        self.conn.set_metrics(metrics);
+    }
 }
 
 pub struct ClientFuture(ConfirmationFuture<Connection>);
@@ -99,6 +199,12 @@ impl Connect for AMQPUri {
         options: ConnectionProperties,
         identity: Option<Identity<'_, '_>>,
     ) -> ClientFuture {
+        #[cfg(any(
+            feature = "runtime-async-std",
+            feature = "runtime-smol",
+            feature = "runtime-tokio"
+        ))]
+        let options = crate::runtime::with_default_runtime(options);
         LapinConnect::connect(self, options, identity).into()
     }
 }
@@ -109,6 +215,12 @@ impl Connect for &str {
         options: ConnectionProperties,
         identity: Option<Identity<'_, '_>>,
     ) -> ClientFuture {
+        #[cfg(any(
+            feature = "runtime-async-std",
+            feature = "runtime-smol",
+            feature = "runtime-tokio"
+        ))]
+        let options = crate::runtime::with_default_runtime(options);
         LapinConnect::connect(self, options, identity).into()
     }
 }
@@ -3,7 +3,10 @@ use lapin::confirmation::{Confirmation, NotifyReady};
 
 use crate::Error;
 
-pub struct ConfirmationFuture<T, I = ()>(Confirmation<T, I>);
+pub struct ConfirmationFuture<T, I = ()> {
+    inner: Confirmation<T, I>,
+    terminated: bool,
+}
 
 pub(crate) struct Watcher(task::Task);
 
@@ -24,10 +27,14 @@ impl<T, I> Future for ConfirmationFuture<T, I> {
     type Error = Error;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        if !self.0.has_subscriber() {
-            self.0.subscribe(Box::new(Watcher::default()));
+        // Polling again once we're done, as select does, keeps us pending instead of panicking
+        if self.terminated {
+            return Ok(Async::NotReady);
         }
-        Ok(if let Some(res) = self.0.try_wait() {
+        // The task may have moved since the last poll, always register the current one
+        self.inner.subscribe(Box::new(Watcher::default()));
+        Ok(if let Some(res) = self.inner.try_wait() {
+            self.terminated = true;
             Async::Ready(res?)
         } else {
             Async::NotReady
@@ -37,6 +44,9 @@ impl<T, I> Future for ConfirmationFuture<T, I> {
 
 impl<T, I> From<Confirmation<T, I>> for ConfirmationFuture<T, I> {
     fn from(confirmation: Confirmation<T, I>) -> Self {
-        Self(confirmation)
+        Self {
+            inner: confirmation,
+            terminated: false,
+        }
     }
 }
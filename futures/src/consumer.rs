@@ -2,11 +2,33 @@ use futures::{Async, Poll, Stream};
 use lapin::Consumer as ConsumerInner;
 use log::trace;
 
-use crate::{confirmation::Watcher, message::Delivery, Error};
+use crate::{confirmation::Watcher, message::Delivery, types::ShortString, CancelledBy, Error};
 
 #[derive(Clone, Debug)]
 pub struct Consumer(pub(crate) ConsumerInner);
 
+impl Consumer {
+    /// the consumer tag, as generated by the server if we didn't pick one
+    pub fn tag(&self) -> ShortString {
+        self.0.tag()
+    }
+
+    /// whether this consumer got cancelled, by us or by the server
+    pub fn is_cancelled(&self) -> bool {
+        self.0.is_cancelled()
+    }
+
+    /// who cancelled this consumer, if it got cancelled
+    pub fn cancelled_by(&self) -> Option<CancelledBy> {
+        self.0.cancelled_by()
+    }
+
+    /// the number of deliveries received but not yet yielded by the stream
+    pub fn buffered_deliveries(&self) -> usize {
+        self.0.inner().buffered_deliveries()
+    }
+}
+
 impl Stream for Consumer {
     type Item = Delivery;
     type Error = Error;
@@ -18,9 +40,8 @@ impl Stream for Consumer {
             "consumer poll; acquired inner lock, consumer_tag={}",
             inner.tag()
         );
-        if !inner.has_task() {
-            inner.set_task(Box::new(Watcher::default()));
-        }
+        // The task may have moved since the last poll, always register the current one
+        inner.set_task(Box::new(Watcher::default()));
         if let Some(delivery) = inner.next_delivery() {
             match delivery {
                 Ok(Some(delivery)) => {
@@ -0,0 +1,200 @@
+use futures::{task, Async, Future, Poll, Stream};
+use lapin::{Connection, FrameBatch, Progress};
+use log::trace;
+use std::{
+    io,
+    time::{Duration, Instant},
+};
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_timer::Interval;
+
+use crate::{confirmation::Watcher, Error};
+
+/// How many frames are taken from the connection at once
+const FRAMES_PER_BATCH: usize = 64;
+const RECEIVE_BUFFER_SIZE: usize = 128 * 1024;
+
+/// Drive a connection over a stream from a tokio runtime, instead of using the io loop thread
+///
+/// It sends the queued frames, handles the received ones and sends heartbeats, and resolves
+/// once the connection is closed. On io errors, or if nothing was received for two heartbeat
+/// intervals, the connection goes in error, failing all the pending operations. See `Client::connect_stream`.
+pub struct IoDriver<S> {
+    connection: Connection,
+    stream: S,
+    receive_buffer: Vec<u8>,
+    received: usize,
+    parse_pending: bool,
+    batch: FrameBatch,
+    flushed: bool,
+    heartbeat: Option<(Duration, Interval)>,
+}
+
+impl<S: AsyncRead + AsyncWrite> IoDriver<S> {
+    pub(crate) fn new(connection: Connection, stream: S) -> Self {
+        Self {
+            connection,
+            stream,
+            receive_buffer: vec![0; RECEIVE_BUFFER_SIZE],
+            received: 0,
+            parse_pending: false,
+            batch: FrameBatch::default(),
+            flushed: true,
+            heartbeat: None,
+        }
+    }
+
+    fn poll_heartbeat(&mut self) -> Result<(), Error> {
+        if self.heartbeat.is_none() && self.connection.status().connected() {
+            if let Some(interval) = self.connection.heartbeat_interval() {
+                trace!("io driver: start heartbeat");
+                self.heartbeat = Some((interval, Interval::new_interval(interval)));
+            }
+        }
+        if let Some((interval, heartbeat)) = self.heartbeat.as_mut() {
+            while let Async::Ready(Some(_)) = heartbeat
+                .poll()
+                .map_err(|e| Error::IOError(io::Error::new(io::ErrorKind::Other, e)))?
+            {
+                // The server sends heartbeats too, nothing for that long means it's gone
+                if Instant::now() - self.connection.last_progress() > *interval * 2 {
+                    return Err(self.connection.io_error(io::ErrorKind::TimedOut.into()));
+                }
+                trace!("io driver: send heartbeat");
+                self.connection.send_heartbeat()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write as many frames as the stream accepts, returning whether anything was written
+    fn poll_write(&mut self) -> Result<bool, Error> {
+        let mut progress = false;
+        while !self.connection.status().blocked() {
+            if self.batch.is_empty() {
+                self.batch = self.connection.next_frames(FRAMES_PER_BATCH)?;
+                if self.batch.is_empty() {
+                    break;
+                }
+            }
+            let res = self.stream.poll_write(&self.batch.io_slices()[0]);
+            match res {
+                Ok(Async::Ready(0)) => {
+                    return Err(self.connection.io_error(io::ErrorKind::WriteZero.into()))
+                }
+                Ok(Async::Ready(written)) => {
+                    trace!("io driver: wrote {} bytes", written);
+                    self.connection.frames_written(&mut self.batch, written);
+                    self.flushed = false;
+                    progress = true;
+                }
+                Ok(Async::NotReady) => break,
+                Err(e) => return Err(self.connection.io_error(e)),
+            }
+        }
+        if !self.flushed {
+            match self.stream.poll_flush() {
+                Ok(Async::Ready(())) => self.flushed = true,
+                Ok(Async::NotReady) => {}
+                Err(e) => return Err(self.connection.io_error(e)),
+            }
+        }
+        Ok(progress)
+    }
+
+    /// Read and handle the available frames, up to max_frames_per_poll per read, returning
+    /// whether anything was read or handled
+    fn poll_read(&mut self) -> Result<bool, Error> {
+        if self.parse_pending {
+            self.parse_received()?;
+            if self.parse_pending {
+                return Ok(true);
+            }
+        }
+        let mut progress = false;
+        loop {
+            if self.received == self.receive_buffer.len() {
+                // A frame larger than the buffer, up to frame_max
+                let size = self.receive_buffer.len() * 2;
+                self.receive_buffer.resize(size, 0);
+            }
+            let res = self
+                .stream
+                .poll_read(&mut self.receive_buffer[self.received..]);
+            match res {
+                Ok(Async::Ready(0)) => {
+                    if self.connection.status().closed() {
+                        return Ok(progress);
+                    }
+                    return Err(self
+                        .connection
+                        .io_error(io::ErrorKind::UnexpectedEof.into()));
+                }
+                Ok(Async::Ready(size)) => {
+                    trace!("io driver: read {} bytes", size);
+                    self.connection.bytes_read(size);
+                    self.received += size;
+                    self.parse_received()?;
+                    progress = true;
+                    if self.parse_pending {
+                        return Ok(progress);
+                    }
+                }
+                Ok(Async::NotReady) => return Ok(progress),
+                Err(e) => return Err(self.connection.io_error(e)),
+            }
+        }
+    }
+
+    /// Handle the frames from the receive buffer, keeping what wasn't consumed
+    fn parse_received(&mut self) -> Result<(), Error> {
+        let (consumed, progress) = match self
+            .connection
+            .handle_data(&self.receive_buffer[..self.received])
+        {
+            Ok(handled) => handled,
+            Err(Error::WouldBlock) => {
+                // No complete frame yet, wait for more data
+                self.parse_pending = false;
+                return Ok(());
+            }
+            Err(error) => return Err(error),
+        };
+        self.receive_buffer.copy_within(consumed..self.received, 0);
+        self.received -= consumed;
+        self.parse_pending = progress == Progress::Pending;
+        Ok(())
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> Future for IoDriver<S> {
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        // Subscribe first not to miss frames queued while polling
+        self.connection
+            .subscribe_frames(Box::new(Watcher::default()));
+        loop {
+            self.poll_heartbeat()?;
+            let wrote = self.poll_write()?;
+            let read = self.poll_read()?;
+            let status = self.connection.status();
+            if status.errored() {
+                return Err(Error::InvalidConnectionState(status.state()));
+            }
+            if status.closed() && self.batch.is_empty() && self.flushed {
+                trace!("io driver: connection closed");
+                return Ok(Async::Ready(()));
+            }
+            if self.parse_pending {
+                // Let the other tasks run before handling the frames left by the work budget
+                task::current().notify();
+                return Ok(Async::NotReady);
+            }
+            if !wrote && !read {
+                return Ok(Async::NotReady);
+            }
+        }
+    }
+}
@@ -6,6 +6,33 @@
 //! It leverages the futures-0.1 library, so you can use it
 //! with tokio, futures-cpupool or any other executor.
 //!
+//! ## Runtimes
+//!
+//! With the default features, this crate doesn't depend on any runtime: the TCP connection, the
+//! heartbeat and the network I/O are handled by lapin's own io loop thread, and consumer
+//! delegates run on the `Executor` from `ConnectionProperties`. The returned futures and streams
+//! only get notified by those threads, so they can be driven by tokio, or by async-std and smol
+//! through the futures-0.3 `compat` layer, without any additional setup.
+//!
+//! The `runtime-tokio`, `runtime-async-std` and `runtime-smol` features instead run the
+//! connection on that runtime: it opens the TCP connection and runs the `IoDriver` handling the
+//! network I/O and the heartbeats. Pick one with `ConnectionProperties::with_runtime`; if a
+//! single runtime feature is enabled, `Client::connect` uses its runtime by default. TLS isn't
+//! handled then, connecting to amqps:// URIs failing.
+//!
+//! Besides `runtime-tokio`, two features pull in tokio-io: `streaming-publish`, for
+//! `Channel::basic_publish_streaming` which reads the body from a tokio-io `AsyncRead`, and
+//! `tokio-driver`, with which `Client::connect_stream` runs the connection over a stream you
+//! provide, through an `IoDriver` future spawned on the tokio runtime.
+//!
+//! ## Declaring without waiting
+//!
+//! The declare, bind, delete and purge methods accept a `nowait` option, in which case the
+//! server doesn't reply and the returned future resolves once the method is queued, saving a
+//! round-trip per method. Errors are then asynchronous: the server closes the channel later on,
+//! failing the next futures of this channel, and calling the handler registered with
+//! `Channel::on_error`.
+//!
 //! ## Publishing a message
 //!
 //! ```rust,no_run
@@ -49,7 +76,7 @@
 //! use futures::{Future, Stream};
 //! use lapin_futures as lapin;
 //! use crate::lapin::{Client, ConnectionProperties};
-//! use crate::lapin::options::{BasicConsumeOptions, QueueDeclareOptions};
+//! use crate::lapin::options::{BasicAckOptions, BasicConsumeOptions, QueueDeclareOptions};
 //! use crate::lapin::types::FieldTable;
 //! use log::{debug, info};
 //!
@@ -81,7 +108,7 @@
 //!         stream.for_each(move |message| {
 //!           debug!("got message: {:?}", message);
 //!           info!("decoded message: {:?}", std::str::from_utf8(&message.data).unwrap());
-//!           ch.basic_ack(message.delivery_tag, false)
+//!           ch.basic_ack(message.delivery_tag, BasicAckOptions::default())
 //!         })
 //!       })
 //!     })
@@ -90,16 +117,46 @@
 //! ```
 
 pub use lapin::{
-    auth, message, options, protocol, tcp, types, uri, BasicProperties, Configuration,
-    ConnectionProperties, ConsumerDelegate, Error, ExchangeKind, Queue, Result,
+    auth, message, options, protocol, tcp, types, uri, BasicProperties, CancelledBy, CloseReason,
+    Configuration, ConnectionMetrics, ConnectionProperties, ConnectionStats, ConsumerDelegate,
+    Error, ExchangeKind, FrameDirection, FrameObserver, FrameScheduling, Payload, Qos, Queue,
+    Result, SendQueueFullPolicy, StateDump, TemporaryQueue,
 };
 
 pub use channel::Channel;
 pub use client::{Client, ClientFuture, Connect};
 pub use confirmation::ConfirmationFuture;
 pub use consumer::Consumer;
+#[cfg(feature = "tokio-driver")]
+pub use driver::IoDriver;
+pub use publish::PublishFuture;
+#[cfg(feature = "runtime-async-std")]
+pub use runtime::AsyncStdRuntime;
+#[cfg(feature = "runtime-smol")]
+pub use runtime::SmolRuntime;
+#[cfg(feature = "runtime-tokio")]
+pub use runtime::TokioRuntime;
+#[cfg(any(
+    feature = "runtime-async-std",
+    feature = "runtime-smol",
+    feature = "runtime-tokio"
+))]
+pub use runtime::{Runtime, RuntimeStream, Sleep, TcpConnect};
+#[cfg(feature = "streaming-publish")]
+pub use streaming_publish::StreamingPublishFuture;
 
 mod channel;
 mod client;
 mod confirmation;
 mod consumer;
+#[cfg(feature = "tokio-driver")]
+mod driver;
+mod publish;
+#[cfg(any(
+    feature = "runtime-async-std",
+    feature = "runtime-smol",
+    feature = "runtime-tokio"
+))]
+mod runtime;
+#[cfg(feature = "streaming-publish")]
+mod streaming_publish;
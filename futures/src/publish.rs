@@ -0,0 +1,181 @@
+use futures::{task, Async, Future, Poll};
+use lapin::Channel as InnerChannel;
+use parking_lot::Mutex;
+use std::{
+    collections::{HashMap, HashSet},
+    mem,
+    sync::Arc,
+};
+
+use crate::{
+    confirmation::Watcher, options::BasicPublishOptions, BasicProperties, ConfirmationFuture,
+    Error, Payload,
+};
+
+/// Future returned by `Channel::basic_publish`
+///
+/// The message is queued right away, unless the outgoing frames queue is full. It is then
+/// delayed and the future stays `NotReady` until some space gets freed, the next messages
+/// published on this channel being delayed too so that they are sent in order. Dropping the
+/// future of a delayed publish discards its message.
+pub struct PublishFuture {
+    channel: InnerChannel,
+    delayed_publishes: DelayedPublishes,
+    state: State,
+}
+
+enum State {
+    Delayed(u64, Box<PendingPublish>),
+    Queued(ConfirmationFuture<()>),
+}
+
+struct PendingPublish {
+    exchange: String,
+    routing_key: String,
+    payload: Payload,
+    options: BasicPublishOptions,
+    properties: BasicProperties,
+}
+
+impl PendingPublish {
+    fn publish(&mut self, channel: &InnerChannel) -> ConfirmationFuture<()> {
+        channel
+            .try_basic_publish(
+                &self.exchange,
+                &self.routing_key,
+                self.options.clone(),
+                mem::replace(&mut self.payload, Payload::default()),
+                mem::replace(&mut self.properties, BasicProperties::default()),
+            )
+            .into()
+    }
+}
+
+/// The publishes of a channel delayed because the outgoing frames queue was full, in order
+#[derive(Clone, Default)]
+pub(crate) struct DelayedPublishes {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    next_ticket: u64,
+    turn: u64,
+    cancelled: HashSet<u64>,
+    waiters: HashMap<u64, task::Task>,
+}
+
+impl DelayedPublishes {
+    fn publish(&self, channel: &InnerChannel, mut message: PendingPublish) -> State {
+        let mut inner = self.inner.lock();
+        if inner.turn == inner.next_ticket && !channel.send_queue_full() {
+            State::Queued(message.publish(channel))
+        } else {
+            let ticket = inner.next_ticket;
+            inner.next_ticket += 1;
+            State::Delayed(ticket, Box::new(message))
+        }
+    }
+
+    fn poll_delayed(
+        &self,
+        channel: &InnerChannel,
+        ticket: u64,
+        message: &mut PendingPublish,
+    ) -> Option<ConfirmationFuture<()>> {
+        let mut inner = self.inner.lock();
+        if inner.turn != ticket {
+            inner.waiters.insert(ticket, task::current());
+            return None;
+        }
+        if channel.send_queue_full() {
+            channel.subscribe_send_queue(Box::new(Watcher::default()));
+            // Check again in case the queue got drained before we subscribed
+            if channel.send_queue_full() {
+                return None;
+            }
+        }
+        let confirmation = message.publish(channel);
+        inner.next_turn();
+        Some(confirmation)
+    }
+
+    fn cancel(&self, ticket: u64) {
+        let mut inner = self.inner.lock();
+        inner.waiters.remove(&ticket);
+        if inner.turn == ticket {
+            inner.next_turn();
+        } else {
+            inner.cancelled.insert(ticket);
+        }
+    }
+}
+
+impl Inner {
+    fn next_turn(&mut self) {
+        self.turn += 1;
+        while self.cancelled.remove(&self.turn) {
+            self.turn += 1;
+        }
+        if let Some(task) = self.waiters.remove(&self.turn) {
+            task.notify();
+        }
+    }
+}
+
+impl PublishFuture {
+    pub(crate) fn new(
+        channel: InnerChannel,
+        delayed_publishes: DelayedPublishes,
+        exchange: &str,
+        routing_key: &str,
+        payload: Payload,
+        options: BasicPublishOptions,
+        properties: BasicProperties,
+    ) -> Self {
+        let state = delayed_publishes.publish(
+            &channel,
+            PendingPublish {
+                exchange: exchange.into(),
+                routing_key: routing_key.into(),
+                payload,
+                options,
+                properties,
+            },
+        );
+        Self {
+            channel,
+            delayed_publishes,
+            state,
+        }
+    }
+}
+
+impl Future for PublishFuture {
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let State::Delayed(ticket, message) = &mut self.state {
+            match self
+                .delayed_publishes
+                .poll_delayed(&self.channel, *ticket, message)
+            {
+                Some(confirmation) => self.state = State::Queued(confirmation),
+                None => return Ok(Async::NotReady),
+            }
+        }
+        match &mut self.state {
+            State::Queued(confirmation) => confirmation.poll(),
+            State::Delayed(..) => Ok(Async::NotReady),
+        }
+    }
+}
+
+impl Drop for PublishFuture {
+    fn drop(&mut self) {
+        if let State::Delayed(ticket, _) = self.state {
+            self.delayed_publishes.cancel(ticket);
+        }
+    }
+}
@@ -0,0 +1,170 @@
+#[cfg(feature = "runtime-async-std")]
+pub use lapin::AsyncStdRuntime;
+pub use lapin::{Runtime, RuntimeStream, Sleep, TcpConnect};
+
+#[cfg(feature = "runtime-smol")]
+use async_executor::Executor;
+#[cfg(feature = "runtime-smol")]
+use async_io::{Async, Timer};
+#[cfg(feature = "runtime-tokio")]
+use futures_util::{
+    compat::{Compat, Compat01As03, Future01CompatExt},
+    FutureExt,
+};
+#[cfg(feature = "runtime-smol")]
+use log::error;
+use std::sync::Arc;
+#[cfg(feature = "runtime-tokio")]
+use std::time::Instant;
+#[cfg(any(feature = "runtime-smol", feature = "runtime-tokio"))]
+use std::{
+    future::Future,
+    io,
+    net::{SocketAddr, ToSocketAddrs},
+    pin::Pin,
+    time::Duration,
+};
+#[cfg(feature = "runtime-smol")]
+use std::{net::TcpStream, thread};
+#[cfg(feature = "runtime-tokio")]
+use tokio_timer::Delay;
+
+use crate::ConnectionProperties;
+
+/// Run connections on the tokio runtime
+///
+/// The connections must be created from a task of the runtime, `Runtime::spawn` panicking
+/// otherwise, as `tokio::spawn` does.
+#[cfg(feature = "runtime-tokio")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TokioRuntime;
+
+#[cfg(feature = "runtime-tokio")]
+impl Runtime for TokioRuntime {
+    fn connect(&self, host: &str, port: u16) -> TcpConnect {
+        // tokio 0.1 doesn't come with an asynchronous resolver
+        let addrs = (host, port)
+            .to_socket_addrs()
+            .map(Iterator::collect::<Vec<_>>);
+        Box::pin(async move {
+            connect_first(addrs?, |addr| async move {
+                let stream = tokio_tcp::TcpStream::connect(&addr).compat().await?;
+                Ok(Box::new(Compat01As03::new(stream)) as Box<dyn RuntimeStream>)
+            })
+            .await
+        })
+    }
+
+    fn sleep(&self, duration: Duration) -> Sleep {
+        Box::pin(Delay::new(Instant::now() + duration).compat().map(|_| ()))
+    }
+
+    fn spawn(&self, driver: Pin<Box<dyn Future<Output = ()> + Send>>) {
+        tokio_executor::spawn(Compat::new(driver.map(Ok)));
+    }
+}
+
+/// Run connections with async-io and async-executor, the building blocks of smol
+///
+/// With `SmolRuntime::default()`, each connection runs on a thread of its own.
+///
+/// It is built on async-io 1.1, async-executor 1.3 and blocking 1.0, the releases from the smol
+/// 1.x stack which still support Rust 1.40: an `Executor` from a newer async-executor is another
+/// type.
+#[cfg(feature = "runtime-smol")]
+#[derive(Clone, Debug, Default)]
+pub struct SmolRuntime {
+    executor: Option<Arc<Executor<'static>>>,
+}
+
+#[cfg(feature = "runtime-smol")]
+impl SmolRuntime {
+    /// Run the connections on `executor`, which the application has to run
+    pub fn new(executor: Arc<Executor<'static>>) -> Self {
+        Self {
+            executor: Some(executor),
+        }
+    }
+}
+
+#[cfg(feature = "runtime-smol")]
+impl Runtime for SmolRuntime {
+    fn connect(&self, host: &str, port: u16) -> TcpConnect {
+        let host = host.to_string();
+        Box::pin(async move {
+            let addrs = blocking::unblock(move || {
+                (host.as_str(), port)
+                    .to_socket_addrs()
+                    .map(Iterator::collect::<Vec<_>>)
+            })
+            .await?;
+            connect_first(addrs, |addr| async move {
+                let stream = Async::<TcpStream>::connect(addr).await?;
+                Ok(Box::new(stream) as Box<dyn RuntimeStream>)
+            })
+            .await
+        })
+    }
+
+    fn sleep(&self, duration: Duration) -> Sleep {
+        Box::pin(async move {
+            Timer::after(duration).await;
+        })
+    }
+
+    fn spawn(&self, driver: Pin<Box<dyn Future<Output = ()> + Send>>) {
+        match &self.executor {
+            Some(executor) => executor.spawn(driver).detach(),
+            None => {
+                if let Err(err) = thread::Builder::new()
+                    .name("lapin-smol".into())
+                    .spawn(move || async_io::block_on(driver))
+                {
+                    error!("failed to spawn the io driver thread: {:?}", err);
+                }
+            }
+        }
+    }
+}
+
+/// Try the addresses in order until one accepts the connection, as
+/// `std::net::TcpStream::connect` does
+#[cfg(any(feature = "runtime-smol", feature = "runtime-tokio"))]
+async fn connect_first<F, C>(
+    addrs: Vec<SocketAddr>,
+    connect: C,
+) -> io::Result<Box<dyn RuntimeStream>>
+where
+    F: Future<Output = io::Result<Box<dyn RuntimeStream>>>,
+    C: Fn(SocketAddr) -> F,
+{
+    let mut last_error = None;
+    for addr in addrs {
+        match connect(addr).await {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_error = Some(err),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "could not resolve to any address",
+        )
+    }))
+}
+
+/// Use the runtime of the only runtime feature enabled when none was set in the properties
+pub(crate) fn with_default_runtime(mut options: ConnectionProperties) -> ConnectionProperties {
+    let runtimes: &[fn() -> Arc<dyn Runtime>] = &[
+        #[cfg(feature = "runtime-async-std")]
+        || Arc::new(AsyncStdRuntime),
+        #[cfg(feature = "runtime-smol")]
+        || Arc::new(SmolRuntime::default()),
+        #[cfg(feature = "runtime-tokio")]
+        || Arc::new(TokioRuntime),
+    ];
+    if let (true, [runtime]) = (options.runtime.is_none(), runtimes) {
+        options.runtime = Some(runtime());
+    }
+    options
+}
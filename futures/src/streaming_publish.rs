@@ -0,0 +1,105 @@
+use futures::{Async, Future, Poll};
+use lapin::StreamingPublish;
+use std::io;
+use tokio_io::AsyncRead;
+
+use crate::{confirmation::Watcher, ConfirmationFuture, Error};
+
+/// Future returned by `Channel::basic_publish_streaming`
+///
+/// The body is read from the `AsyncRead` one chunk at a time, waiting for some space in the
+/// outgoing frames queue whenever it is full.
+pub struct StreamingPublishFuture<R> {
+    publish: Option<StreamingPublish>,
+    body: R,
+    chunk: Vec<u8>,
+    pending: usize,
+    error: Option<Error>,
+    confirmation: Option<ConfirmationFuture<()>>,
+    terminated: bool,
+}
+
+impl<R: AsyncRead> StreamingPublishFuture<R> {
+    pub(crate) fn new(publish: lapin::Result<StreamingPublish>, body: R) -> Self {
+        match publish {
+            Ok(publish) => Self {
+                chunk: vec![0; publish.chunk_size()],
+                publish: Some(publish),
+                body,
+                pending: 0,
+                error: None,
+                confirmation: None,
+                terminated: false,
+            },
+            Err(error) => Self {
+                publish: None,
+                body,
+                chunk: Vec::new(),
+                pending: 0,
+                error: Some(error),
+                confirmation: None,
+                terminated: false,
+            },
+        }
+    }
+
+    fn poll_publish(&mut self) -> Poll<(), Error> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        if let Some(confirmation) = self.confirmation.as_mut() {
+            return confirmation.poll();
+        }
+        let publish = match self.publish.as_mut() {
+            Some(publish) => publish,
+            None => return Ok(Async::NotReady),
+        };
+        loop {
+            if self.pending != 0 {
+                if !publish.can_send() {
+                    publish.subscribe(Box::new(Watcher::default()));
+                    // Check again in case the queue got drained before we subscribed
+                    if !publish.can_send() {
+                        return Ok(Async::NotReady);
+                    }
+                }
+                publish.send_chunk(&self.chunk[..self.pending])?;
+                self.pending = 0;
+            }
+            let remaining = publish.remaining();
+            // Read one extra byte once everything has been sent to make sure the reader is
+            // exhausted
+            let size = std::cmp::min(std::cmp::max(remaining, 1), self.chunk.len() as u64);
+            match self.body.poll_read(&mut self.chunk[..size as usize]) {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::Ready(0)) => break,
+                // If the body is longer than announced, send_chunk will fail
+                Ok(Async::Ready(size)) => self.pending = size,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(publish.abort(Error::IOError(e))),
+            }
+        }
+        if let Some(publish) = self.publish.take() {
+            self.confirmation = Some(publish.finish().into());
+        }
+        self.poll_publish()
+    }
+}
+
+impl<R: AsyncRead> Future for StreamingPublishFuture<R> {
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        // Polling again once we're done, as select does, keeps us pending instead of panicking
+        if self.terminated {
+            return Ok(Async::NotReady);
+        }
+        let res = self.poll_publish();
+        match res {
+            Ok(Async::NotReady) => {}
+            _ => self.terminated = true,
+        }
+        res
+    }
+}
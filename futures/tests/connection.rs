@@ -1,6 +1,6 @@
 use crate::lapin::options::{
-    BasicConsumeOptions, BasicPublishOptions, BasicQosOptions, QueueDeclareOptions,
-    QueueDeleteOptions, QueuePurgeOptions,
+    BasicAckOptions, BasicConsumeOptions, BasicPublishOptions, BasicQosOptions,
+    QueueDeclareOptions, QueueDeleteOptions, QueuePurgeOptions,
 };
 use crate::lapin::types::FieldTable;
 use crate::lapin::{BasicProperties, Client, ConnectionProperties};
@@ -83,7 +83,7 @@ fn connection() {
                                     let msg = message.unwrap();
                                     info!("got message: {:?}", msg);
                                     assert_eq!(msg.data, b"hello from tokio");
-                                    ch1.basic_ack(msg.delivery_tag, false)
+                                    ch1.basic_ack(msg.delivery_tag, BasicAckOptions::default())
                                 })
                                 .and_then(move |_| {
                                     ch2.queue_delete("hello", QueueDeleteOptions::default())
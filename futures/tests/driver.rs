@@ -0,0 +1,131 @@
+#![cfg(feature = "tokio-driver")]
+
+use crate::lapin::options::{
+    BasicAckOptions, BasicConsumeOptions, BasicPublishOptions, ExchangeDeclareOptions,
+    QueueDeclareOptions, QueueDeleteOptions,
+};
+use crate::lapin::types::FieldTable;
+use crate::lapin::{BasicProperties, Client, ConnectionProperties, ExchangeKind};
+use futures::{sync::oneshot, Future, Stream};
+use lapin_futures as lapin;
+use log::info;
+use tokio::net::TcpStream;
+
+fn uri() -> lapin::uri::AMQPUri {
+    let addr = std::env::var("AMQP_ADDR").unwrap_or_else(|_| "amqp://127.0.0.1:5672/%2f".into());
+    Client::validate_uri(&addr).expect("invalid AMQP_ADDR")
+}
+
+fn connect() -> impl Future<Item = Client, Error = lapin::Error> {
+    connect_with_driver().map(|(client, _)| client)
+}
+
+/// Resolves once the io driver stopped, with its result
+type DriverStopped = oneshot::Receiver<Result<(), lapin::Error>>;
+
+fn connect_with_driver() -> impl Future<Item = (Client, DriverStopped), Error = lapin::Error> {
+    let uri = uri();
+    let addr = format!("{}:{}", uri.authority.host, uri.authority.port)
+        .parse()
+        .expect("the tests need an IP address");
+    TcpStream::connect(&addr)
+        .map_err(lapin::Error::IOError)
+        .and_then(move |stream| {
+            let (client, driver) =
+                Client::connect_stream(stream, uri, ConnectionProperties::default())?;
+            let (stopped, driver_stopped) = oneshot::channel();
+            tokio::spawn(driver.then(|res| {
+                info!("io driver stopped: {:?}", res);
+                let _ = stopped.send(res);
+                Ok(())
+            }));
+            Ok(client.map(|client| (client, driver_stopped)))
+        })
+        .and_then(|client| client)
+}
+
+#[test]
+fn driver_publish_consume() {
+    let _ = env_logger::try_init();
+
+    tokio::run(
+        connect()
+            .and_then(|client| client.create_channel().map(|channel| (client, channel)))
+            .and_then(|(client, channel)| {
+                channel
+                    .queue_declare(
+                        "driver",
+                        QueueDeclareOptions::default(),
+                        FieldTable::default(),
+                    )
+                    .and_then(move |queue| {
+                        let consumer_channel = channel.clone();
+                        channel
+                            .basic_publish(
+                                "",
+                                "driver",
+                                b"hello from the io driver".to_vec(),
+                                BasicPublishOptions::default(),
+                                BasicProperties::default(),
+                            )
+                            .and_then(move |_| {
+                                consumer_channel.basic_consume(
+                                    &queue,
+                                    "driver_consumer",
+                                    BasicConsumeOptions::default(),
+                                    FieldTable::default(),
+                                )
+                            })
+                            .and_then(|stream| stream.into_future().map_err(|(err, _)| err))
+                            .and_then(move |(message, _)| {
+                                let msg = message.unwrap();
+                                assert_eq!(msg.data, b"hello from the io driver");
+                                channel
+                                    .basic_ack(msg.delivery_tag, BasicAckOptions::default())
+                                    .and_then(move |_| {
+                                        channel
+                                            .queue_delete("driver", QueueDeleteOptions::default())
+                                    })
+                            })
+                    })
+                    // Closing the connection stops the io driver, and thus the runtime
+                    .and_then(move |_| client.close(200, "OK"))
+            })
+            .map_err(|err| panic!("driver test failed: {:?}", err)),
+    );
+}
+
+#[test]
+fn driver_server_close() {
+    let _ = env_logger::try_init();
+
+    tokio::run(
+        connect_with_driver()
+            .and_then(|(client, driver_stopped)| {
+                client
+                    .create_channel()
+                    .map(|channel| (channel, driver_stopped))
+            })
+            .map_err(|err| panic!("driver test failed: {:?}", err))
+            .and_then(|(channel, driver_stopped)| {
+                // An unknown exchange type is a connection error, the server closes the connection
+                channel
+                    .exchange_declare(
+                        "driver-invalid",
+                        ExchangeKind::Custom("invalid".into()),
+                        ExchangeDeclareOptions::default(),
+                        FieldTable::default(),
+                    )
+                    .then(move |res| {
+                        match res {
+                            Err(lapin::Error::ConnectionClosedByServer(_)) => {}
+                            res => panic!("unexpected result: {:?}", res),
+                        }
+                        // The io driver stops along with the connection
+                        driver_stopped
+                            .map(|_| ())
+                            .map_err(|_| panic!("io driver dropped"))
+                    })
+            }),
+    );
+}
@@ -0,0 +1,202 @@
+use amq_protocol::frame::AMQPFrame;
+use futures::{
+    executor::{self, Notify, NotifyHandle, Spawn},
+    Async,
+};
+use lapin::Connection;
+use lapin_futures::options::BasicPublishOptions;
+use lapin_futures::protocol::{basic, channel, connection, AMQPClass};
+use lapin_futures::types::FieldTable;
+use lapin_futures::{
+    BasicProperties, Channel, Client, ConnectionProperties, PublishFuture, SendQueueFullPolicy,
+};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+#[derive(Default)]
+struct Notified(AtomicBool);
+
+impl Notified {
+    fn take(&self) -> bool {
+        self.0.swap(false, Ordering::SeqCst)
+    }
+}
+
+impl Notify for Notified {
+    fn notify(&self, _id: usize) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+struct Publish {
+    future: Spawn<PublishFuture>,
+    notified: Arc<Notified>,
+}
+
+impl Publish {
+    fn new(channel: &Channel, payload: &[u8]) -> Self {
+        Self {
+            future: executor::spawn(channel.basic_publish(
+                "",
+                "queue",
+                payload.to_vec(),
+                BasicPublishOptions::default(),
+                BasicProperties::default(),
+            )),
+            notified: Arc::default(),
+        }
+    }
+
+    fn is_ready(&mut self) -> bool {
+        let notify = NotifyHandle::from(self.notified.clone());
+        match self.future.poll_future_notify(&notify, 0) {
+            Ok(Async::Ready(())) => true,
+            Ok(Async::NotReady) => false,
+            Err(error) => panic!("publish failed: {:?}", error),
+        }
+    }
+}
+
+/// Open a connection and a channel against a fake broker
+fn open_channel(properties: ConnectionProperties) -> (Connection, Channel) {
+    let uri = Client::validate_uri("amqp://127.0.0.1:5672/%2f").unwrap();
+    let (conn, connected) = Connection::handshake(uri, properties).unwrap();
+    conn.peek_outgoing();
+    for method in vec![
+        connection::AMQPMethod::Start(connection::Start {
+            version_major: 0,
+            version_minor: 9,
+            server_properties: FieldTable::default(),
+            mechanisms: "PLAIN".into(),
+            locales: "en_US".into(),
+        }),
+        connection::AMQPMethod::Tune(connection::Tune {
+            channel_max: 2047,
+            frame_max: 8192,
+            heartbeat: 0,
+        }),
+        connection::AMQPMethod::OpenOk(connection::OpenOk {}),
+    ] {
+        conn.inject_frame(AMQPFrame::Method(0, AMQPClass::Connection(method)))
+            .unwrap();
+    }
+    connected.wait().unwrap();
+    conn.peek_outgoing();
+    let mut create = executor::spawn(Channel::create(&conn));
+    conn.inject_frame(AMQPFrame::Method(
+        1,
+        AMQPClass::Channel(channel::AMQPMethod::OpenOk(channel::OpenOk {})),
+    ))
+    .unwrap();
+    conn.peek_outgoing();
+    let channel = create.wait_future().unwrap();
+    (conn, channel)
+}
+
+/// The payloads of the published messages, in the order their frames were sent
+fn sent_payloads(conn: &Connection) -> Vec<Vec<u8>> {
+    conn.peek_outgoing()
+        .into_iter()
+        .filter_map(|frame| match frame {
+            AMQPFrame::Body(_, payload) => Some(payload),
+            AMQPFrame::Method(_, AMQPClass::Basic(basic::AMQPMethod::Publish(_))) => None,
+            AMQPFrame::Header(..) => None,
+            frame => panic!("unexpected frame: {:?}", frame),
+        })
+        .collect()
+}
+
+#[test]
+fn publish_waits_for_room_in_order() {
+    let _ = env_logger::try_init();
+
+    // Async publishes must never block, even with this policy
+    let (conn, channel) = open_channel(ConnectionProperties {
+        send_queue_capacity: 1,
+        send_queue_full_policy: SendQueueFullPolicy::Block,
+        ..ConnectionProperties::default()
+    });
+
+    // Queued right away, without being polled
+    let mut first = Publish::new(&channel, b"first");
+    let mut second = Publish::new(&channel, b"second");
+    let cancelled = Publish::new(&channel, b"cancelled");
+    let mut third = Publish::new(&channel, b"third");
+    assert!(!third.is_ready());
+    assert!(!second.is_ready());
+    drop(cancelled);
+
+    assert_eq!(sent_payloads(&conn), vec![b"first".to_vec()]);
+    assert!(first.is_ready());
+    assert!(second.notified.take());
+    assert!(!third.is_ready());
+    assert!(!second.is_ready());
+    assert!(third.notified.take());
+    assert!(!third.is_ready());
+
+    assert_eq!(sent_payloads(&conn), vec![b"second".to_vec()]);
+    assert!(second.is_ready());
+    assert!(third.notified.take());
+    assert!(!third.is_ready());
+    assert_eq!(sent_payloads(&conn), vec![b"third".to_vec()]);
+    assert!(third.is_ready());
+}
+
+#[test]
+fn publish_without_polling() {
+    let _ = env_logger::try_init();
+
+    let (conn, channel) = open_channel(ConnectionProperties::default());
+    drop(Publish::new(&channel, b"fire and forget"));
+    assert_eq!(sent_payloads(&conn), vec![b"fire and forget".to_vec()]);
+}
+
+#[cfg(feature = "streaming-publish")]
+fn poll_streaming_publish(
+    channel: &Channel,
+    body: &'static [u8],
+    body_size: u64,
+) -> (
+    Spawn<lapin_futures::StreamingPublishFuture<&'static [u8]>>,
+    NotifyHandle,
+) {
+    let publish = executor::spawn(channel.basic_publish_streaming(
+        "",
+        "queue",
+        body,
+        body_size,
+        BasicPublishOptions::default(),
+        BasicProperties::default(),
+    ));
+    (publish, NotifyHandle::from(Arc::new(Notified::default())))
+}
+
+#[cfg(feature = "streaming-publish")]
+#[test]
+fn streaming_publish_polled_after_completion() {
+    let _ = env_logger::try_init();
+
+    let (conn, channel) = open_channel(ConnectionProperties::default());
+    let (mut publish, notify) = poll_streaming_publish(&channel, b"streamed", 8);
+    assert_eq!(publish.poll_future_notify(&notify, 0), Ok(Async::NotReady));
+    assert_eq!(sent_payloads(&conn), vec![b"streamed".to_vec()]);
+    assert_eq!(publish.poll_future_notify(&notify, 0), Ok(Async::Ready(())));
+    // Polling again, as select does, keeps it pending instead of panicking
+    assert_eq!(publish.poll_future_notify(&notify, 0), Ok(Async::NotReady));
+}
+
+#[cfg(feature = "streaming-publish")]
+#[test]
+fn streaming_publish_polled_after_failure() {
+    let _ = env_logger::try_init();
+
+    let (_conn, channel) = open_channel(ConnectionProperties::default());
+    let (mut publish, notify) = poll_streaming_publish(&channel, b"longer than announced", 6);
+    assert_eq!(
+        publish.poll_future_notify(&notify, 0),
+        Err(lapin_futures::Error::BodySizeMismatch(6, 7))
+    );
+    assert_eq!(publish.poll_future_notify(&notify, 0), Ok(Async::NotReady));
+}
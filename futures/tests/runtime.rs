@@ -0,0 +1,160 @@
+#![cfg(any(
+    feature = "runtime-async-std",
+    feature = "runtime-smol",
+    feature = "runtime-tokio"
+))]
+
+use futures::{future, Future, Stream};
+use lapin_futures::options::{
+    BasicAckOptions, BasicConsumeOptions, BasicPublishOptions, QueueDeclareOptions,
+};
+use lapin_futures::types::FieldTable;
+use lapin_futures::{BasicProperties, Client, ConnectionProperties, Error};
+
+#[path = "../../tests/mock_broker/mod.rs"]
+mod mock_broker;
+
+use mock_broker::{MockBroker, Summary};
+
+const PAYLOAD: &[u8] = b"hello from the runtime";
+
+/// Connect, publish a message and consume it back, then close the connection
+fn publish_consume(
+    uri: &str,
+    options: ConnectionProperties,
+) -> impl Future<Item = Vec<u8>, Error = Error> + Send + 'static {
+    Client::connect(uri, options)
+        .and_then(|client| client.create_channel().map(|channel| (client, channel)))
+        .and_then(|(client, channel)| {
+            channel
+                .queue_declare(
+                    "runtime",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .and_then(move |queue| {
+                    let consumer_channel = channel.clone();
+                    channel
+                        .basic_publish(
+                            "",
+                            "runtime",
+                            PAYLOAD.to_vec(),
+                            BasicPublishOptions::default(),
+                            BasicProperties::default(),
+                        )
+                        .and_then(move |_| {
+                            consumer_channel.basic_consume(
+                                &queue,
+                                "runtime_consumer",
+                                BasicConsumeOptions::default(),
+                                FieldTable::default(),
+                            )
+                        })
+                        .and_then(|stream| stream.into_future().map_err(|(err, _)| err))
+                        .and_then(move |(message, _)| {
+                            let message = message.expect("no delivery");
+                            channel
+                                .basic_ack(message.delivery_tag, BasicAckOptions::default())
+                                .map(move |_| message.data.into_vec())
+                        })
+                })
+                // Closing the connection stops the io driver
+                .and_then(move |data| client.close(200, "OK").map(move |_| data))
+        })
+}
+
+fn expected_summary() -> Summary {
+    Summary {
+        published: vec![PAYLOAD.to_vec()],
+        acked: vec![1],
+        closed: true,
+    }
+}
+
+#[cfg(feature = "runtime-tokio")]
+#[test]
+fn tokio_runtime() {
+    let _ = env_logger::try_init();
+
+    use lapin_futures::TokioRuntime;
+
+    let broker = MockBroker::start();
+    let options = ConnectionProperties::default().with_runtime(TokioRuntime);
+    let mut runtime = tokio::runtime::Runtime::new().unwrap();
+    let uri = broker.uri().to_string();
+    // TokioRuntime spawns the io driver as soon as we connect, which must happen on the runtime
+    let data = runtime
+        .block_on(future::lazy(move || publish_consume(&uri, options)))
+        .unwrap();
+    runtime.shutdown_on_idle().wait().unwrap();
+    assert_eq!(data, PAYLOAD);
+    assert_eq!(broker.finish(), expected_summary());
+}
+
+#[cfg(feature = "runtime-async-std")]
+#[test]
+fn async_std_runtime() {
+    let _ = env_logger::try_init();
+
+    use futures_util::compat::Future01CompatExt;
+    use lapin_futures::AsyncStdRuntime;
+
+    let broker = MockBroker::start();
+    let options = ConnectionProperties::default().with_runtime(AsyncStdRuntime);
+    let data = async_std::task::block_on(publish_consume(broker.uri(), options).compat()).unwrap();
+    assert_eq!(data, PAYLOAD);
+    assert_eq!(broker.finish(), expected_summary());
+}
+
+#[cfg(feature = "runtime-smol")]
+#[test]
+fn smol_runtime() {
+    let _ = env_logger::try_init();
+
+    use async_executor::Executor;
+    use futures_util::compat::Future01CompatExt;
+    use lapin_futures::SmolRuntime;
+    use std::sync::Arc;
+
+    let broker = MockBroker::start();
+    let executor = Arc::new(Executor::new());
+    let options = ConnectionProperties::default().with_runtime(SmolRuntime::new(executor.clone()));
+    let data =
+        async_io::block_on(executor.run(publish_consume(broker.uri(), options).compat())).unwrap();
+    assert_eq!(data, PAYLOAD);
+    assert_eq!(broker.finish(), expected_summary());
+}
+
+#[cfg(all(
+    feature = "runtime-smol",
+    not(any(feature = "runtime-async-std", feature = "runtime-tokio"))
+))]
+#[test]
+fn inferred_runtime() {
+    let _ = env_logger::try_init();
+
+    use lapin_futures::FrameObserver;
+    use parking_lot::Mutex;
+    use std::{collections::HashSet, sync::Arc, thread};
+
+    let broker = MockBroker::start();
+    let threads = Arc::new(Mutex::new(HashSet::new()));
+    let observer = {
+        let threads = threads.clone();
+        FrameObserver::new(move |_, _| {
+            threads
+                .lock()
+                .insert(thread::current().name().map(String::from));
+        })
+    };
+    let options = ConnectionProperties {
+        frame_observer: Some(observer),
+        ..ConnectionProperties::default()
+    };
+    let data = publish_consume(broker.uri(), options).wait().unwrap();
+    assert_eq!(data, PAYLOAD);
+    assert_eq!(broker.finish(), expected_summary());
+    // Without an executor, SmolRuntime runs the connection on a thread of its own
+    let expected = vec![Some("lapin-smol".to_string())].into_iter().collect();
+    assert_eq!(*threads.lock(), expected);
+}
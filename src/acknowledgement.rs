@@ -3,11 +3,9 @@ use crate::{
     wait::{Wait, WaitHandle},
     Error, Result,
 };
+use log::trace;
 use parking_lot::Mutex;
-use std::{
-    collections::{HashMap, HashSet},
-    sync::Arc,
-};
+use std::{collections::HashMap, sync::Arc};
 
 pub type DeliveryTag = u64;
 
@@ -23,12 +21,32 @@ impl Acknowledgements {
         }
     }
 
-    pub(crate) fn register_pending(&self, delivery_tag: DeliveryTag) {
-        self.inner.lock().register_pending(delivery_tag);
+    pub(crate) fn register_pending(&self, delivery_tag: DeliveryTag) -> Wait<()> {
+        self.inner.lock().register_pending(delivery_tag)
     }
 
-    pub(crate) fn get_last_pending(&self) -> Option<Wait<()>> {
-        self.inner.lock().last.take()
+    /// Forget a publish which couldn't be queued, and won't be confirmed
+    pub(crate) fn cancel_pending(&self, delivery_tag: DeliveryTag) {
+        let mut inner = self.inner.lock();
+        inner.pending.remove(&delivery_tag);
+        inner.check_settled();
+    }
+
+    /// Wait until no publish is waiting for its confirm anymore
+    pub(crate) fn wait_settled(&self) -> Wait<()> {
+        let (wait, wait_handle) = Wait::new();
+        let mut inner = self.inner.lock();
+        if inner.pending.is_empty() {
+            wait_handle.finish(());
+        } else {
+            inner.settled_waiters.push(wait_handle);
+        }
+        wait
+    }
+
+    /// The delivery tags of the publishes nacked since the last call
+    pub(crate) fn drain_nacked(&self) -> Vec<DeliveryTag> {
+        self.inner.lock().nacked.drain(..).collect()
     }
 
     pub(crate) fn ack(&self, delivery_tag: DeliveryTag) -> Result<()> {
@@ -41,15 +59,40 @@ impl Acknowledgements {
 
     pub(crate) fn ack_all_pending(&self) {
         let mut inner = self.inner.lock();
-        for wait in inner.drain_pending() {
-            wait.finish(());
+        let mut pending = inner.drain_pending();
+        pending.sort_by_key(|tup| tup.0);
+        for (delivery_tag, publish_wait) in pending {
+            trace!("publish acked; delivery_tag={}", delivery_tag);
+            if let Some(message) = inner.returned_messages.next_unconfirmed() {
+                publish_wait.error(Error::MessageReturned(Box::new(message)));
+            } else {
+                publish_wait.finish(());
+            }
         }
+        inner.check_settled();
     }
 
     pub(crate) fn nack_all_pending(&self) {
         let mut inner = self.inner.lock();
-        for wait in inner.drain_pending() {
-            wait.finish(());
+        let mut pending = inner.drain_pending();
+        pending.sort_by_key(|tup| tup.0);
+        for (delivery_tag, publish_wait) in pending {
+            trace!("publish nacked; delivery_tag={}", delivery_tag);
+            inner.nacked.push(delivery_tag);
+            publish_wait.error(Error::PublishNacked(delivery_tag));
+        }
+        inner.check_settled();
+    }
+
+    /// Fail the confirmations which will never come, the channel being gone
+    pub(crate) fn fail_all_pending(&self, error: &dyn Fn() -> Error) {
+        let mut inner = self.inner.lock();
+        for (delivery_tag, publish_wait) in inner.drain_pending() {
+            trace!("publish won't be confirmed; delivery_tag={}", delivery_tag);
+            publish_wait.error(error());
+        }
+        for wait_handle in inner.settled_waiters.drain(..) {
+            wait_handle.error(error());
         }
     }
 
@@ -72,39 +115,55 @@ impl Acknowledgements {
 
 #[derive(Debug)]
 struct Inner {
-    last: Option<Wait<()>>,
     pending: HashMap<DeliveryTag, WaitHandle<()>>,
+    nacked: Vec<DeliveryTag>,
+    settled_waiters: Vec<WaitHandle<()>>,
     returned_messages: ReturnedMessages,
 }
 
 impl Inner {
     fn new(returned_messages: ReturnedMessages) -> Self {
         Self {
-            last: None,
             pending: HashMap::default(),
+            nacked: Vec::default(),
+            settled_waiters: Vec::default(),
             returned_messages,
         }
     }
 
-    fn register_pending(&mut self, delivery_tag: DeliveryTag) {
-        let (wait, wait_handle) = Wait::new();
-        self.pending.insert(delivery_tag, wait_handle);
-        self.last = Some(wait);
+    fn register_pending(&mut self, delivery_tag: DeliveryTag) -> Wait<()> {
+        let (publish_wait, publish_wait_handle) = Wait::new();
+        self.pending.insert(delivery_tag, publish_wait_handle);
+        publish_wait
     }
 
     fn drop_pending(&mut self, delivery_tag: DeliveryTag, success: bool) -> Result<()> {
-        if let Some(delivery_wait) = self.pending.remove(&delivery_tag) {
+        if let Some(publish_wait) = self.pending.remove(&delivery_tag) {
             if success {
-                delivery_wait.finish(());
+                if let Some(message) = self.returned_messages.next_unconfirmed() {
+                    publish_wait.error(Error::MessageReturned(Box::new(message)));
+                } else {
+                    publish_wait.finish(());
+                }
             } else {
-                self.returned_messages.register_waiter(delivery_wait);
+                self.nacked.push(delivery_tag);
+                publish_wait.error(Error::PublishNacked(delivery_tag));
             }
+            self.check_settled();
             Ok(())
         } else {
             Err(Error::PreconditionFailed)
         }
     }
 
+    fn check_settled(&mut self) {
+        if self.pending.is_empty() {
+            for wait_handle in self.settled_waiters.drain(..) {
+                wait_handle.finish(());
+            }
+        }
+    }
+
     fn ack(&mut self, delivery_tag: DeliveryTag) -> Result<()> {
         self.drop_pending(delivery_tag, true)?;
         Ok(())
@@ -115,16 +174,19 @@ impl Inner {
         Ok(())
     }
 
-    fn drain_pending(&mut self) -> Vec<WaitHandle<()>> {
-        self.pending.drain().map(|tup| tup.1).collect()
+    fn drain_pending(&mut self) -> Vec<(DeliveryTag, WaitHandle<()>)> {
+        self.pending.drain().collect()
     }
 
-    fn list_pending_before(&mut self, delivery_tag: DeliveryTag) -> HashSet<DeliveryTag> {
-        self.pending
+    fn list_pending_before(&mut self, delivery_tag: DeliveryTag) -> Vec<DeliveryTag> {
+        let mut tags = self
+            .pending
             .iter()
             .map(|tup| tup.0)
             .filter(|tag| **tag <= delivery_tag)
             .cloned()
-            .collect()
+            .collect::<Vec<_>>();
+        tags.sort();
+        tags
     }
 }
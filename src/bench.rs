@@ -0,0 +1,167 @@
+//! Helpers for the benchmarks, not part of the public API
+
+use crate::{
+    connection_status::ConnectionState,
+    consumer::Consumer,
+    executor::DefaultExecutor,
+    options::BasicPublishOptions,
+    queue::{Queue, QueueState},
+    types::ShortString,
+    BasicProperties, Channel, Connection, Payload, SerializeOutcome,
+};
+use amq_protocol::{
+    frame::{gen_frame, AMQPContentHeader, AMQPFrame},
+    protocol::{basic, AMQPClass},
+};
+
+/// A connection with a single consumer, fed with pre-serialized deliveries
+///
+/// With `shared`, the deliveries are views on the received data, as with the io loop,
+/// otherwise their payloads are copied out of it, as with an external io driver.
+pub struct ConsumeBench {
+    conn: Connection,
+    consumer: Consumer,
+    data: Payload,
+    frames: usize,
+    shared: bool,
+}
+
+impl ConsumeBench {
+    pub fn new(deliveries: u64, payload_size: usize, shared: bool) -> Self {
+        let conn = Connection::default();
+        conn.set_state(ConnectionState::Connected);
+        conn.configuration().set_channel_max(2047);
+        let channel = conn
+            .create_open_channel()
+            .expect("failed to create channel");
+        let consumer_tag = ShortString::from("bench");
+        let mut queue: QueueState = Queue::new("bench".into(), 0, 0).into();
+        let consumer = Consumer::new(consumer_tag.clone(), DefaultExecutor::default());
+        queue.register_consumer(consumer_tag.clone(), consumer.clone());
+        channel.register_queue(queue);
+        let payload = vec![42; payload_size];
+        let mut data = Vec::new();
+        let mut frames = 0;
+        for delivery_tag in 1..=deliveries {
+            for frame in &[
+                AMQPFrame::Method(
+                    channel.id(),
+                    AMQPClass::Basic(basic::AMQPMethod::Deliver(basic::Deliver {
+                        consumer_tag: consumer_tag.clone(),
+                        delivery_tag,
+                        redelivered: false,
+                        exchange: "".into(),
+                        routing_key: "bench".into(),
+                    })),
+                ),
+                AMQPFrame::Header(
+                    channel.id(),
+                    60,
+                    Box::new(AMQPContentHeader {
+                        class_id: 60,
+                        weight: 0,
+                        body_size: payload_size as u64,
+                        properties: BasicProperties::default(),
+                    }),
+                ),
+                AMQPFrame::Body(channel.id(), payload.clone()),
+            ] {
+                data = gen_frame(frame)(data.into())
+                    .expect("failed to serialize frame")
+                    .into_inner()
+                    .0;
+                frames += 1;
+            }
+        }
+        Self {
+            conn,
+            consumer,
+            data: data.into(),
+            frames,
+            shared,
+        }
+    }
+
+    /// The number of frames handled by each run
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// Parse and handle all the deliveries, then drain them from the consumer
+    pub fn run(&self) {
+        let (consumed, res) = if self.shared {
+            self.conn.parse_shared(&self.data)
+        } else {
+            self.conn.parse_all(&self.data)
+        };
+        assert_eq!(consumed, self.data.len());
+        res.expect("failed to handle frames");
+        while let Some(delivery) = self.consumer.inner().next_delivery() {
+            delivery.expect("delivery error");
+        }
+    }
+}
+
+/// A connection with an open channel, publishing messages and dropping the serialized frames
+///
+/// With `shared`, the messages share the same payload, otherwise each gets its own copy of it,
+/// as when publishing from a `Vec<u8>`.
+pub struct PublishBench {
+    conn: Connection,
+    channel: Channel,
+    messages: u64,
+    payload: Payload,
+    shared: bool,
+    buffer: Vec<u8>,
+}
+
+impl PublishBench {
+    pub fn new(messages: u64, payload_size: usize, frame_max: u32, shared: bool) -> Self {
+        let conn = Connection::default();
+        conn.set_state(ConnectionState::Connected);
+        conn.configuration().set_channel_max(2047);
+        conn.configuration().set_frame_max(frame_max);
+        let channel = conn
+            .create_open_channel()
+            .expect("failed to create channel");
+        Self {
+            conn,
+            channel,
+            messages,
+            payload: vec![42; payload_size].into(),
+            shared,
+            buffer: vec![0; frame_max as usize],
+        }
+    }
+
+    /// The number of messages published by each run
+    pub fn messages(&self) -> u64 {
+        self.messages
+    }
+
+    /// Publish all the messages, serializing their frames to the send buffer as the io loop
+    /// would
+    pub fn run(&mut self) {
+        for _ in 0..self.messages {
+            let payload = if self.shared {
+                self.payload.clone()
+            } else {
+                self.payload.to_vec().into()
+            };
+            let _ = self.channel.basic_publish(
+                "",
+                "bench",
+                BasicPublishOptions::default(),
+                payload,
+                BasicProperties::default(),
+            );
+            loop {
+                match self.conn.serialize_frame(&mut self.buffer) {
+                    Ok(SerializeOutcome::Wrote(_)) => {}
+                    Ok(SerializeOutcome::Idle) => break,
+                    Err(error) => panic!("failed to serialize frame: {:?}", error),
+                }
+            }
+        }
+    }
+}
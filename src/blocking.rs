@@ -0,0 +1,278 @@
+use crate::{
+    confirmation::Confirmation,
+    connection_status::ConnectionState,
+    message::Delivery,
+    options::{BasicAckOptions, BasicConsumeOptions, BasicPublishOptions, QueueDeclareOptions},
+    types::FieldTable,
+    uri::AMQPScheme,
+    BasicProperties, Channel, Connection, ConnectionProperties, Consumer, Error, Payload, Queue,
+    Result, SerializeOutcome,
+};
+use log::trace;
+use std::{
+    io::{self, Read, Write},
+    net::TcpStream,
+    time::Instant,
+};
+
+const SEND_BUFFER_SIZE: usize = 8 * 1024;
+const RECEIVE_BUFFER_SIZE: usize = 128 * 1024;
+
+/// A connection to an AMQP server driven from the calling thread, without the io loop thread
+/// nor an async runtime
+///
+/// It owns the TCP stream and only does network I/O while one of its methods blocks: the queued
+/// frames get serialized and written, then the stream is read and parsed until the operation
+/// completes. Heartbeats are only sent while blocked that way, so a client left idle for longer
+/// than the negotiated interval gets disconnected by the server.
+///
+/// ```rust,no_run
+/// use lapin::{BasicProperties, BlockingClient, ConnectionProperties};
+///
+/// let mut client =
+///     BlockingClient::connect("amqp://127.0.0.1:5672/%2f", ConnectionProperties::default())
+///         .expect("connect");
+/// let queue = client.declare("hello").expect("declare");
+/// client
+///     .publish("", "hello", b"Hello world!".to_vec(), BasicProperties::default())
+///     .expect("publish");
+/// let consumer = client.consume(&queue, "my_consumer").expect("consume");
+/// while let Some(delivery) = client.next_delivery(&consumer).expect("next_delivery") {
+///     client.ack(&delivery).expect("ack");
+/// }
+/// ```
+pub struct BlockingClient {
+    io: BlockingIo,
+    channel: Channel,
+}
+
+impl BlockingClient {
+    /// Connect to an AMQP server and open a channel, blocking until both are done
+    ///
+    /// TLS isn't handled: amqps:// URIs are refused.
+    pub fn connect(uri: &str, options: ConnectionProperties) -> Result<BlockingClient> {
+        let uri = Connection::validate_uri(uri)?;
+        if uri.scheme == AMQPScheme::AMQPS {
+            return Err(Error::InvalidUri(
+                "amqps:// isn't supported by the blocking client".into(),
+            ));
+        }
+        let stream = TcpStream::connect((uri.authority.host.as_str(), uri.authority.port))?;
+        let (connection, handshake) = Connection::handshake(uri, options)?;
+        let mut io = BlockingIo {
+            connection,
+            stream,
+            send_buffer: vec![0; SEND_BUFFER_SIZE],
+            receive_buffer: vec![0; RECEIVE_BUFFER_SIZE],
+            received: 0,
+        };
+        let connection = io.wait(handshake)?;
+        let channel = io.wait(connection.create_channel())?;
+        Ok(BlockingClient { io, channel })
+    }
+
+    /// The underlying connection
+    pub fn connection(&self) -> &Connection {
+        &self.io.connection
+    }
+
+    /// The channel used by the methods of the client, for the operations it doesn't provide
+    ///
+    /// The `Confirmation`s it returns must be handed to `wait` instead of being waited on
+    /// directly, which would block forever as nothing else drives the connection.
+    pub fn channel(&self) -> &Channel {
+        &self.channel
+    }
+
+    /// Drive the connection until `confirmation` completes, and return its result
+    pub fn wait<T, I>(&mut self, confirmation: Confirmation<T, I>) -> Result<T> {
+        self.io.wait(confirmation)
+    }
+
+    /// Declare a queue with the default options
+    pub fn declare(&mut self, queue: &str) -> Result<Queue> {
+        let declare = self.channel.queue_declare(
+            queue,
+            QueueDeclareOptions::default(),
+            FieldTable::default(),
+        );
+        self.wait(declare)
+    }
+
+    /// Publish a message, blocking until it is sent, or acked by the server if publisher
+    /// confirms are enabled on the channel
+    pub fn publish<P: Into<Payload>>(
+        &mut self,
+        exchange: &str,
+        routing_key: &str,
+        payload: P,
+        properties: BasicProperties,
+    ) -> Result<()> {
+        let payload = payload.into();
+        loop {
+            // basic_publish could park this thread, which is the one freeing the send queue
+            let publish = self.channel.try_basic_publish(
+                exchange,
+                routing_key,
+                BasicPublishOptions::default(),
+                payload.clone(),
+                properties.clone(),
+            );
+            match self.wait(publish) {
+                Err(Error::SendQueueFull) => self.io.flush()?,
+                res => return res,
+            }
+        }
+    }
+
+    /// Start consuming from a queue, the deliveries being read with `next_delivery`
+    pub fn consume(&mut self, queue: &Queue, consumer_tag: &str) -> Result<Consumer> {
+        let consume = self.channel.basic_consume(
+            queue,
+            consumer_tag,
+            BasicConsumeOptions::default(),
+            FieldTable::default(),
+        );
+        self.wait(consume)
+    }
+
+    /// Block until the next delivery of `consumer`, returning `None` once it got cancelled
+    ///
+    /// The consumer must not have a delegate, which would get the deliveries instead.
+    pub fn next_delivery(&mut self, consumer: &Consumer) -> Result<Option<Delivery>> {
+        loop {
+            if let Some(delivery) = consumer.inner().next_delivery() {
+                return delivery;
+            }
+            self.io.flush()?;
+            self.io.read()?;
+        }
+    }
+
+    /// Acknowledge a delivery received by one of the consumers of the client
+    pub fn ack(&mut self, delivery: &Delivery) -> Result<()> {
+        let ack = delivery.ack(&self.channel, BasicAckOptions::default());
+        self.wait(ack)
+    }
+
+    /// Close the connection, blocking until the server acknowledged it
+    pub fn close(mut self) -> Result<()> {
+        let close = self.io.connection.close(200, "OK");
+        self.wait(close)
+    }
+}
+
+/// The network side of a `BlockingClient`, moving frames between the connection and the stream
+struct BlockingIo {
+    connection: Connection,
+    stream: TcpStream,
+    send_buffer: Vec<u8>,
+    receive_buffer: Vec<u8>,
+    received: usize,
+}
+
+impl BlockingIo {
+    fn wait<T, I>(&mut self, confirmation: Confirmation<T, I>) -> Result<T> {
+        loop {
+            self.flush()?;
+            if let Some(res) = confirmation.try_wait() {
+                return res;
+            }
+            self.read()?;
+        }
+    }
+
+    /// Serialize the queued frames and write them to the stream
+    fn flush(&mut self) -> Result<()> {
+        let mut pending = 0;
+        while !self.connection.status().blocked() {
+            match self.connection.serialize(&mut self.send_buffer[pending..]) {
+                Ok(SerializeOutcome::Wrote(size)) => pending += size,
+                Ok(SerializeOutcome::Idle) => break,
+                Err(Error::BufferTooSmall) if pending == 0 => {
+                    // A frame larger than the buffer, up to frame_max
+                    let size = self.send_buffer.len() * 2;
+                    self.send_buffer.resize(size, 0);
+                }
+                Err(Error::BufferTooSmall) => {
+                    self.write(pending)?;
+                    pending = 0;
+                }
+                Err(error) => return Err(error),
+            }
+        }
+        self.write(pending)
+    }
+
+    fn write(&mut self, size: usize) -> Result<()> {
+        if size == 0 {
+            return Ok(());
+        }
+        if let Err(e) = self.stream.write_all(&self.send_buffer[..size]) {
+            return Err(self.connection.io_error(e));
+        }
+        trace!("blocking client: wrote {} bytes", size);
+        self.connection.metrics().on_bytes_written(size);
+        Ok(())
+    }
+
+    /// Read from the stream and handle the received frames, sending a heartbeat instead if
+    /// nothing was received for the negotiated interval
+    fn read(&mut self) -> Result<()> {
+        if self.received == self.receive_buffer.len() {
+            // A frame larger than the buffer, up to frame_max
+            let size = self.receive_buffer.len() * 2;
+            self.receive_buffer.resize(size, 0);
+        }
+        let heartbeat = self.connection.heartbeat_interval();
+        if let Err(e) = self.stream.set_read_timeout(heartbeat) {
+            return Err(self.connection.io_error(e));
+        }
+        match self.stream.read(&mut self.receive_buffer[self.received..]) {
+            Ok(0) if self.connection.status().closed() => {
+                Err(Error::InvalidConnectionState(ConnectionState::Closed))
+            }
+            Ok(0) => Err(self
+                .connection
+                .io_error(io::ErrorKind::UnexpectedEof.into())),
+            Ok(size) => {
+                trace!("blocking client: read {} bytes", size);
+                self.connection.bytes_read(size);
+                self.received += size;
+                self.parse_received()
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => Ok(()),
+            // Timed out, the error kind depending on the platform
+            Err(e)
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut =>
+            {
+                let interval = heartbeat.unwrap_or_default();
+                // The server sends heartbeats too, nothing for that long means it's gone
+                if Instant::now() - self.connection.last_progress() > interval * 2 {
+                    return Err(self.connection.io_error(io::ErrorKind::TimedOut.into()));
+                }
+                trace!("blocking client: send heartbeat");
+                self.connection.send_heartbeat()
+            }
+            Err(e) => Err(self.connection.io_error(e)),
+        }
+    }
+
+    /// Handle the frames from the receive buffer, keeping what wasn't consumed
+    fn parse_received(&mut self) -> Result<()> {
+        while self.received > 0 {
+            let (consumed, _) = match self
+                .connection
+                .handle_data(&self.receive_buffer[..self.received])
+            {
+                Ok(handled) => handled,
+                // No complete frame yet, wait for more data
+                Err(Error::WouldBlock) => break,
+                Err(error) => return Err(error),
+            };
+            self.receive_buffer.copy_within(consumed..self.received, 0);
+            self.received -= consumed;
+        }
+        Ok(())
+    }
+}
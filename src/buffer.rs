@@ -1,40 +1,35 @@
-use amq_protocol::frame::{BackToTheBuffer, GenError, GenResult, WriteContext};
-use std::{cmp, io};
+use crate::Payload;
+use std::{cmp, sync::Arc};
 
+/// A buffer whose data can be handed out as payloads sharing its memory
+///
+/// The memory is never written to while shared: it gets replaced instead, the data left being
+/// copied to the new one.
 #[derive(Debug, PartialEq, Clone)]
 pub(crate) struct Buffer {
-    memory: Vec<u8>,
+    memory: Arc<Vec<u8>>,
     capacity: usize,
     position: usize,
     end: usize,
 }
 
-pub(crate) struct Checkpoint(usize);
-
 impl Buffer {
     pub(crate) fn with_capacity(capacity: usize) -> Buffer {
         Buffer {
-            memory: vec![0; capacity],
+            memory: Arc::new(vec![0; capacity]),
             capacity,
             position: 0,
             end: 0,
         }
     }
 
-    pub(crate) fn checkpoint(&self) -> Checkpoint {
-        Checkpoint(self.end)
-    }
-
-    pub(crate) fn rollback(&mut self, checkpoint: Checkpoint) {
-        self.end = checkpoint.0;
-    }
-
     pub(crate) fn grow(&mut self, new_size: usize) -> bool {
         if self.capacity >= new_size {
             return false;
         }
 
-        self.memory.resize(new_size, 0);
+        self.unshare();
+        Arc::make_mut(&mut self.memory).resize(new_size, 0);
         self.capacity = new_size;
         true
     }
@@ -63,67 +58,48 @@ impl Buffer {
         &self.memory[self.position..self.end]
     }
 
+    /// Same as data, as a payload sharing the memory of the buffer
+    pub(crate) fn shared_data(&self) -> Payload {
+        Payload::shared(self.memory.clone(), self.position, self.end)
+    }
+
     pub(crate) fn space(&mut self) -> &mut [u8] {
-        &mut self.memory[self.end..self.capacity]
+        self.unshare();
+        let (end, capacity) = (self.end, self.capacity);
+        &mut Arc::make_mut(&mut self.memory)[end..capacity]
     }
 
     pub(crate) fn shift(&mut self) {
+        self.unshare();
         let length = self.end - self.position;
         if length > self.position {
             return;
         } else {
-            let (start, end) = self.memory.split_at_mut(self.position);
+            let memory = Arc::make_mut(&mut self.memory);
+            let (start, end) = memory.split_at_mut(self.position);
             start[..length].copy_from_slice(&end[..length]);
         }
         self.position = 0;
         self.end = length;
     }
 
-    pub(crate) fn shift_unless_available(&mut self, size: usize) {
-        if self.available_space() < size {
-            self.shift();
+    /// Move the data to new memory if payloads still share the current one
+    fn unshare(&mut self) {
+        if Arc::get_mut(&mut self.memory).is_some() {
+            return;
         }
+        let length = self.end - self.position;
+        let mut memory = vec![0; self.capacity];
+        memory[..length].copy_from_slice(&self.memory[self.position..self.end]);
+        self.memory = Arc::new(memory);
+        self.position = 0;
+        self.end = length;
     }
-}
-
-impl io::Write for &mut Buffer {
-    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
-        let amt = self.space().write(data)?;
-        self.fill(amt);
-        Ok(amt)
-    }
-
-    fn flush(&mut self) -> io::Result<()> {
-        Ok(())
-    }
-}
 
-impl BackToTheBuffer for &mut Buffer {
-    fn reserve_write_use<
-        Tmp,
-        Gen: Fn(WriteContext<Self>) -> Result<(WriteContext<Self>, Tmp), GenError>,
-        Before: Fn(WriteContext<Self>, Tmp) -> GenResult<Self>,
-    >(
-        mut s: WriteContext<Self>,
-        reserved: usize,
-        gen: &Gen,
-        before: &Before,
-    ) -> Result<WriteContext<Self>, GenError> {
-        if s.write.available_space() < reserved {
-            return Err(GenError::BufferTooSmall(
-                reserved - s.write.available_space(),
-            ));
+    pub(crate) fn shift_unless_available(&mut self, size: usize) {
+        if self.available_space() < size {
+            self.shift();
         }
-        let start = s.write.end;
-        s.write.end += reserved;
-        gen(s).and_then(|(s, tmp)| {
-            let end = s.write.end;
-            s.write.end = start;
-            before(s, tmp).and_then(|s| {
-                s.write.end = end;
-                Ok(s)
-            })
-        })
     }
 }
 
@@ -175,4 +151,19 @@ mod tests {
         assert_eq!(b.available_space(), 0);
         assert_eq!(b.data(), &b"cdefghijkl"[..]);
     }
+
+    #[test]
+    fn shared_data() {
+        let mut b = Buffer::with_capacity(10);
+        b.space()[..4].copy_from_slice(b"abcd");
+        b.fill(4);
+        let shared = b.shared_data();
+        b.consume(3);
+        // Writing to the buffer moves the data left to new memory
+        b.space()[..2].copy_from_slice(b"ef");
+        b.fill(2);
+        assert_eq!(shared, b"abcd");
+        assert_eq!(b.data(), &b"def"[..]);
+        assert_eq!(b.available_space(), 7);
+    }
 }
@@ -1,30 +1,43 @@
 use crate::{
     acknowledgement::{Acknowledgements, DeliveryTag},
     auth::Credentials,
-    channel_status::{ChannelState, ChannelStatus},
+    channel_status::{ChannelState, ChannelStatus, Qos},
+    configuration::SendQueueFullPolicy,
     confirmation::Confirmation,
     connection::Connection,
+    connection_properties::DEFAULT_LOCALE,
     connection_status::ConnectionState,
-    consumer::Consumer,
+    consumer::{CancelledBy, Consumer},
+    dead_letters::RejectedPublish,
+    error::CloseReason,
+    error_handler::ChannelErrorHandler,
     executor::Executor,
-    frames::{ExpectedReply, Priority},
+    frames::{ExpectedReply, OutboundFrame, Priority, PublishFrames},
     id_sequence::IdSequence,
-    message::{BasicGetMessage, BasicReturnMessage, Delivery},
+    message::{BasicGetMessage, BasicReturnMessage, Delivery, UnconfirmedMessages},
     protocol::{self, AMQPClass, AMQPError, AMQPSoftError},
     queue::Queue,
     queues::Queues,
     returned_messages::ReturnedMessages,
+    state_dump::{channel_state_label, ChannelDump},
+    streaming_publish::StreamingPublish,
+    temporary_queue::TemporaryQueue,
     types::*,
-    wait::{Wait, WaitHandle},
-    BasicProperties, Error, ExchangeKind, Result,
+    unacked_deliveries::UnackedDeliveries,
+    wait::{NotifyReady, ThreadNotifier, Wait, WaitHandle},
+    BasicProperties, Error, ExchangeKind, Payload, Result,
 };
 use amq_protocol::frame::{AMQPContentHeader, AMQPFrame};
 use log::{debug, error, info, trace};
-use std::{borrow::Borrow, sync::Arc};
+use parking_lot::Mutex;
+use std::{borrow::Borrow, io::Read, sync::Arc, thread, time::SystemTime};
 
-#[cfg(test)]
+#[cfg(any(test, feature = "bench"))]
 use crate::queue::QueueState;
 
+/// The default frame_max of RabbitMQ
+const STREAMING_CHUNK_MAX: usize = 128 * 1024;
+
 #[derive(Clone, Debug)]
 pub struct Channel {
     id: u16,
@@ -32,8 +45,12 @@ pub struct Channel {
     status: ChannelStatus,
     acknowledgements: Acknowledgements,
     delivery_tag: IdSequence<DeliveryTag>,
+    publish_lock: Arc<Mutex<()>>,
+    consumer_tag: IdSequence<u64>,
     queues: Queues,
     returned_messages: ReturnedMessages,
+    unacked_deliveries: UnackedDeliveries,
+    error_handler: ChannelErrorHandler,
     executor: Arc<dyn Executor>,
 }
 
@@ -50,8 +67,12 @@ impl Channel {
             status: ChannelStatus::default(),
             acknowledgements: Acknowledgements::new(returned_messages.clone()),
             delivery_tag: IdSequence::new(false),
+            publish_lock: Arc::default(),
+            consumer_tag: IdSequence::new(false),
             queues: Queues::default(),
             returned_messages,
+            unacked_deliveries: UnackedDeliveries::default(),
+            error_handler: ChannelErrorHandler::default(),
             executor,
         }
     }
@@ -62,16 +83,36 @@ impl Channel {
 
     fn set_closed(&self) -> Result<()> {
         self.set_state(ChannelState::Closed);
+        let id = self.id;
+        self.acknowledgements
+            .fail_all_pending(&|| Error::ChannelClosed(id));
         self.cancel_consumers()
             .and(self.connection.remove_channel(self.id))
     }
 
     fn set_error(&self) -> Result<()> {
         self.set_state(ChannelState::Error);
+        self.acknowledgements
+            .fail_all_pending(&|| Error::InvalidChannelState(ChannelState::Error));
         self.error_consumers()
             .and(self.connection.remove_channel(self.id))
     }
 
+    /// The error for methods sent on a channel which isn't open
+    fn not_connected_error(&self) -> Error {
+        match self.status.state() {
+            ChannelState::Closing | ChannelState::Closed => Error::ChannelClosed(self.id),
+            _ => Error::NotConnected,
+        }
+    }
+
+    /// Fail the publisher confirms and the basic_get still waiting for something from the
+    /// server, the connection being gone
+    pub(crate) fn fail_pending(&self, error: &dyn Fn() -> Error) {
+        self.acknowledgements.fail_all_pending(error);
+        self.queues.fail_get_messages(error);
+    }
+
     pub(crate) fn cancel_consumers(&self) -> Result<()> {
         self.queues.cancel_consumers()
     }
@@ -82,16 +123,40 @@ impl Channel {
 
     pub(crate) fn set_state(&self, state: ChannelState) {
         self.status.set_state(state);
+        // The publishers waiting for room in the send queue must give up
+        if !self.status.is_connected() {
+            self.connection.notify_send_queue();
+        }
+    }
+
+    pub(crate) fn dump_state(&self, pending_operations: Vec<String>) -> ChannelDump {
+        ChannelDump {
+            id: self.id,
+            state: channel_state_label(&self.status.state()),
+            confirm: self.status.confirm(),
+            send_flow: self.status.flow(),
+            pending_operations,
+            consumers: self.queues.dump_consumers(),
+        }
     }
 
     pub fn id(&self) -> u16 {
         self.id
     }
 
+    /// Close the channel, resolving once the server replied with channel.close-ok
+    ///
+    /// The methods sent on the channel from then on fail with `Error::ChannelClosed`, as do the
+    /// publisher confirms still pending once it is closed.
     pub fn close(&self, reply_code: ShortUInt, reply_text: &str) -> Confirmation<()> {
         self.do_channel_close(reply_code, reply_text, 0, 0)
     }
 
+    /// Start consuming from a queue
+    ///
+    /// With an empty `consumer_tag`, the server generates one, see `Consumer::tag`. As there is
+    /// no reply to learn it from with `options.nowait`, a `lapin-ctag-<n>` one gets generated
+    /// instead.
     pub fn basic_consume(
         &self,
         queue: &Queue,
@@ -99,9 +164,261 @@ impl Channel {
         options: BasicConsumeOptions,
         arguments: FieldTable,
     ) -> Confirmation<Consumer> {
+        if consumer_tag.is_empty() && options.nowait {
+            let consumer_tag = format!("lapin-ctag-{}", self.consumer_tag.next());
+            return self.do_basic_consume(queue.borrow(), &consumer_tag, options, arguments);
+        }
         self.do_basic_consume(queue.borrow(), consumer_tag, options, arguments)
     }
 
+    /// Publish a message
+    ///
+    /// When publisher confirms are enabled on this channel (see `confirm_select`), the returned
+    /// `Confirmation` resolves once the server acked the message, or fails with
+    /// `Error::PublishNacked` if it got nacked, or with `Error::MessageReturned` if the server
+    /// returned it. Otherwise it resolves once the message is sent.
+    ///
+    /// The payload is either a `Vec<u8>`, taken over without being copied, or a `Payload` which
+    /// can be shared with other publishes. It is only copied when written to the send buffer.
+    pub fn basic_publish<P: Into<Payload>>(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        options: BasicPublishOptions,
+        payload: P,
+        properties: BasicProperties,
+    ) -> Confirmation<()> {
+        let wait_for_room = self.waits_for_send_queue();
+        self.publish(
+            exchange,
+            routing_key,
+            options,
+            payload.into(),
+            properties,
+            wait_for_room,
+        )
+    }
+
+    /// Publish a message without ever blocking, failing with `Error::SendQueueFull` if the send
+    /// queue is full whatever the `SendQueueFullPolicy`
+    ///
+    /// This is meant for async code, which should instead wait for `send_queue_full` to turn
+    /// false, using `subscribe_send_queue` to get notified.
+    pub fn try_basic_publish<P: Into<Payload>>(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        options: BasicPublishOptions,
+        payload: P,
+        properties: BasicProperties,
+    ) -> Confirmation<()> {
+        self.publish(
+            exchange,
+            routing_key,
+            options,
+            payload.into(),
+            properties,
+            false,
+        )
+    }
+
+    fn publish(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        options: BasicPublishOptions,
+        payload: Payload,
+        properties: BasicProperties,
+        wait_for_room: bool,
+    ) -> Confirmation<()> {
+        if let Some(rejection) = self.publish_rejection(wait_for_room) {
+            if self.connection.has_dead_letter_store() {
+                self.connection.store_dead_letter(RejectedPublish {
+                    exchange: exchange.into(),
+                    routing_key: routing_key.into(),
+                    options,
+                    payload: payload.into_vec(),
+                    properties,
+                    error: rejection(),
+                    rejected_at: SystemTime::now(),
+                });
+            }
+            return Confirmation::new_error(rejection());
+        }
+        self.do_basic_publish(
+            exchange,
+            routing_key,
+            options,
+            payload,
+            properties,
+            wait_for_room,
+        )
+    }
+
+    /// Publish a message whose body is read from `body`, without loading it fully in memory
+    ///
+    /// `body_size` is announced to the server before reading the body, so if `body` yields a
+    /// different number of bytes, an `Error::BodySizeMismatch` is returned and the channel cannot
+    /// be used anymore. This blocks the current thread until the whole body has been queued.
+    pub fn basic_publish_streaming<R: Read>(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        options: BasicPublishOptions,
+        body: R,
+        body_size: u64,
+        properties: BasicProperties,
+    ) -> Confirmation<()> {
+        let wait_for_room = self.waits_for_send_queue();
+        match self.do_start_streaming_publish(
+            exchange,
+            routing_key,
+            options,
+            body_size,
+            properties,
+            wait_for_room,
+        ) {
+            Ok(publish) => publish.send_reader(body),
+            Err(error) => Confirmation::new_error(error),
+        }
+    }
+
+    /// Start publishing a message whose body will be sent chunk by chunk
+    ///
+    /// See `StreamingPublish` for the following steps.
+    pub fn start_streaming_publish(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        options: BasicPublishOptions,
+        body_size: u64,
+        properties: BasicProperties,
+    ) -> Result<StreamingPublish> {
+        self.do_start_streaming_publish(
+            exchange,
+            routing_key,
+            options,
+            body_size,
+            properties,
+            false,
+        )
+    }
+
+    fn do_start_streaming_publish(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        options: BasicPublishOptions,
+        body_size: u64,
+        properties: BasicProperties,
+        wait_for_room: bool,
+    ) -> Result<StreamingPublish> {
+        if let Some(rejection) = self.publish_rejection(wait_for_room) {
+            return Err(rejection());
+        }
+        let BasicPublishOptions {
+            mandatory,
+            immediate,
+        } = options;
+        let method = AMQPClass::Basic(protocol::basic::AMQPMethod::Publish(
+            protocol::basic::Publish {
+                exchange: exchange.into(),
+                routing_key: routing_key.into(),
+                mandatory,
+                immediate,
+            },
+        ));
+        let frames = vec![self.content_frames(method, body_size, properties)];
+        // Kept apart from the state, which tracks the content we receive
+        self.status.set_streaming(true);
+        self.connection.start_streaming(self.id);
+        let publisher_confirm = match self.send_publish_frames(frames, wait_for_room) {
+            Ok((publisher_confirm, _)) => publisher_confirm,
+            Err(error) => {
+                self.end_streaming_publish();
+                return Err(error);
+            }
+        };
+        // Don't allocate unbounded chunks if frame_max hasn't been negotiated
+        let chunk_size = std::cmp::min(self.body_frame_max(), STREAMING_CHUNK_MAX);
+        Ok(StreamingPublish::new(
+            self.clone(),
+            body_size,
+            chunk_size,
+            publisher_confirm,
+        ))
+    }
+
+    pub(crate) fn send_body_frame(&self, chunk: &[u8]) -> Result<Wait<()>> {
+        self.connection.send_content_frames(
+            self.id,
+            vec![(OutboundFrame::Body(self.id, chunk.into()), None)],
+        )
+    }
+
+    pub(crate) fn end_streaming_publish(&self) {
+        self.status.set_streaming(false);
+        self.connection.end_streaming(self.id);
+    }
+
+    /// The server got a partial message on this channel, nothing else can be sent on it
+    pub(crate) fn poison(&self) {
+        if let Err(error) = self.set_error() {
+            error!("failed to mark channel {} as errored: {}", self.id, error);
+        }
+    }
+
+    pub(crate) fn send_queue_saturated(&self, default_capacity: usize) -> bool {
+        self.connection.send_queue_saturated(default_capacity)
+    }
+
+    /// Wait until everything queued on the connection so far has been serialized, not only the
+    /// frames of this channel
+    pub fn flush(&self) -> Confirmation<()> {
+        self.connection.drain()
+    }
+
+    /// Whether publishing would currently fail with `Error::SendQueueFull`
+    pub fn send_queue_full(&self) -> bool {
+        self.connection.send_queue_full()
+    }
+
+    /// Get notified once some space got freed in the outgoing frames queue
+    pub fn subscribe_send_queue(&self, task: Box<dyn NotifyReady + Send>) {
+        self.connection.subscribe_send_queue(task);
+    }
+
+    /// Register a handler which will be called for each message returned by the server, for
+    /// example when publishing a mandatory message which cannot be routed
+    pub fn on_return<R: Fn(BasicReturnMessage) + Send + Sync + 'static>(&self, handler: Box<R>) {
+        self.returned_messages.set_handler(Arc::new(handler));
+    }
+
+    /// The number of deliveries received on this channel which weren't acked, nacked or
+    /// rejected yet, not counting the ones of `no_ack` consumers or gets
+    pub fn unacked_count(&self) -> usize {
+        self.unacked_deliveries.count()
+    }
+
+    /// Register a handler which will be called if the server closes this channel, with the
+    /// reason why
+    ///
+    /// Only this channel gets closed, the connection and its other channels keep working.
+    pub fn on_error<E: Fn(CloseReason) + Send + 'static>(&self, handler: Box<E>) {
+        self.error_handler.set_handler(handler);
+    }
+
+    /// Take the messages returned by the server since the last call, whether a handler was
+    /// registered with `on_return` or not
+    pub fn drain_returned_messages(&self) -> Vec<BasicReturnMessage> {
+        self.returned_messages.drain()
+    }
+
+    /// Declare an exchange of the given kind
+    ///
+    /// With `options.nowait`, the server doesn't reply and the returned confirmation resolves
+    /// as soon as the method is queued. A failure then only shows up later, as the server
+    /// closing the channel. The same goes for the other methods accepting `nowait`.
     pub fn exchange_declare(
         &self,
         exchange: &str,
@@ -109,27 +426,208 @@ impl Channel {
         options: ExchangeDeclareOptions,
         arguments: FieldTable,
     ) -> Confirmation<()> {
-        self.do_exchange_declare(exchange, kind.kind(), options, arguments)
+        self.do_exchange_declare(exchange, kind.as_str(), options, arguments)
     }
 
-    pub fn wait_for_confirms(&self) -> Confirmation<Vec<BasicReturnMessage>> {
-        if let Some(wait) = self.acknowledgements.get_last_pending() {
-            trace!("Waiting for pending confirms");
-            let returned_messages = self.returned_messages.clone();
-            Confirmation::new(wait).map(Box::new(move |_| returned_messages.drain()))
-        } else {
-            trace!("No confirms to wait for");
-            let (wait, wait_handle) = Wait::new();
-            wait_handle.finish(Vec::default());
-            Confirmation::new(wait)
+    /// Enable publisher confirms on this channel, see `basic_publish`
+    ///
+    /// Fails with `Error::ChannelModeConflict` if the channel is in transactional mode.
+    pub fn confirm_select(&self, options: ConfirmSelectOptions) -> Confirmation<()> {
+        if self.status.transactional() {
+            return Confirmation::new_error(Error::ChannelModeConflict(self.id));
         }
+        self.do_confirm_select(options)
     }
 
-    #[cfg(test)]
+    /// Put this channel in transactional mode, the publishes and acknowledgements then being
+    /// applied on `tx_commit`
+    ///
+    /// Fails with `Error::ChannelModeConflict` if publisher confirms are enabled.
+    pub fn tx_select(&self) -> Confirmation<()> {
+        if self.status.confirm() {
+            return Confirmation::new_error(Error::ChannelModeConflict(self.id));
+        }
+        self.do_tx_select()
+    }
+
+    /// Commit the publishes and acknowledgements made since the last commit or rollback
+    ///
+    /// It is sent after the messages published before it on this channel. Fails with
+    /// `Error::NotTransactional` unless `tx_select` succeeded first, or with
+    /// `Error::StreamingPublishInProgress` while a message is being streamed.
+    pub fn tx_commit(&self) -> Confirmation<()> {
+        if !self.status.transactional() {
+            return Confirmation::new_error(Error::NotTransactional(self.id));
+        }
+        // It is sent after the queued publishes, which can't be split by a streamed message
+        if self.status.streaming() {
+            return Confirmation::new_error(Error::StreamingPublishInProgress(self.id));
+        }
+        self.do_tx_commit()
+    }
+
+    /// Discard the publishes and acknowledgements made since the last commit or rollback
+    ///
+    /// Like `tx_commit`, it is sent after the messages published before it on this channel.
+    pub fn tx_rollback(&self) -> Confirmation<()> {
+        if !self.status.transactional() {
+            return Confirmation::new_error(Error::NotTransactional(self.id));
+        }
+        if self.status.streaming() {
+            return Confirmation::new_error(Error::StreamingPublishInProgress(self.id));
+        }
+        self.do_tx_rollback()
+    }
+
+    /// Check that an exchange exists without creating it
+    ///
+    /// If it doesn't, the server closes the channel, see `Error::not_found`
+    pub fn exchange_declare_passive(&self, exchange: &str) -> Confirmation<()> {
+        self.exchange_declare(
+            exchange,
+            ExchangeKind::default(),
+            ExchangeDeclareOptions {
+                passive: true,
+                ..ExchangeDeclareOptions::default()
+            },
+            FieldTable::default(),
+        )
+    }
+
+    /// Check that a queue exists without creating it, getting its messages and consumers counts
+    ///
+    /// If it doesn't, the server closes the channel, see `Error::not_found`
+    pub fn queue_declare_passive(&self, queue: &str) -> Confirmation<Queue> {
+        self.queue_declare(
+            queue,
+            QueueDeclareOptions {
+                passive: true,
+                ..QueueDeclareOptions::default()
+            },
+            FieldTable::default(),
+        )
+    }
+
+    /// Declare a queue
+    ///
+    /// A quorum queue, as requested by the `x-queue-type` argument, can be neither exclusive,
+    /// auto-deleted nor lazy: such a declaration fails with `Error::InvalidQueueOptions` without
+    /// anything being sent, instead of getting the channel closed by the server.
+    pub fn queue_declare(
+        &self,
+        queue: &str,
+        options: QueueDeclareOptions,
+        arguments: FieldTable,
+    ) -> Confirmation<Queue> {
+        let queue_type = QueueType::from_arguments(&arguments);
+        if queue_type == Some(QueueType::Quorum) {
+            let invalid = |reason| {
+                Confirmation::new_error(Error::InvalidQueueOptions(format!(
+                    "quorum queue {} cannot be {}",
+                    queue, reason
+                )))
+            };
+            if options.exclusive {
+                return invalid("exclusive");
+            }
+            if options.auto_delete {
+                return invalid("auto-delete");
+            }
+            if QueueMode::from_arguments(&arguments) == Some(QueueMode::Lazy) {
+                return invalid("lazy");
+            }
+        }
+        self.do_queue_declare(queue, options, arguments, queue_type)
+    }
+
+    /// Declare a quorum queue, the replicated queue type of RabbitMQ
+    ///
+    /// `x-queue-type` is set to `quorum` in `arguments`, see `queue_declare` for the options
+    /// it is incompatible with. Quorum queues are always durable, whatever `options.durable`.
+    pub fn queue_declare_quorum(
+        &self,
+        queue: &str,
+        options: QueueDeclareOptions,
+        mut arguments: FieldTable,
+    ) -> Confirmation<Queue> {
+        arguments.insert(
+            "x-queue-type".into(),
+            AMQPValue::LongString(QueueType::Quorum.as_str().into()),
+        );
+        self.queue_declare(
+            queue,
+            QueueDeclareOptions {
+                durable: true,
+                ..options
+            },
+            arguments,
+        )
+    }
+
+    /// Declare an exclusive, auto-delete queue named by the server, typically to receive replies
+    ///
+    /// The queue gets deleted once the `TemporaryQueue` is dropped, without waiting for the server,
+    /// unless the channel is already closed.
+    pub fn declare_temporary_queue(&self) -> Confirmation<TemporaryQueue, Queue> {
+        let channel = self.clone();
+        self.queue_declare(
+            "",
+            QueueDeclareOptions {
+                exclusive: true,
+                auto_delete: true,
+                ..QueueDeclareOptions::default()
+            },
+            FieldTable::default(),
+        )
+        .map(Box::new(move |queue| {
+            TemporaryQueue::new(queue, channel.clone())
+        }))
+    }
+
+    /// Bind a queue to a topic exchange
+    ///
+    /// `pattern` is checked with `types::validate_topic_pattern` first, a malformed one failing
+    /// with `Error::InvalidRoutingKey` without anything being sent.
+    pub fn queue_bind_topic(
+        &self,
+        queue: &str,
+        exchange: &str,
+        pattern: &str,
+        options: QueueBindOptions,
+        arguments: FieldTable,
+    ) -> Confirmation<()> {
+        if let Err(error) = validate_topic_pattern(pattern) {
+            return Confirmation::new_error(error);
+        }
+        self.queue_bind(queue, exchange, pattern, options, arguments)
+    }
+
+    /// Wait until every publish has been acked or nacked by the server, in confirm mode
+    ///
+    /// Resolves with the publishes which were nacked or returned since the last call. Fails if
+    /// the channel gets closed before.
+    pub fn wait_for_confirms(&self) -> Confirmation<UnconfirmedMessages> {
+        trace!("Waiting for pending confirms");
+        let acknowledgements = self.acknowledgements.clone();
+        let returned_messages = self.returned_messages.clone();
+        Confirmation::new(self.acknowledgements.wait_settled()).map(Box::new(move |_| {
+            UnconfirmedMessages {
+                nacked: acknowledgements.drain_nacked(),
+                returned: returned_messages.drain(),
+            }
+        }))
+    }
+
+    #[cfg(any(test, feature = "bench"))]
     pub(crate) fn register_queue(&self, queue: QueueState) {
         self.queues.register(queue);
     }
 
+    #[cfg(test)]
+    pub(crate) fn unacked_delivery_tags(&self) -> Vec<DeliveryTag> {
+        self.unacked_deliveries.delivery_tags()
+    }
+
     pub(crate) fn send_method_frame(
         &self,
         method: AMQPClass,
@@ -142,34 +640,59 @@ impl Channel {
         )
     }
 
-    fn send_method_frame_with_body(
+    /// Send a method once the messages published before it on this channel are sent
+    fn send_method_frame_after_content(
         &self,
         method: AMQPClass,
-        payload: Vec<u8>,
-        properties: BasicProperties,
+        expected_reply: Option<ExpectedReply>,
     ) -> Result<Wait<()>> {
+        self.connection.send_frame_after_content(
+            self.id,
+            AMQPFrame::Method(self.id, method),
+            expected_reply,
+        )
+    }
+
+    fn content_frames(
+        &self,
+        method: AMQPClass,
+        body_size: u64,
+        properties: BasicProperties,
+    ) -> (OutboundFrame, Option<OutboundFrame>) {
         let class_id = method.get_amqp_class_id();
         let header = AMQPContentHeader {
             class_id,
             weight: 0,
-            body_size: payload.len() as u64,
+            body_size,
             properties,
         };
-        let frame_max = self.connection.configuration().frame_max();
-        let mut frames = vec![(
-            AMQPFrame::Method(self.id, method),
-            Some(AMQPFrame::Header(self.id, class_id, Box::new(header))),
-        )];
+        (
+            AMQPFrame::Method(self.id, method).into(),
+            Some(AMQPFrame::Header(self.id, class_id, Box::new(header)).into()),
+        )
+    }
+
+    fn body_frame_max(&self) -> usize {
+        self.connection.configuration().max_body_chunk()
+    }
 
-        // a content body frame 8 bytes of overhead
+    fn send_method_frame_with_body(
+        &self,
+        method: AMQPClass,
+        payload: Payload,
+        properties: BasicProperties,
+        wait_for_room: bool,
+    ) -> Result<Wait<()>> {
+        let mut frames = vec![self.content_frames(method, payload.len() as u64, properties)];
+        // The body frames are views on the payload, which isn't copied
         frames.extend(
             payload
-                .as_slice()
-                .chunks(frame_max as usize - 8)
-                .map(|chunk| (AMQPFrame::Body(self.id, chunk.into()), None)),
+                .chunks(self.body_frame_max())
+                .map(|chunk| (OutboundFrame::Body(self.id, chunk), None)),
         );
 
-        self.connection.send_frames(self.id, frames)
+        let (publisher_confirm, send_wait) = self.send_publish_frames(frames, wait_for_room)?;
+        Ok(publisher_confirm.unwrap_or(send_wait))
     }
 
     pub(crate) fn send_frame(
@@ -190,35 +713,60 @@ impl Channel {
         if let ChannelState::WillReceiveContent(queue_name, request_id_or_consumer_tag) =
             self.status.state()
         {
-            if size > 0 {
-                self.status.set_state(ChannelState::ReceivingContent(
-                    queue_name.clone(),
-                    request_id_or_consumer_tag.clone(),
-                    size as usize,
-                ));
-            } else {
-                self.status.set_state(ChannelState::Connected);
+            let max_message_size = self.connection.configuration().max_message_size();
+            if max_message_size != 0 && size > max_message_size as u64 {
+                error!(
+                    "message of {} bytes larger than max_message_size ({}) on channel {}",
+                    size, max_message_size, self.id
+                );
+                if let Some(queue_name) = queue_name.as_ref() {
+                    self.queues.fail_delivery(
+                        queue_name.as_str(),
+                        request_id_or_consumer_tag.as_ref().map(ShortString::as_str),
+                        Error::MessageTooLarge(size, max_message_size),
+                    )?;
+                }
+                // Only this channel is closed, its frames being discarded until the server
+                // confirms it, the body frames to come included
+                return self
+                    .do_channel_close(
+                        AMQPSoftError::CONTENTTOOLARGE.get_id(),
+                        "message larger than max_message_size",
+                        60,
+                        0,
+                    )
+                    .into_error();
             }
-            if let Some(queue_name) = queue_name {
+            if let Some(queue_name) = queue_name.as_ref() {
                 self.queues.handle_content_header_frame(
                     queue_name.as_str(),
-                    request_id_or_consumer_tag,
+                    request_id_or_consumer_tag.as_ref().map(ShortString::as_str),
                     size,
                     properties,
                 )?;
             } else {
                 self.returned_messages.set_delivery_properties(properties);
                 if size == 0 {
-                    self.returned_messages.new_delivery_complete();
+                    self.returned_messages
+                        .new_delivery_complete(self.status.confirm());
                 }
             }
+            if size > 0 {
+                self.status.set_state(ChannelState::ReceivingContent(
+                    queue_name,
+                    request_id_or_consumer_tag,
+                    size as usize,
+                ));
+            } else {
+                self.status.set_state(ChannelState::Connected);
+            }
             Ok(())
         } else {
             self.set_error()
         }
     }
 
-    pub(crate) fn handle_body_frame(&self, payload: Vec<u8>) -> Result<()> {
+    pub(crate) fn handle_body_frame(&self, payload: Payload) -> Result<()> {
         let payload_size = payload.len();
 
         if let ChannelState::ReceivingContent(
@@ -231,7 +779,7 @@ impl Channel {
                 if let Some(queue_name) = queue_name.as_ref() {
                     self.queues.handle_body_frame(
                         queue_name.as_str(),
-                        request_id_or_consumer_tag.clone(),
+                        request_id_or_consumer_tag.as_ref().map(ShortString::as_str),
                         remaining_size,
                         payload_size,
                         payload,
@@ -239,7 +787,8 @@ impl Channel {
                 } else {
                     self.returned_messages.receive_delivery_content(payload);
                     if remaining_size == payload_size {
-                        self.returned_messages.new_delivery_complete();
+                        self.returned_messages
+                            .new_delivery_complete(self.status.confirm());
                     }
                 }
                 if remaining_size == payload_size {
@@ -261,10 +810,103 @@ impl Channel {
         }
     }
 
-    fn before_basic_publish(&self) {
-        if self.status.confirm() {
-            let delivery_tag = self.delivery_tag.next();
-            self.acknowledgements.register_pending(delivery_tag);
+    /// Whether the blocking publish methods wait for room in the send queue, as the send queue
+    /// full policy says
+    ///
+    /// `try_basic_publish` and `start_streaming_publish` never do, failing right away as the
+    /// futures wrappers rely on it.
+    fn waits_for_send_queue(&self) -> bool {
+        self.connection.configuration().send_queue_full_policy() == SendQueueFullPolicy::Block
+    }
+
+    /// Park the current thread as long as `full` returns true and the channel is connected
+    ///
+    /// There is no timeout: the thread gets unparked by the send queue subscription whenever
+    /// frames leave the queue, or when the channel stops being connected (see `set_state`).
+    pub(crate) fn park_while_send_queue_full<F: Fn() -> bool>(&self, full: F) {
+        while self.status.is_connected() && full() {
+            self.subscribe_send_queue(Box::new(ThreadNotifier(thread::current())));
+            // Check again in case the queue got drained or the channel closed before we
+            // subscribed
+            if self.status.is_connected() && full() {
+                thread::park();
+            }
+        }
+    }
+
+    fn publish_rejection(&self, wait_for_room: bool) -> Option<Box<dyn Fn() -> Error>> {
+        let state = self.status.state();
+        if !self.status.is_connected() {
+            let id = self.id;
+            match state {
+                ChannelState::Closing | ChannelState::Closed => {
+                    Some(Box::new(move || Error::ChannelClosed(id)))
+                }
+                _ => Some(Box::new(|| Error::NotConnected)),
+            }
+        } else if self.status.streaming() {
+            // Another message is being streamed on this channel
+            let id = self.id;
+            Some(Box::new(move || Error::StreamingPublishInProgress(id)))
+        } else if !wait_for_room && self.connection.send_queue_full() {
+            Some(Box::new(|| Error::SendQueueFull))
+        } else {
+            None
+        }
+    }
+
+    /// Queue the frames of a publish, along with the wait for its confirm in confirm mode
+    ///
+    /// The server numbers the publishes in the order it receives them, so the delivery tag is
+    /// taken and the frames queued atomically, the tag being given back if they can't be queued.
+    fn send_publish_frames(
+        &self,
+        frames: PublishFrames,
+        wait_for_room: bool,
+    ) -> Result<(Option<Wait<()>>, Wait<()>)> {
+        let _publish_lock = self.publish_lock.lock();
+        if !self.status.confirm() {
+            return Ok((None, self.queue_publish_frames(frames, wait_for_room)?));
+        }
+        // Register the delivery tag before queuing the frames so that we cannot miss the ack
+        let delivery_tag = self.delivery_tag.next();
+        let publisher_confirm = self.acknowledgements.register_pending(delivery_tag);
+        match self.queue_publish_frames(frames, wait_for_room) {
+            Ok(send_wait) => Ok((Some(publisher_confirm), send_wait)),
+            Err(error @ Error::SendQueueFull)
+            | Err(error @ Error::NotConnected)
+            | Err(error @ Error::ChannelClosed(_)) => {
+                // Nothing was queued
+                self.acknowledgements.cancel_pending(delivery_tag);
+                self.delivery_tag.rollback(delivery_tag);
+                Err(error)
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Queue the frames of a publish, failing with `Error::SendQueueFull` if the send queue is
+    /// full unless `wait_for_room`, in which case this blocks until they fit in it
+    ///
+    /// Other publishers may fill the send queue again between the wakeup and the next attempt,
+    /// hence the loop.
+    fn queue_publish_frames(
+        &self,
+        mut frames: PublishFrames,
+        wait_for_room: bool,
+    ) -> Result<Wait<()>> {
+        loop {
+            frames = match self.connection.try_send_frames(self.id, frames)? {
+                Ok(send_wait) => return Ok(send_wait),
+                Err(frames) => frames,
+            };
+            if !wait_for_room {
+                return Err(Error::SendQueueFull);
+            }
+            if !self.status.is_connected() {
+                return Err(self.not_connected_error());
+            }
+            self.park_while_send_queue_full(|| self.connection.send_queue_full());
         }
     }
 
@@ -295,6 +937,32 @@ impl Channel {
         Ok(())
     }
 
+    /// Close the connection ahead of all the frames waiting to be sent, after a protocol error
+    pub(crate) fn connection_close_now(
+        &self,
+        reply_code: ShortUInt,
+        reply_text: &str,
+    ) -> Result<()> {
+        let method = AMQPClass::Connection(protocol::connection::AMQPMethod::Close(
+            protocol::connection::Close {
+                reply_code,
+                reply_text: reply_text.into(),
+                class_id: 0,
+                method_id: 0,
+            },
+        ));
+        let (_, wait_handle) = Wait::new();
+        self.send_frame(
+            Priority::CRITICAL,
+            AMQPFrame::Method(self.id, method),
+            Some((
+                Reply::ConnectionCloseOk(wait_handle.clone()),
+                Box::new(wait_handle),
+            )),
+        )?;
+        self.on_connection_close_sent()
+    }
+
     fn on_connection_close_sent(&self) -> Result<()> {
         self.connection.set_closing();
         Ok(())
@@ -314,10 +982,12 @@ impl Channel {
     }
 
     fn on_basic_recover_async_sent(&self) -> Result<()> {
-        self.queues.drop_prefetched_messages()
+        self.unacked_deliveries.clear();
+        self.queues.recover_consumers()
     }
 
     fn on_basic_ack_sent(&self, multiple: bool, delivery_tag: DeliveryTag) -> Result<()> {
+        self.unacked_deliveries.settle(delivery_tag, multiple);
         if multiple && delivery_tag == 0 {
             self.queues.drop_prefetched_messages()
         } else {
@@ -326,6 +996,7 @@ impl Channel {
     }
 
     fn on_basic_nack_sent(&self, multiple: bool, delivery_tag: DeliveryTag) -> Result<()> {
+        self.unacked_deliveries.settle(delivery_tag, multiple);
         if multiple && delivery_tag == 0 {
             self.queues.drop_prefetched_messages()
         } else {
@@ -333,6 +1004,11 @@ impl Channel {
         }
     }
 
+    fn on_basic_reject_sent(&self, delivery_tag: DeliveryTag) -> Result<()> {
+        self.unacked_deliveries.settle(delivery_tag, false);
+        Ok(())
+    }
+
     fn tune_connection_configuration(&self, channel_max: u16, frame_max: u32, heartbeat: u16) {
         // If we disable the heartbeat (0) but the server don't, follow him and enable it too
         // If both us and the server want heartbeat enabled, pick the lowest value.
@@ -378,32 +1054,26 @@ impl Channel {
         let state = self.connection.status().state();
         if let ConnectionState::SentProtocolHeader(wait_handle, credentials, mut options) = state {
             let mechanism = options.mechanism.to_string();
-            let locale = options.locale.clone();
+            let locale = negotiate_locale(&options.locales, method.locales.as_str());
+            self.connection.configuration().set_locale(locale.clone());
 
             if !method.mechanisms.split_whitespace().any(|m| m == mechanism) {
                 error!("unsupported mechanism: {}", mechanism);
             }
-            if !method.locales.split_whitespace().any(|l| l == locale) {
-                error!("unsupported locale: {}", mechanism);
-            }
 
-            if !options.client_properties.contains_key("product")
-                || !options.client_properties.contains_key("version")
-            {
-                options.client_properties.insert(
-                    "product".into(),
-                    AMQPValue::LongString(env!("CARGO_PKG_NAME").into()),
-                );
-                options.client_properties.insert(
-                    "version".into(),
-                    AMQPValue::LongString(env!("CARGO_PKG_VERSION").into()),
-                );
+            // Each of them can be overridden, to show the application in the server instead
+            for (key, value) in &[
+                ("product", env!("CARGO_PKG_NAME")),
+                ("version", env!("CARGO_PKG_VERSION")),
+                ("platform", "rust"),
+            ] {
+                if !options.client_properties.contains_key(key) {
+                    options
+                        .client_properties
+                        .insert((*key).into(), AMQPValue::LongString((*value).into()));
+                }
             }
 
-            options
-                .client_properties
-                .insert("platform".into(), AMQPValue::LongString("rust".into()));
-
             let mut capabilities = FieldTable::default();
             capabilities.insert("publisher_confirms".into(), AMQPValue::Boolean(true));
             capabilities.insert(
@@ -500,21 +1170,34 @@ impl Channel {
         } else {
             info!("Connection closed on channel {}: {:?}", self.id, method);
         }
+        let reason = CloseReason {
+            reply_code: method.reply_code,
+            reply_text: method.reply_text.clone(),
+            class_id: method.class_id,
+            method_id: method.method_id,
+        };
+        self.connection
+            .status()
+            .set_close_reason(Some(reason.clone()));
         let state = self.connection.status().state();
         self.connection.set_closing();
-        self.connection.drop_pending_frames();
+        self.connection
+            .drop_pending_frames(&|| Error::ConnectionClosedByServer(reason.clone()));
         match state {
-            ConnectionState::SentProtocolHeader(wait_handle, ..) => {
-                wait_handle.error(Error::ConnectionRefused)
-            }
-            ConnectionState::SentStartOk(wait_handle, _) => {
-                wait_handle.error(Error::ConnectionRefused)
+            ConnectionState::SentProtocolHeader(wait_handle, ..)
+            | ConnectionState::SentStartOk(wait_handle, _)
+            | ConnectionState::SentOpen(wait_handle) => {
+                // We advertise authentication_failure_close, bad credentials being reported
+                // with ACCESS_REFUSED instead of the server dropping the socket
+                if method.reply_code == AMQPSoftError::ACCESSREFUSED.get_id() {
+                    wait_handle.error(Error::AuthenticationFailed(method.reply_text.to_string()))
+                } else {
+                    wait_handle.error(Error::ConnectionRefused)
+                }
             }
-            ConnectionState::SentOpen(wait_handle) => wait_handle.error(Error::ConnectionRefused),
             _ => {}
         }
-        self.connection_close_ok().into_error()?;
-        Ok(())
+        self.connection_close_ok().into_error()
     }
 
     fn on_connection_blocked_received(&self, _method: protocol::connection::Blocked) -> Result<()> {
@@ -545,6 +1228,7 @@ impl Channel {
 
     fn on_channel_flow_received(&self, method: protocol::channel::Flow) -> Result<()> {
         self.status.set_send_flow(method.active);
+        self.connection.set_send_flow(self.id, method.active);
         self.channel_flow_ok(ChannelFlowOkOptions {
             active: method.active,
         })
@@ -570,7 +1254,22 @@ impl Channel {
         } else {
             info!("Channel {} closed: {:?}", self.id, method);
         }
-        self.channel_close_ok().into_error()
+        let reason = CloseReason {
+            reply_code: method.reply_code,
+            reply_text: method.reply_text,
+            class_id: method.class_id,
+            method_id: method.method_id,
+        };
+        self.status.set_close_reason(reason.clone());
+        // Nothing will be answered on this channel anymore, fail what's pending
+        self.set_state(ChannelState::Closing);
+        let id = self.id;
+        let error = || Error::ChannelClosedByServer(id, reason.clone());
+        self.connection.fail_pending_operations(id, &error);
+        self.acknowledgements.fail_all_pending(&error);
+        let res = self.channel_close_ok().into_error();
+        self.error_handler.on_error(reason);
+        res
     }
 
     fn on_channel_close_ok_received(&self) -> Result<()> {
@@ -601,8 +1300,10 @@ impl Channel {
         &self,
         method: protocol::queue::DeclareOk,
         wait_handle: WaitHandle<Queue>,
+        queue_type: Option<QueueType>,
     ) -> Result<()> {
-        let queue = Queue::new(method.queue, method.message_count, method.consumer_count);
+        let mut queue = Queue::new(method.queue, method.message_count, method.consumer_count);
+        queue.set_queue_type(queue_type);
         wait_handle.finish(queue.clone());
         self.queues.register(queue.into());
         Ok(())
@@ -613,7 +1314,11 @@ impl Channel {
         method: protocol::basic::GetOk,
         wait_handle: WaitHandle<Option<BasicGetMessage>>,
         queue: ShortString,
+        no_ack: Boolean,
     ) -> Result<()> {
+        if !no_ack {
+            self.unacked_deliveries.register(method.delivery_tag);
+        }
         self.queues.start_basic_get_delivery(
             queue.as_str(),
             BasicGetMessage::new(
@@ -632,7 +1337,7 @@ impl Channel {
 
     fn on_basic_get_empty_received(&self, _: protocol::basic::GetEmpty) -> Result<()> {
         match self.connection.next_expected_reply(self.id) {
-            Some(Reply::BasicGetOk(wait_handle, _)) => {
+            Some(Reply::BasicGetOk(wait_handle, _, _)) => {
                 wait_handle.finish(None);
                 Ok(())
             }
@@ -649,7 +1354,12 @@ impl Channel {
         method: protocol::basic::ConsumeOk,
         wait_handle: WaitHandle<Consumer>,
         queue: ShortString,
+        no_ack: Boolean,
     ) -> Result<()> {
+        if no_ack {
+            self.unacked_deliveries
+                .register_no_ack_consumer(method.consumer_tag.clone());
+        }
         let consumer = Consumer::new(method.consumer_tag.clone(), self.executor.clone());
         self.queues
             .register_consumer(queue.as_str(), method.consumer_tag, consumer.clone());
@@ -667,6 +1377,8 @@ impl Channel {
                 method.redelivered,
             ),
         ) {
+            self.unacked_deliveries
+                .register_consumer_delivery(method.consumer_tag.as_str(), method.delivery_tag);
             self.status.set_state(ChannelState::WillReceiveContent(
                 Some(queue_name),
                 Some(method.consumer_tag),
@@ -676,8 +1388,10 @@ impl Channel {
     }
 
     fn on_basic_cancel_received(&self, method: protocol::basic::Cancel) -> Result<()> {
+        self.unacked_deliveries
+            .deregister_consumer(method.consumer_tag.as_str());
         self.queues
-            .deregister_consumer(method.consumer_tag.as_str())
+            .deregister_consumer(method.consumer_tag.as_str(), CancelledBy::Server)
             .and(if !method.nowait {
                 self.basic_cancel_ok(method.consumer_tag.as_str())
                     .into_error()
@@ -687,8 +1401,10 @@ impl Channel {
     }
 
     fn on_basic_cancel_ok_received(&self, method: protocol::basic::CancelOk) -> Result<()> {
+        self.unacked_deliveries
+            .deregister_consumer(method.consumer_tag.as_str());
         self.queues
-            .deregister_consumer(method.consumer_tag.as_str())
+            .deregister_consumer(method.consumer_tag.as_str(), CancelledBy::Client)
     }
 
     fn on_basic_ack_received(&self, method: protocol::basic::Ack) -> Result<()> {
@@ -766,8 +1482,23 @@ impl Channel {
         Ok(())
     }
 
+    fn on_basic_qos_ok_received(&self, prefetch_count: ShortUInt, global: Boolean) -> Result<()> {
+        self.status.set_qos(Qos {
+            prefetch_count,
+            global,
+        });
+        Ok(())
+    }
+
     fn on_basic_recover_ok_received(&self) -> Result<()> {
-        self.queues.drop_prefetched_messages()
+        // The server delivers them again
+        self.unacked_deliveries.clear();
+        self.queues.recover_consumers()
+    }
+
+    fn on_tx_select_ok_received(&self) -> Result<()> {
+        self.status.set_transactional();
+        Ok(())
     }
 
     fn on_confirm_select_ok_received(&self) -> Result<()> {
@@ -780,4 +1511,2183 @@ impl Channel {
     }
 }
 
+/// Pick the first of our locales advertised by the server, falling back to en_US
+fn negotiate_locale(preferred: &[String], server_locales: &str) -> String {
+    preferred
+        .iter()
+        .find(|locale| {
+            server_locales
+                .split_whitespace()
+                .any(|l| l == locale.as_str())
+        })
+        .cloned()
+        .unwrap_or_else(|| {
+            debug!(
+                "none of {:?} supported by the server ({}), using {}",
+                preferred, server_locales, DEFAULT_LOCALE
+            );
+            DEFAULT_LOCALE.into()
+        })
+}
+
 include!(concat!(env!("OUT_DIR"), "/channel.rs"));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        channel_status::ChannelState,
+        connection::{tests::delivery_frames, Connection},
+        connection_status::ConnectionState,
+        types::ShortString,
+        BasicProperties, Channel, ConnectionProperties, Error,
+    };
+    use amq_protocol::{
+        frame::{gen_frame, parse_frame, AMQPContentHeader, AMQPFrame},
+        protocol::{basic, AMQPClass},
+    };
+
+    #[test]
+    fn blocking_publishers_racing_for_the_send_queue() {
+        let _ = env_logger::try_init();
+
+        use crate::options::BasicPublishOptions;
+
+        const PUBLISHERS: usize = 4;
+        const PUBLISHES: usize = 100;
+
+        let (conn, _) = Connection::connected_channel();
+        conn.configuration().set_frame_max(8192);
+        conn.configuration().set_send_queue_capacity(1);
+        conn.configuration()
+            .set_send_queue_full_policy(SendQueueFullPolicy::Block);
+        let publishers = (0..PUBLISHERS)
+            .map(|_| {
+                let channel = conn.create_open_channel().unwrap();
+                thread::spawn(move || {
+                    (0..PUBLISHES)
+                        .filter_map(|_| {
+                            channel
+                                .basic_publish(
+                                    "",
+                                    "queue",
+                                    BasicPublishOptions::default(),
+                                    b"data".to_vec(),
+                                    BasicProperties::default(),
+                                )
+                                .try_wait()
+                                .and_then(Result::err)
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+        // Play the io loop until everything got sent
+        let mut published = 0;
+        while published < PUBLISHERS * PUBLISHES {
+            match conn.next_frame() {
+                Some((send_id, frame)) => {
+                    if let AMQPFrame::Method(_, AMQPClass::Basic(basic::AMQPMethod::Publish(_))) =
+                        frame
+                    {
+                        published += 1;
+                    }
+                    conn.mark_sent(send_id);
+                }
+                None => thread::yield_now(),
+            }
+        }
+        for publisher in publishers {
+            let errors = publisher.join().unwrap();
+            assert!(errors.is_empty(), "unexpected errors: {:?}", errors);
+        }
+    }
+
+    #[test]
+    fn delivery_tag_given_back_when_send_queue_full() {
+        let (conn, channel) = Connection::connected_channel();
+        conn.configuration().set_frame_max(8192);
+        conn.configuration().set_send_queue_capacity(1);
+        channel.status().set_confirm();
+        let publish_frames = || {
+            let method = AMQPClass::Basic(protocol::basic::AMQPMethod::Publish(
+                protocol::basic::Publish {
+                    exchange: "".into(),
+                    routing_key: "queue".into(),
+                    mandatory: false,
+                    immediate: false,
+                },
+            ));
+            vec![channel.content_frames(method, 0, BasicProperties::default())]
+        };
+        let (first, _) = channel
+            .send_publish_frames(publish_frames(), false)
+            .unwrap();
+        // The publish_rejection check passed but the send queue filled up in the meantime
+        match channel.send_publish_frames(publish_frames(), false) {
+            Err(Error::SendQueueFull) => {}
+            res => panic!("unexpected publish result: {:?}", res.map(|_| ())),
+        }
+        while conn.next_frame().is_some() {}
+        let (second, _) = channel
+            .send_publish_frames(publish_frames(), false)
+            .unwrap();
+        let (first, second) = (first.unwrap(), second.unwrap());
+        for delivery_tag in 1..=2 {
+            conn.handle_frame(AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Basic(protocol::basic::AMQPMethod::Ack(protocol::basic::Ack {
+                    delivery_tag,
+                    multiple: false,
+                })),
+            ))
+            .unwrap();
+        }
+        assert!(first.try_wait().unwrap().is_ok());
+        assert!(second.try_wait().unwrap().is_ok());
+    }
+
+    #[test]
+    fn delivery_acknowledgements() {
+        let _ = env_logger::try_init();
+
+        use crate::message::Delivery;
+        use crate::options::{BasicAckOptions, BasicNackOptions, BasicRejectOptions};
+
+        let (conn, channel) = Connection::connected_channel();
+        let delivery = Delivery::new(42, "".into(), "queue".into(), false);
+
+        let _ = delivery.ack(&channel, BasicAckOptions { multiple: true });
+        let _ = delivery.nack(
+            &channel,
+            BasicNackOptions {
+                multiple: false,
+                requeue: true,
+            },
+        );
+        let _ = delivery.reject(&channel, BasicRejectOptions { requeue: false });
+        match (conn.next_frame(), conn.next_frame(), conn.next_frame()) {
+            (
+                Some((
+                    _,
+                    AMQPFrame::Method(
+                        _,
+                        AMQPClass::Basic(basic::AMQPMethod::Ack(basic::Ack {
+                            delivery_tag: 42,
+                            multiple: true,
+                        })),
+                    ),
+                )),
+                Some((
+                    _,
+                    AMQPFrame::Method(
+                        _,
+                        AMQPClass::Basic(basic::AMQPMethod::Nack(basic::Nack {
+                            delivery_tag: 42,
+                            multiple: false,
+                            requeue: true,
+                        })),
+                    ),
+                )),
+                Some((
+                    _,
+                    AMQPFrame::Method(
+                        _,
+                        AMQPClass::Basic(basic::AMQPMethod::Reject(basic::Reject {
+                            delivery_tag: 42,
+                            requeue: false,
+                        })),
+                    ),
+                )),
+            ) => {}
+            frames => panic!("unexpected frames: {:?}", frames),
+        }
+    }
+
+    #[test]
+    fn basic_publish_frame_max() {
+        let _ = env_logger::try_init();
+
+        use crate::options::BasicPublishOptions;
+
+        const PAYLOAD_SIZE: usize = 1024 * 1024;
+
+        let (conn, channel) = Connection::connected_channel();
+        conn.configuration().set_frame_max(4096);
+        let _ = channel.basic_publish(
+            "",
+            "queue",
+            BasicPublishOptions::default(),
+            vec![42; PAYLOAD_SIZE],
+            BasicProperties::default(),
+        );
+        match conn.next_frame() {
+            Some((_, AMQPFrame::Method(_, AMQPClass::Basic(basic::AMQPMethod::Publish(_))))) => {}
+            frame => panic!("unexpected frame: {:?}", frame),
+        }
+        match conn.next_frame() {
+            Some((_, AMQPFrame::Header(_, _, header))) => {
+                assert_eq!(header.body_size, PAYLOAD_SIZE as u64)
+            }
+            frame => panic!("unexpected frame: {:?}", frame),
+        }
+        let mut sizes = Vec::new();
+        while let Some((_, frame)) = conn.next_frame() {
+            match frame {
+                AMQPFrame::Body(_, payload) => sizes.push(payload.len()),
+                frame => panic!("unexpected frame: {:?}", frame),
+            }
+        }
+        // 1 MiB split in chunks of 4088 bytes
+        assert_eq!(sizes.len(), 257);
+        assert!(sizes[..256].iter().all(|size| *size == 4088));
+        assert_eq!(sizes[256], PAYLOAD_SIZE - 256 * 4088);
+    }
+
+    #[test]
+    fn queue_declare_counts() {
+        let _ = env_logger::try_init();
+
+        use crate::options::QueueDeclareOptions;
+        use crate::queue::Queue;
+        use crate::types::FieldTable;
+        use amq_protocol::protocol::queue;
+
+        let (conn, channel, consumer) = Connection::channel_with_consumer("consumer-tag");
+        let consumer_tag = consumer.tag();
+
+        let declared = channel.queue_declare(
+            "consumed",
+            QueueDeclareOptions {
+                passive: true,
+                ..QueueDeclareOptions::default()
+            },
+            FieldTable::default(),
+        );
+        let declare_ok_frame = AMQPFrame::Method(
+            channel.id(),
+            AMQPClass::Queue(queue::AMQPMethod::DeclareOk(queue::DeclareOk {
+                queue: "consumed".into(),
+                message_count: 12,
+                consumer_count: 1,
+            })),
+        );
+        conn.handle_frame(declare_ok_frame).unwrap();
+        let queue = declared.try_wait().unwrap().unwrap();
+        assert_eq!(queue.message_count(), 12);
+        assert_eq!(queue.consumer_count(), 1);
+
+        // The consumer is still registered after declaring the queue again
+        let deliveries = delivery_frames(channel.id(), &consumer_tag, 1, b"data");
+        let (_, res) = conn.parse_all(&deliveries);
+        assert!(res.is_ok());
+        assert_eq!(consumer.inner().buffered_deliveries(), 1);
+    }
+
+    #[test]
+    fn basic_publish_nacked() {
+        let _ = env_logger::try_init();
+
+        use crate::options::BasicPublishOptions;
+
+        // Bootstrap connection state to a publishing state with publisher confirms
+        let (conn, channel) = Connection::connected_channel();
+        conn.configuration().set_frame_max(8192);
+        channel.status().set_confirm();
+        let acked = channel.basic_publish(
+            "",
+            "queue",
+            BasicPublishOptions::default(),
+            b"acked".to_vec(),
+            BasicProperties::default(),
+        );
+        let nacked = channel.basic_publish(
+            "",
+            "queue",
+            BasicPublishOptions::default(),
+            b"nacked".to_vec(),
+            BasicProperties::default(),
+        );
+        assert!(acked.try_wait().is_none());
+        assert!(nacked.try_wait().is_none());
+        // Now test the state machine behaviour
+        {
+            let ack_frame = AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Basic(basic::AMQPMethod::Ack(basic::Ack {
+                    delivery_tag: 1,
+                    multiple: false,
+                })),
+            );
+            conn.handle_frame(ack_frame).unwrap();
+            assert!(acked.try_wait().unwrap().is_ok());
+        }
+        {
+            let nack_frame = AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Basic(basic::AMQPMethod::Nack(basic::Nack {
+                    delivery_tag: 2,
+                    multiple: false,
+                    requeue: false,
+                })),
+            );
+            conn.handle_frame(nack_frame).unwrap();
+            match nacked.try_wait() {
+                Some(Err(Error::PublishNacked(2))) => {}
+                res => panic!("unexpected publish result: {:?}", res),
+            }
+        }
+    }
+
+    #[test]
+    fn basic_qos() {
+        let _ = env_logger::try_init();
+
+        use crate::options::BasicQosOptions;
+
+        let (conn, channel) = Connection::connected_channel();
+        for global in &[false, true] {
+            let confirmation = channel.basic_qos(10, BasicQosOptions { global: *global });
+            match conn.next_frame() {
+                Some((
+                    send_id,
+                    AMQPFrame::Method(
+                        channel_id,
+                        AMQPClass::Basic(basic::AMQPMethod::Qos(basic::Qos {
+                            prefetch_count,
+                            global: sent_global,
+                            ..
+                        })),
+                    ),
+                )) => {
+                    assert_eq!(channel_id, channel.id());
+                    assert_eq!(prefetch_count, 10);
+                    assert_eq!(sent_global, *global);
+                    conn.mark_sent(send_id);
+                }
+                frame => panic!("unexpected frame: {:?}", frame),
+            }
+            assert!(confirmation.try_wait().is_none());
+            let qos_ok_frame = AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Basic(basic::AMQPMethod::QosOk(basic::QosOk {})),
+            );
+            conn.handle_frame(qos_ok_frame).unwrap();
+            assert!(confirmation.try_wait().unwrap().is_ok());
+        }
+    }
+
+    #[test]
+    fn basic_publish_returned() {
+        let _ = env_logger::try_init();
+
+        use crate::options::BasicPublishOptions;
+
+        // Bootstrap connection state to a publishing state with publisher confirms
+        let (conn, channel) = Connection::connected_channel();
+        conn.configuration().set_frame_max(8192);
+        channel.status().set_confirm();
+        let returned = channel.basic_publish(
+            "",
+            "unroutable",
+            BasicPublishOptions {
+                mandatory: true,
+                ..BasicPublishOptions::default()
+            },
+            b"returned".to_vec(),
+            BasicProperties::default(),
+        );
+        // Now test the state machine behaviour
+        {
+            let return_frame = AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Basic(basic::AMQPMethod::Return(basic::Return {
+                    reply_code: 312,
+                    reply_text: "NO_ROUTE".into(),
+                    exchange: "".into(),
+                    routing_key: "unroutable".into(),
+                })),
+            );
+            conn.handle_frame(return_frame).unwrap();
+            let header_frame = AMQPFrame::Header(
+                channel.id(),
+                60,
+                Box::new(AMQPContentHeader {
+                    class_id: 60,
+                    weight: 0,
+                    body_size: 8,
+                    properties: BasicProperties::default(),
+                }),
+            );
+            conn.handle_frame(header_frame).unwrap();
+            let body_frame = AMQPFrame::Body(channel.id(), b"returned".to_vec());
+            conn.handle_frame(body_frame).unwrap();
+            assert!(returned.try_wait().is_none());
+        }
+        {
+            let ack_frame = AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Basic(basic::AMQPMethod::Ack(basic::Ack {
+                    delivery_tag: 1,
+                    multiple: false,
+                })),
+            );
+            conn.handle_frame(ack_frame).unwrap();
+            match returned.try_wait() {
+                Some(Err(Error::MessageReturned(message))) => {
+                    assert_eq!(message.reply_code, 312);
+                    assert_eq!(message.delivery.routing_key.as_str(), "unroutable");
+                    assert_eq!(message.delivery.data, b"returned");
+                }
+                res => panic!("unexpected publish result: {:?}", res),
+            }
+        }
+    }
+
+    #[test]
+    fn payloads_not_copied() {
+        let _ = env_logger::try_init();
+
+        use crate::options::BasicPublishOptions;
+
+        let (conn, channel, consumer) = Connection::channel_with_consumer("consumer-tag");
+        conn.configuration().set_frame_max(8192);
+
+        let payload = Payload::from(vec![42; 10_000]);
+        let _ = channel.basic_publish(
+            "",
+            "queue",
+            BasicPublishOptions::default(),
+            payload.clone(),
+            BasicProperties::default(),
+        );
+        let mut body_ptrs = Vec::new();
+        while let Some((send_id, frame)) = conn.pop_frame() {
+            if let OutboundFrame::Body(_, body) = frame {
+                body_ptrs.push(body.as_ptr());
+            }
+            conn.mark_sent(send_id);
+        }
+        // Split in views on the published buffer
+        assert_eq!(body_ptrs, vec![payload.as_ptr(), payload[8184..].as_ptr()]);
+
+        let data = Payload::from(delivery_frames(
+            channel.id(),
+            &consumer.tag(),
+            1,
+            &[42; 4096],
+        ));
+        let (consumed, res) = conn.parse_shared(&data);
+        assert_eq!(consumed, data.len());
+        assert!(res.is_ok());
+        let delivery = consumer.inner().next_delivery().unwrap().unwrap().unwrap();
+        // A view on the received buffer, the body frame ending with its payload and frame end
+        assert_eq!(delivery.data.len(), 4096);
+        assert_eq!(delivery.data.as_ptr(), data[data.len() - 4097..].as_ptr());
+    }
+
+    #[test]
+    fn send_queue_full_blocks() {
+        let _ = env_logger::try_init();
+
+        use crate::configuration::SendQueueFullPolicy;
+        use crate::options::BasicPublishOptions;
+        use std::{thread, time::Duration};
+
+        let (conn, channel) = Connection::connected_channel();
+        conn.configuration().set_send_queue_capacity(1);
+        conn.configuration()
+            .set_send_queue_full_policy(SendQueueFullPolicy::Block);
+        let publish = |payload: &[u8]| {
+            channel.basic_publish(
+                "",
+                "queue",
+                BasicPublishOptions::default(),
+                payload.to_vec(),
+                BasicProperties::default(),
+            )
+        };
+        assert!(publish(b"first").try_wait().is_none());
+        assert!(channel.send_queue_full());
+
+        // Play the io loop, catching up after a while
+        let writer_conn = conn.clone();
+        let writer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            let mut sent = 0;
+            while sent < 6 {
+                match writer_conn.next_frame() {
+                    Some((send_id, _)) => {
+                        writer_conn.mark_sent(send_id);
+                        sent += 1;
+                    }
+                    None => thread::sleep(Duration::from_millis(1)),
+                }
+            }
+        });
+        // Blocks instead of failing with Error::SendQueueFull
+        publish(b"second").wait().unwrap();
+        writer.join().unwrap();
+        assert_eq!(conn.pending_frame_count(), 0);
+    }
+
+    #[test]
+    fn locale_negotiation() {
+        let _ = env_logger::try_init();
+
+        use crate::types::FieldTable;
+        use amq_protocol::protocol::connection;
+
+        for (preferred, server_locales, expected) in &[
+            (vec!["fr_FR", "de_DE"], "en_US de_DE fr_FR", "fr_FR"),
+            (vec!["it_IT", "de_DE"], "en_US de_DE", "de_DE"),
+            (vec!["it_IT"], "en_US de_DE", "en_US"),
+            (vec![], "en_US", "en_US"),
+        ] {
+            let options = ConnectionProperties {
+                locales: preferred.iter().map(|l| l.to_string()).collect(),
+                ..ConnectionProperties::default()
+            };
+            let uri = Connection::validate_uri("amqp://127.0.0.1:5672/%2f").unwrap();
+            let (conn, _wait) = Connection::handshake(uri, options).unwrap();
+            let (send_id, _) = conn.next_frame().unwrap();
+            conn.mark_sent(send_id);
+            let start_frame = AMQPFrame::Method(
+                0,
+                AMQPClass::Connection(connection::AMQPMethod::Start(connection::Start {
+                    version_major: 0,
+                    version_minor: 9,
+                    server_properties: FieldTable::default(),
+                    mechanisms: "PLAIN AMQPLAIN".into(),
+                    locales: (*server_locales).into(),
+                })),
+            );
+            conn.handle_frame(start_frame).unwrap();
+            assert_eq!(&conn.configuration().locale(), expected);
+            match conn.next_frame() {
+                Some((
+                    _,
+                    AMQPFrame::Method(
+                        0,
+                        AMQPClass::Connection(connection::AMQPMethod::StartOk(start_ok)),
+                    ),
+                )) => assert_eq!(&start_ok.locale.to_string(), expected),
+                frame => panic!("unexpected frame: {:?}", frame),
+            }
+        }
+    }
+
+    #[test]
+    fn channel_close() {
+        let _ = env_logger::try_init();
+
+        use crate::options::{BasicPublishOptions, BasicQosOptions};
+        use amq_protocol::protocol::channel;
+
+        let (conn, chan) = Connection::connected_channel();
+        chan.status().set_confirm();
+        let publish = chan.basic_publish(
+            "",
+            "queue",
+            BasicPublishOptions::default(),
+            b"unconfirmed".to_vec(),
+            BasicProperties::default(),
+        );
+        while let Some((send_id, _)) = conn.next_frame() {
+            conn.mark_sent(send_id);
+        }
+
+        let close = chan.close(200, "OK");
+        match conn.next_frame() {
+            Some((
+                send_id,
+                AMQPFrame::Method(_, AMQPClass::Channel(channel::AMQPMethod::Close(_))),
+            )) => conn.mark_sent(send_id),
+            frame => panic!("unexpected frame: {:?}", frame),
+        }
+        assert_eq!(chan.status().state(), ChannelState::Closing);
+        match chan.basic_qos(10, BasicQosOptions::default()).wait() {
+            Err(Error::ChannelClosed(id)) => assert_eq!(id, chan.id()),
+            res => panic!("unexpected result: {:?}", res),
+        }
+        assert!(close.try_wait().is_none());
+
+        conn.handle_frame(AMQPFrame::Method(
+            chan.id(),
+            AMQPClass::Channel(channel::AMQPMethod::CloseOk(channel::CloseOk {})),
+        ))
+        .unwrap();
+        assert!(close.wait().is_ok());
+        assert_eq!(chan.status().state(), ChannelState::Closed);
+        assert!(conn.channels().get(chan.id()).is_none());
+        match publish.wait() {
+            Err(Error::ChannelClosed(_)) => {}
+            res => panic!("the pending confirmation should have failed: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn channel_closed_by_server() {
+        let _ = env_logger::try_init();
+
+        use crate::options::QueueDeclareOptions;
+        use crate::types::FieldTable;
+        use amq_protocol::protocol::{channel, AMQPSoftError};
+
+        let (conn, chan) = Connection::connected_channel();
+        let declare = chan.queue_declare(
+            "redeclared",
+            QueueDeclareOptions::default(),
+            FieldTable::default(),
+        );
+        conn.peek_outgoing();
+
+        conn.inject_frame(AMQPFrame::Method(
+            chan.id(),
+            AMQPClass::Channel(channel::AMQPMethod::Close(channel::Close {
+                reply_code: AMQPSoftError::PRECONDITIONFAILED.get_id(),
+                reply_text: "PRECONDITION_FAILED - inequivalent arg 'durable'".into(),
+                class_id: 50,
+                method_id: 10,
+            })),
+        ))
+        .unwrap();
+        match declare.wait() {
+            Err(Error::ChannelClosedByServer(id, reason)) => {
+                assert_eq!(id, chan.id());
+                assert_eq!(reason.reply_code, 406);
+                assert_eq!((reason.class_id, reason.method_id), (50, 10));
+            }
+            res => panic!("unexpected result: {:?}", res),
+        }
+        match conn.peek_outgoing().as_slice() {
+            [AMQPFrame::Method(_, AMQPClass::Channel(channel::AMQPMethod::CloseOk(_)))] => {}
+            frames => panic!("unexpected frames: {:?}", frames),
+        }
+        assert_eq!(chan.status().state(), ChannelState::Closed);
+        assert_eq!(
+            chan.status().close_reason().map(|reason| reason.reply_code),
+            Some(406)
+        );
+        assert!(conn.channels().get(chan.id()).is_none());
+        assert!(conn.status().connected());
+    }
+
+    #[test]
+    fn max_message_size() {
+        let _ = env_logger::try_init();
+
+        use crate::options::BasicQosOptions;
+        use amq_protocol::protocol::{channel, AMQPSoftError};
+
+        let (conn, chan, consumer) = Connection::channel_with_consumer("consumer-tag");
+        conn.configuration().set_max_message_size(1024);
+        let queue_name = ShortString::from("consumed");
+        let consumer_tag = consumer.tag();
+
+        conn.handle_frame(AMQPFrame::Method(
+            chan.id(),
+            AMQPClass::Basic(basic::AMQPMethod::Deliver(basic::Deliver {
+                consumer_tag,
+                delivery_tag: 1,
+                redelivered: false,
+                exchange: "".into(),
+                routing_key: queue_name,
+            })),
+        ))
+        .unwrap();
+        let header_frame = AMQPFrame::Header(
+            chan.id(),
+            60,
+            Box::new(AMQPContentHeader {
+                class_id: 60,
+                weight: 0,
+                body_size: 1 << 40,
+                properties: BasicProperties::default(),
+            }),
+        );
+        conn.handle_frame(header_frame).unwrap();
+        match consumer.into_iter().next() {
+            Some(Err(Error::MessageTooLarge(size, 1024))) => assert_eq!(size, 1 << 40),
+            res => panic!("unexpected delivery: {:?}", res),
+        }
+        assert_eq!(chan.status().state(), ChannelState::Closing);
+        match conn.peek_outgoing().as_slice() {
+            [AMQPFrame::Method(
+                channel_id,
+                AMQPClass::Channel(channel::AMQPMethod::Close(channel::Close {
+                    reply_code, ..
+                })),
+            )] => {
+                assert_eq!(*channel_id, chan.id());
+                assert_eq!(*reply_code, AMQPSoftError::CONTENTTOOLARGE.get_id());
+            }
+            frames => panic!("unexpected frames: {:?}", frames),
+        }
+
+        // The rest of the message is discarded along with the channel
+        conn.handle_frame(AMQPFrame::Body(chan.id(), vec![0; 1024]))
+            .unwrap();
+        conn.handle_frame(AMQPFrame::Method(
+            chan.id(),
+            AMQPClass::Channel(channel::AMQPMethod::CloseOk(channel::CloseOk {})),
+        ))
+        .unwrap();
+        assert_eq!(chan.status().state(), ChannelState::Closed);
+        assert_eq!(conn.status().state(), ConnectionState::Connected);
+
+        // The other channels aren't affected
+        let other = conn.create_open_channel().unwrap();
+        let qos = other.basic_qos(10, BasicQosOptions::default());
+        conn.peek_outgoing();
+        conn.handle_frame(AMQPFrame::Method(
+            other.id(),
+            AMQPClass::Basic(basic::AMQPMethod::QosOk(basic::QosOk {})),
+        ))
+        .unwrap();
+        assert!(qos.try_wait().unwrap().is_ok());
+    }
+
+    #[test]
+    fn publisher_confirms_multiple() {
+        let _ = env_logger::try_init();
+
+        use crate::options::BasicPublishOptions;
+
+        let (conn, channel) = Connection::connected_channel();
+        channel.status().set_confirm();
+        let publishes = (0..4)
+            .map(|_| {
+                channel.basic_publish(
+                    "",
+                    "queue",
+                    BasicPublishOptions::default(),
+                    b"confirmed".to_vec(),
+                    BasicProperties::default(),
+                )
+            })
+            .collect::<Vec<_>>();
+        conn.peek_outgoing();
+        assert!(publishes.iter().all(|publish| publish.try_wait().is_none()));
+
+        conn.inject_frame(AMQPFrame::Method(
+            channel.id(),
+            AMQPClass::Basic(basic::AMQPMethod::Ack(basic::Ack {
+                delivery_tag: 2,
+                multiple: true,
+            })),
+        ))
+        .unwrap();
+        assert!(publishes[2].try_wait().is_none());
+        conn.inject_frame(AMQPFrame::Method(
+            channel.id(),
+            AMQPClass::Basic(basic::AMQPMethod::Nack(basic::Nack {
+                delivery_tag: 4,
+                multiple: true,
+                requeue: false,
+            })),
+        ))
+        .unwrap();
+
+        let results = publishes
+            .into_iter()
+            .map(Confirmation::wait)
+            .collect::<Vec<_>>();
+        match results.as_slice() {
+            [Ok(()), Ok(()), Err(Error::PublishNacked(3)), Err(Error::PublishNacked(4))] => {}
+            results => panic!("unexpected confirms: {:?}", results),
+        }
+    }
+
+    #[test]
+    fn delivery_properties() {
+        let _ = env_logger::try_init();
+
+        use crate::types::{AMQPValue, FieldTable};
+
+        let (conn, channel, consumer) = Connection::channel_with_consumer("consumer-tag");
+        let queue_name = ShortString::from("consumed");
+        let consumer_tag = consumer.tag();
+
+        let mut headers = FieldTable::default();
+        headers.insert("x-trace-id".into(), AMQPValue::LongString("abc".into()));
+        headers.insert("x-attempt".into(), AMQPValue::LongInt(3));
+        let properties = BasicProperties::default()
+            .with_content_type("application/json".into())
+            .with_correlation_id("correlation".into())
+            .with_headers(headers.clone());
+        conn.handle_frame(AMQPFrame::Method(
+            channel.id(),
+            AMQPClass::Basic(basic::AMQPMethod::Deliver(basic::Deliver {
+                consumer_tag,
+                delivery_tag: 1,
+                redelivered: false,
+                exchange: "".into(),
+                routing_key: queue_name,
+            })),
+        ))
+        .unwrap();
+        conn.handle_frame(AMQPFrame::Header(
+            channel.id(),
+            60,
+            Box::new(AMQPContentHeader {
+                class_id: 60,
+                weight: 0,
+                body_size: 2,
+                properties,
+            }),
+        ))
+        .unwrap();
+        conn.handle_frame(AMQPFrame::Body(channel.id(), b"{}".to_vec()))
+            .unwrap();
+
+        let delivery = consumer.inner().next_delivery().unwrap().unwrap().unwrap();
+        assert_eq!(delivery.data, b"{}");
+        assert_eq!(
+            delivery.properties.content_type(),
+            &Some("application/json".into())
+        );
+        assert_eq!(
+            delivery.properties.correlation_id(),
+            &Some("correlation".into())
+        );
+        assert_eq!(delivery.properties.headers(), &Some(headers));
+    }
+
+    #[test]
+    fn wait_for_confirms() {
+        let _ = env_logger::try_init();
+
+        use crate::options::BasicPublishOptions;
+        use amq_protocol::protocol::{channel, AMQPSoftError};
+
+        let (conn, chan) = Connection::connected_channel();
+        chan.status().set_confirm();
+        let ack = |delivery_tag| {
+            AMQPFrame::Method(
+                chan.id(),
+                AMQPClass::Basic(basic::AMQPMethod::Ack(basic::Ack {
+                    delivery_tag,
+                    multiple: false,
+                })),
+            )
+        };
+        let publish = || {
+            chan.basic_publish(
+                "",
+                "queue",
+                BasicPublishOptions::default(),
+                b"confirmed".to_vec(),
+                BasicProperties::default(),
+            )
+        };
+
+        let unconfirmed = chan.wait_for_confirms().try_wait();
+        assert_eq!(unconfirmed.map(Result::unwrap), Some(Default::default()));
+
+        let _publishes = (0..3).map(|_| publish()).collect::<Vec<_>>();
+        conn.peek_outgoing();
+        let confirms = chan.wait_for_confirms();
+        conn.inject_frame(ack(1)).unwrap();
+        conn.inject_frame(AMQPFrame::Method(
+            chan.id(),
+            AMQPClass::Basic(basic::AMQPMethod::Nack(basic::Nack {
+                delivery_tag: 2,
+                multiple: false,
+                requeue: false,
+            })),
+        ))
+        .unwrap();
+        assert!(confirms.try_wait().is_none());
+        conn.inject_frame(ack(3)).unwrap();
+        let unconfirmed = confirms.wait().unwrap();
+        assert_eq!(unconfirmed.nacked, vec![2]);
+        assert!(unconfirmed.returned.is_empty());
+
+        let _publish = publish();
+        conn.peek_outgoing();
+        let confirms = chan.wait_for_confirms();
+        conn.inject_frame(AMQPFrame::Method(
+            chan.id(),
+            AMQPClass::Channel(channel::AMQPMethod::Close(channel::Close {
+                reply_code: AMQPSoftError::NOTFOUND.get_id(),
+                reply_text: "NOT_FOUND - no exchange".into(),
+                class_id: 60,
+                method_id: 40,
+            })),
+        ))
+        .unwrap();
+        match confirms.wait() {
+            Err(Error::ChannelClosedByServer(id, _)) => assert_eq!(id, chan.id()),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn basic_recover() {
+        let _ = env_logger::try_init();
+
+        use crate::options::BasicRecoverOptions;
+
+        let (conn, channel, consumer) = Connection::channel_with_consumer("consumer-tag");
+        let queue_name = ShortString::from("consumed");
+        let consumer_tag = consumer.tag();
+
+        let recover = channel.basic_recover(BasicRecoverOptions { requeue: true });
+        match conn.peek_outgoing().as_slice() {
+            [AMQPFrame::Method(_, AMQPClass::Basic(basic::AMQPMethod::Recover(recover)))] => {
+                assert!(recover.requeue)
+            }
+            frames => panic!("unexpected frames: {:?}", frames),
+        }
+        assert!(recover.try_wait().is_none());
+        conn.inject_frame(AMQPFrame::Method(
+            channel.id(),
+            AMQPClass::Basic(basic::AMQPMethod::RecoverOk(basic::RecoverOk {})),
+        ))
+        .unwrap();
+        assert!(recover.wait().is_ok());
+
+        conn.handle_frame(AMQPFrame::Method(
+            channel.id(),
+            AMQPClass::Basic(basic::AMQPMethod::Deliver(basic::Deliver {
+                consumer_tag,
+                delivery_tag: 2,
+                redelivered: true,
+                exchange: "".into(),
+                routing_key: queue_name,
+            })),
+        ))
+        .unwrap();
+        conn.handle_frame(AMQPFrame::Header(
+            channel.id(),
+            60,
+            Box::new(AMQPContentHeader {
+                class_id: 60,
+                weight: 0,
+                body_size: 2,
+                properties: BasicProperties::default(),
+            }),
+        ))
+        .unwrap();
+        conn.handle_frame(AMQPFrame::Body(channel.id(), b"{}".to_vec()))
+            .unwrap();
+
+        let delivery = consumer.inner().next_delivery().unwrap().unwrap().unwrap();
+        assert_eq!(delivery.delivery_tag, 2);
+        assert!(delivery.redelivered);
+    }
+
+    #[test]
+    fn basic_return_without_confirms() {
+        let _ = env_logger::try_init();
+
+        use crate::options::BasicPublishOptions;
+        use parking_lot::Mutex;
+        use std::sync::mpsc;
+
+        let (conn, channel) = Connection::connected_channel();
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        channel.on_return(Box::new(move |message| {
+            sender.lock().send(message).unwrap();
+        }));
+        let publish = channel.basic_publish(
+            "",
+            "unroutable",
+            BasicPublishOptions {
+                mandatory: true,
+                ..BasicPublishOptions::default()
+            },
+            b"returned".to_vec(),
+            BasicProperties::default(),
+        );
+        conn.peek_outgoing();
+        assert!(publish.wait().is_ok());
+
+        conn.handle_frame(AMQPFrame::Method(
+            channel.id(),
+            AMQPClass::Basic(basic::AMQPMethod::Return(basic::Return {
+                reply_code: 312,
+                reply_text: "NO_ROUTE".into(),
+                exchange: "".into(),
+                routing_key: "unroutable".into(),
+            })),
+        ))
+        .unwrap();
+        conn.handle_frame(AMQPFrame::Header(
+            channel.id(),
+            60,
+            Box::new(AMQPContentHeader {
+                class_id: 60,
+                weight: 0,
+                body_size: 8,
+                properties: BasicProperties::default().with_message_id("returned-id".into()),
+            }),
+        ))
+        .unwrap();
+        conn.handle_frame(AMQPFrame::Body(channel.id(), b"returned".to_vec()))
+            .unwrap();
+        assert_eq!(channel.status().state(), ChannelState::Connected);
+
+        let message = receiver.try_recv().unwrap();
+        assert_eq!(message.reply_code, 312);
+        assert_eq!(message.reply_text.as_str(), "NO_ROUTE");
+        assert_eq!(message.delivery.routing_key.as_str(), "unroutable");
+        assert_eq!(
+            message.delivery.properties.message_id(),
+            &Some("returned-id".into())
+        );
+        assert_eq!(message.delivery.data, b"returned");
+        assert_eq!(channel.drain_returned_messages(), vec![message]);
+        assert!(channel.drain_returned_messages().is_empty());
+    }
+
+    #[test]
+    fn queue_declare_passive_not_found() {
+        let _ = env_logger::try_init();
+
+        use amq_protocol::protocol::{channel, queue, AMQPSoftError};
+
+        let (conn, chan) = Connection::connected_channel();
+        let declare = chan.queue_declare_passive("missing");
+        match conn.peek_outgoing().as_slice() {
+            [AMQPFrame::Method(_, AMQPClass::Queue(queue::AMQPMethod::Declare(declare)))] => {
+                assert_eq!(declare.queue.as_str(), "missing");
+                assert!(declare.passive);
+            }
+            frames => panic!("unexpected frames: {:?}", frames),
+        }
+
+        conn.inject_frame(AMQPFrame::Method(
+            chan.id(),
+            AMQPClass::Channel(channel::AMQPMethod::Close(channel::Close {
+                reply_code: AMQPSoftError::NOTFOUND.get_id(),
+                reply_text: "NOT_FOUND - no queue 'missing' in vhost '/'".into(),
+                class_id: 50,
+                method_id: 10,
+            })),
+        ))
+        .unwrap();
+        match declare.wait() {
+            Err(error) => assert!(error.not_found(), "unexpected error: {:?}", error),
+            Ok(queue) => panic!("unexpected queue: {:?}", queue),
+        }
+        assert!(!Error::ChannelClosed(chan.id()).not_found());
+    }
+
+    #[test]
+    fn basic_qos_remembered() {
+        let _ = env_logger::try_init();
+
+        use crate::channel_status::Qos;
+        use crate::options::BasicQosOptions;
+
+        let (conn, channel) = Connection::connected_channel();
+        let qos = channel.basic_qos(10, BasicQosOptions { global: true });
+        match conn.peek_outgoing().as_slice() {
+            [AMQPFrame::Method(_, AMQPClass::Basic(basic::AMQPMethod::Qos(qos)))] => {
+                assert_eq!(qos.prefetch_count, 10);
+                assert!(qos.global);
+            }
+            frames => panic!("unexpected frames: {:?}", frames),
+        }
+        assert_eq!(channel.status().qos(), None);
+
+        conn.inject_frame(AMQPFrame::Method(
+            channel.id(),
+            AMQPClass::Basic(basic::AMQPMethod::QosOk(basic::QosOk {})),
+        ))
+        .unwrap();
+        assert!(qos.wait().is_ok());
+        assert_eq!(
+            channel.status().qos(),
+            Some(Qos {
+                prefetch_count: 10,
+                global: true,
+            })
+        );
+    }
+
+    #[test]
+    fn exchange_bindings() {
+        let _ = env_logger::try_init();
+
+        use crate::options::{ExchangeBindOptions, ExchangeUnbindOptions};
+        use crate::types::FieldTable;
+        use amq_protocol::protocol::exchange;
+
+        let (conn, channel) = Connection::connected_channel();
+
+        let bind = channel.exchange_bind(
+            "destination",
+            "source",
+            "key.#",
+            ExchangeBindOptions::default(),
+            FieldTable::default(),
+        );
+        match conn.peek_outgoing().as_slice() {
+            [AMQPFrame::Method(_, AMQPClass::Exchange(exchange::AMQPMethod::Bind(bind)))] => {
+                assert_eq!(bind.destination.as_str(), "destination");
+                assert_eq!(bind.source.as_str(), "source");
+                assert_eq!(bind.routing_key.as_str(), "key.#");
+            }
+            frames => panic!("unexpected frames: {:?}", frames),
+        }
+        assert!(bind.try_wait().is_none());
+        conn.inject_frame(AMQPFrame::Method(
+            channel.id(),
+            AMQPClass::Exchange(exchange::AMQPMethod::BindOk(exchange::BindOk {})),
+        ))
+        .unwrap();
+        assert!(bind.wait().is_ok());
+
+        let unbind = channel.exchange_unbind(
+            "destination",
+            "source",
+            "key.#",
+            ExchangeUnbindOptions::default(),
+            FieldTable::default(),
+        );
+        match conn.peek_outgoing().as_slice() {
+            [AMQPFrame::Method(_, AMQPClass::Exchange(exchange::AMQPMethod::Unbind(unbind)))] => {
+                assert_eq!(unbind.destination.as_str(), "destination");
+                assert_eq!(unbind.source.as_str(), "source");
+            }
+            frames => panic!("unexpected frames: {:?}", frames),
+        }
+        conn.inject_frame(AMQPFrame::Method(
+            channel.id(),
+            AMQPClass::Exchange(exchange::AMQPMethod::UnbindOk(exchange::UnbindOk {})),
+        ))
+        .unwrap();
+        assert!(unbind.wait().is_ok());
+    }
+
+    #[test]
+    fn basic_get() {
+        let _ = env_logger::try_init();
+
+        use crate::options::BasicGetOptions;
+
+        let (conn, channel) = Connection::connected_channel();
+        let get_ok = |delivery_tag, message_count| {
+            AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Basic(basic::AMQPMethod::GetOk(basic::GetOk {
+                    delivery_tag,
+                    redelivered: delivery_tag == 1,
+                    exchange: "exchange".into(),
+                    routing_key: "key".into(),
+                    message_count,
+                })),
+            )
+        };
+        let header = |body_size| {
+            AMQPFrame::Header(
+                channel.id(),
+                60,
+                Box::new(AMQPContentHeader {
+                    class_id: 60,
+                    weight: 0,
+                    body_size,
+                    properties: BasicProperties::default().with_priority(5),
+                }),
+            )
+        };
+
+        // The queue wasn't declared on this channel
+        let get = channel.basic_get("fetched", BasicGetOptions { no_ack: true });
+        match conn.peek_outgoing().as_slice() {
+            [AMQPFrame::Method(_, AMQPClass::Basic(basic::AMQPMethod::Get(get)))] => {
+                assert_eq!(get.queue.as_str(), "fetched");
+                assert!(get.no_ack);
+            }
+            frames => panic!("unexpected frames: {:?}", frames),
+        }
+        conn.inject_frame(get_ok(1, 1)).unwrap();
+        conn.inject_frame(header(4)).unwrap();
+        assert!(get.try_wait().is_none());
+        conn.inject_frame(AMQPFrame::Body(channel.id(), b"data".to_vec()))
+            .unwrap();
+        let message = get.wait().unwrap().unwrap();
+        assert_eq!(message.delivery.delivery_tag, 1);
+        assert!(message.delivery.redelivered);
+        assert_eq!(message.delivery.exchange.as_str(), "exchange");
+        assert_eq!(message.delivery.routing_key.as_str(), "key");
+        assert_eq!(message.delivery.properties.priority(), &Some(5));
+        assert_eq!(message.delivery.data, b"data");
+        assert_eq!(message.message_count, 1);
+
+        let get = channel.basic_get("fetched", BasicGetOptions::default());
+        conn.peek_outgoing();
+        conn.inject_frame(get_ok(2, 0)).unwrap();
+        conn.inject_frame(header(0)).unwrap();
+        let message = get.wait().unwrap().unwrap();
+        assert_eq!(message.delivery.delivery_tag, 2);
+        assert!(message.delivery.data.is_empty());
+        assert_eq!(message.message_count, 0);
+        assert_eq!(channel.status().state(), ChannelState::Connected);
+        let delivery = crate::message::Delivery::from(message);
+        assert_eq!(delivery.delivery_tag, 2);
+
+        let get = channel.basic_get("fetched", BasicGetOptions::default());
+        conn.peek_outgoing();
+        conn.inject_frame(AMQPFrame::Method(
+            channel.id(),
+            AMQPClass::Basic(basic::AMQPMethod::GetEmpty(basic::GetEmpty {})),
+        ))
+        .unwrap();
+        assert!(get.wait().unwrap().is_none());
+    }
+
+    #[test]
+    fn delivery_acknowledgements_closed_channel() {
+        let _ = env_logger::try_init();
+
+        use crate::message::Delivery;
+        use crate::options::{BasicAckOptions, BasicNackOptions, BasicRejectOptions};
+        use amq_protocol::protocol::channel;
+
+        let (conn, chan) = Connection::connected_channel();
+        let delivery = Delivery::new(42, "".into(), "queue".into(), false);
+
+        // There is no reply to wait for, the ack resolves once sent
+        let ack = delivery.ack(&chan, BasicAckOptions::default());
+        assert!(ack.try_wait().is_none());
+        conn.peek_outgoing();
+        assert!(ack.try_wait().unwrap().is_ok());
+
+        let close = chan.close(200, "OK");
+        conn.peek_outgoing();
+        conn.inject_frame(AMQPFrame::Method(
+            chan.id(),
+            AMQPClass::Channel(channel::AMQPMethod::CloseOk(channel::CloseOk {})),
+        ))
+        .unwrap();
+        assert!(close.wait().is_ok());
+
+        let results = vec![
+            delivery.ack(&chan, BasicAckOptions::default()),
+            delivery.nack(&chan, BasicNackOptions::default()),
+            delivery.reject(&chan, BasicRejectOptions::default()),
+        ]
+        .into_iter()
+        .map(Confirmation::wait)
+        .collect::<Vec<_>>();
+        match results.as_slice() {
+            [Err(Error::ChannelClosed(_)), Err(Error::ChannelClosed(_)), Err(Error::ChannelClosed(_))] =>
+                {}
+            results => panic!("unexpected results: {:?}", results),
+        }
+        assert!(conn.peek_outgoing().is_empty());
+    }
+
+    #[test]
+    fn channel_flow() {
+        let _ = env_logger::try_init();
+
+        use crate::options::{BasicPublishOptions, ChannelFlowOptions};
+        use amq_protocol::protocol::channel;
+
+        let (conn, chan) = Connection::connected_channel();
+        let server_flow = |active| {
+            AMQPFrame::Method(
+                chan.id(),
+                AMQPClass::Channel(channel::AMQPMethod::Flow(channel::Flow { active })),
+            )
+        };
+
+        // The server pauses our publishes
+        conn.inject_frame(server_flow(false)).unwrap();
+        match conn.peek_outgoing().as_slice() {
+            [AMQPFrame::Method(_, AMQPClass::Channel(channel::AMQPMethod::FlowOk(flow_ok)))] => {
+                assert!(!flow_ok.active)
+            }
+            frames => panic!("unexpected frames: {:?}", frames),
+        }
+        assert!(!chan.status().flow());
+        let _ = chan.basic_publish(
+            "",
+            "queue",
+            BasicPublishOptions::default(),
+            b"paused".to_vec(),
+            BasicProperties::default(),
+        );
+        assert!(conn.peek_outgoing().is_empty());
+        conn.inject_frame(server_flow(true)).unwrap();
+        assert!(chan.status().flow());
+        match conn.peek_outgoing().as_slice() {
+            [AMQPFrame::Method(_, AMQPClass::Channel(channel::AMQPMethod::FlowOk(flow_ok))), AMQPFrame::Method(_, AMQPClass::Basic(basic::AMQPMethod::Publish(_))), AMQPFrame::Header(..), AMQPFrame::Body(..)] =>
+            {
+                assert!(flow_ok.active)
+            }
+            frames => panic!("unexpected frames: {:?}", frames),
+        }
+
+        // We pause the deliveries of the server
+        let flow = chan.channel_flow(ChannelFlowOptions { active: false });
+        match conn.peek_outgoing().as_slice() {
+            [AMQPFrame::Method(_, AMQPClass::Channel(channel::AMQPMethod::Flow(flow)))] => {
+                assert!(!flow.active)
+            }
+            frames => panic!("unexpected frames: {:?}", frames),
+        }
+        assert!(flow.try_wait().is_none());
+        conn.inject_frame(AMQPFrame::Method(
+            chan.id(),
+            AMQPClass::Channel(channel::AMQPMethod::FlowOk(channel::FlowOk {
+                active: false,
+            })),
+        ))
+        .unwrap();
+        assert!(!flow.wait().unwrap());
+    }
+
+    #[test]
+    fn transactions() {
+        let _ = env_logger::try_init();
+
+        use crate::options::{BasicPublishOptions, ConfirmSelectOptions};
+        use amq_protocol::protocol::tx;
+
+        let (conn, channel) = Connection::connected_channel();
+        match channel.tx_commit().wait() {
+            Err(Error::NotTransactional(id)) => assert_eq!(id, channel.id()),
+            res => panic!("unexpected result: {:?}", res),
+        }
+        match channel.tx_rollback().wait() {
+            Err(Error::NotTransactional(id)) => assert_eq!(id, channel.id()),
+            res => panic!("unexpected result: {:?}", res),
+        }
+        assert!(conn.peek_outgoing().is_empty());
+
+        let select = channel.tx_select();
+        match conn.peek_outgoing().as_slice() {
+            [AMQPFrame::Method(_, AMQPClass::Tx(tx::AMQPMethod::Select(_)))] => {}
+            frames => panic!("unexpected frames: {:?}", frames),
+        }
+        conn.inject_frame(AMQPFrame::Method(
+            channel.id(),
+            AMQPClass::Tx(tx::AMQPMethod::SelectOk(tx::SelectOk {})),
+        ))
+        .unwrap();
+        assert!(select.wait().is_ok());
+        assert!(channel.status().transactional());
+
+        let _ = channel.basic_publish(
+            "",
+            "queue",
+            BasicPublishOptions::default(),
+            b"transactional".to_vec(),
+            BasicProperties::default(),
+        );
+        let commit = channel.tx_commit();
+        match conn.peek_outgoing().as_slice() {
+            [AMQPFrame::Method(_, AMQPClass::Basic(basic::AMQPMethod::Publish(_))), AMQPFrame::Header(..), AMQPFrame::Body(..), AMQPFrame::Method(_, AMQPClass::Tx(tx::AMQPMethod::Commit(_)))] =>
+                {}
+            frames => panic!("unexpected frames: {:?}", frames),
+        }
+        conn.inject_frame(AMQPFrame::Method(
+            channel.id(),
+            AMQPClass::Tx(tx::AMQPMethod::CommitOk(tx::CommitOk {})),
+        ))
+        .unwrap();
+        assert!(commit.wait().is_ok());
+
+        let rollback = channel.tx_rollback();
+        conn.peek_outgoing();
+        conn.inject_frame(AMQPFrame::Method(
+            channel.id(),
+            AMQPClass::Tx(tx::AMQPMethod::RollbackOk(tx::RollbackOk {})),
+        ))
+        .unwrap();
+        assert!(rollback.wait().is_ok());
+
+        match channel
+            .confirm_select(ConfirmSelectOptions::default())
+            .wait()
+        {
+            Err(Error::ChannelModeConflict(id)) => assert_eq!(id, channel.id()),
+            res => panic!("unexpected result: {:?}", res),
+        }
+        assert!(conn.peek_outgoing().is_empty());
+
+        let confirmed = conn.create_open_channel().unwrap();
+        confirmed.status().set_confirm();
+        match confirmed.tx_select().wait() {
+            Err(Error::ChannelModeConflict(id)) => assert_eq!(id, confirmed.id()),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn unacked_deliveries() {
+        let _ = env_logger::try_init();
+
+        use crate::options::{
+            BasicAckOptions, BasicConsumeOptions, BasicNackOptions, BasicRejectOptions,
+        };
+        use crate::queue::{Queue, QueueState};
+        use crate::types::FieldTable;
+
+        let (conn, channel) = Connection::connected_channel();
+        let queue = Queue::new("consumed".into(), 0, 0);
+        channel.register_queue(QueueState::from(queue.clone()));
+        let consume = |consumer_tag: &str, no_ack| {
+            let consumer = channel.basic_consume(
+                &queue,
+                consumer_tag,
+                BasicConsumeOptions {
+                    no_ack,
+                    ..BasicConsumeOptions::default()
+                },
+                FieldTable::default(),
+            );
+            conn.peek_outgoing();
+            conn.inject_frame(AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Basic(basic::AMQPMethod::ConsumeOk(basic::ConsumeOk {
+                    consumer_tag: consumer_tag.into(),
+                })),
+            ))
+            .unwrap();
+            consumer.wait().unwrap()
+        };
+        let _acked = consume("acked", false);
+        let _auto_acked = consume("auto-acked", true);
+
+        for delivery_tag in 1..=5 {
+            let frames = delivery_frames(channel.id(), &"acked".into(), delivery_tag, b"data");
+            assert!(conn.parse_all(&frames).1.is_ok());
+        }
+        let frames = delivery_frames(channel.id(), &"auto-acked".into(), 6, b"data");
+        assert!(conn.parse_all(&frames).1.is_ok());
+        assert_eq!(channel.unacked_count(), 5);
+
+        let _ = channel.basic_ack(3, BasicAckOptions { multiple: true });
+        assert_eq!(channel.unacked_delivery_tags(), vec![4, 5]);
+        let _ = channel.basic_reject(5, BasicRejectOptions::default());
+        assert_eq!(channel.unacked_delivery_tags(), vec![4]);
+        let _ = channel.basic_nack(
+            0,
+            BasicNackOptions {
+                multiple: true,
+                requeue: true,
+            },
+        );
+        assert_eq!(channel.unacked_count(), 0);
+    }
+
+    #[test]
+    fn channel_error_handler() {
+        let _ = env_logger::try_init();
+
+        use crate::options::BasicQosOptions;
+        use amq_protocol::protocol::{channel, AMQPSoftError};
+        use parking_lot::Mutex;
+        use std::sync::mpsc;
+
+        let (conn, failing) = Connection::connected_channel();
+        let other = conn.create_open_channel().unwrap();
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        failing.on_error(Box::new(move |reason| {
+            sender.lock().send(reason).unwrap();
+        }));
+
+        conn.inject_frame(AMQPFrame::Method(
+            failing.id(),
+            AMQPClass::Channel(channel::AMQPMethod::Close(channel::Close {
+                reply_code: AMQPSoftError::PRECONDITIONFAILED.get_id(),
+                reply_text: "PRECONDITION_FAILED - inequivalent arg 'durable'".into(),
+                class_id: 50,
+                method_id: 10,
+            })),
+        ))
+        .unwrap();
+        let reason = receiver.try_recv().unwrap();
+        assert_eq!(reason.reply_code, 406);
+        assert_eq!(
+            reason.reply_text.as_str(),
+            "PRECONDITION_FAILED - inequivalent arg 'durable'"
+        );
+        assert!(receiver.try_recv().is_err());
+
+        conn.peek_outgoing();
+        assert_eq!(failing.status().state(), ChannelState::Closed);
+        assert!(conn.status().connected());
+        let qos = other.basic_qos(1, BasicQosOptions::default());
+        match conn.peek_outgoing().as_slice() {
+            [AMQPFrame::Method(id, AMQPClass::Basic(basic::AMQPMethod::Qos(_)))] => {
+                assert_eq!(*id, other.id())
+            }
+            frames => panic!("unexpected frames: {:?}", frames),
+        }
+        assert!(qos.try_wait().is_none());
+    }
+
+    #[test]
+    fn basic_cancel() {
+        let _ = env_logger::try_init();
+
+        use crate::options::{BasicCancelOptions, BasicConsumeOptions};
+        use crate::queue::{Queue, QueueState};
+        use crate::types::FieldTable;
+
+        let (conn, channel) = Connection::connected_channel();
+        let queue = Queue::new("consumed".into(), 0, 0);
+        channel.register_queue(QueueState::from(queue.clone()));
+        let consumer_tag = ShortString::from("consumer-tag");
+        let consumer = channel.basic_consume(
+            &queue,
+            consumer_tag.as_str(),
+            BasicConsumeOptions::default(),
+            FieldTable::default(),
+        );
+        conn.peek_outgoing();
+        conn.inject_frame(AMQPFrame::Method(
+            channel.id(),
+            AMQPClass::Basic(basic::AMQPMethod::ConsumeOk(basic::ConsumeOk {
+                consumer_tag: consumer_tag.clone(),
+            })),
+        ))
+        .unwrap();
+        let consumer = consumer.wait().unwrap();
+
+        let frames = delivery_frames(channel.id(), &consumer_tag, 1, b"before cancel");
+        assert!(conn.parse_all(&frames).1.is_ok());
+        let cancel = channel.basic_cancel(consumer_tag.as_str(), BasicCancelOptions::default());
+        match conn.peek_outgoing().as_slice() {
+            [AMQPFrame::Method(_, AMQPClass::Basic(basic::AMQPMethod::Cancel(cancel)))] => {
+                assert_eq!(cancel.consumer_tag, consumer_tag)
+            }
+            frames => panic!("unexpected frames: {:?}", frames),
+        }
+        // A delivery sent by the server before it received the cancel
+        let frames = delivery_frames(channel.id(), &consumer_tag, 2, b"in flight");
+        assert!(conn.parse_all(&frames).1.is_ok());
+        assert!(cancel.try_wait().is_none());
+        conn.inject_frame(AMQPFrame::Method(
+            channel.id(),
+            AMQPClass::Basic(basic::AMQPMethod::CancelOk(basic::CancelOk {
+                consumer_tag: consumer_tag.clone(),
+            })),
+        ))
+        .unwrap();
+        assert!(cancel.wait().is_ok());
+        assert!(consumer.is_cancelled());
+        assert_eq!(
+            consumer.cancelled_by(),
+            Some(crate::consumer::CancelledBy::Client)
+        );
+
+        let deliveries = consumer
+            .into_iter()
+            .map(|delivery| delivery.unwrap().data)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            deliveries,
+            vec![b"before cancel".to_vec(), b"in flight".to_vec()]
+        );
+    }
+
+    #[test]
+    fn basic_cancel_by_server() {
+        let _ = env_logger::try_init();
+
+        use crate::consumer::CancelledBy;
+        use crate::options::BasicConsumeOptions;
+        use crate::queue::{Queue, QueueState};
+        use crate::types::FieldTable;
+
+        let (conn, channel) = Connection::connected_channel();
+        let queue = Queue::new("deleted".into(), 0, 0);
+        channel.register_queue(QueueState::from(queue.clone()));
+        let consumer_tag = ShortString::from("consumer-tag");
+        let consumer = channel.basic_consume(
+            &queue,
+            consumer_tag.as_str(),
+            BasicConsumeOptions::default(),
+            FieldTable::default(),
+        );
+        conn.peek_outgoing();
+        conn.inject_frame(AMQPFrame::Method(
+            channel.id(),
+            AMQPClass::Basic(basic::AMQPMethod::ConsumeOk(basic::ConsumeOk {
+                consumer_tag: consumer_tag.clone(),
+            })),
+        ))
+        .unwrap();
+        let consumer = consumer.wait().unwrap();
+        assert!(!consumer.is_cancelled());
+
+        // The queue got deleted
+        conn.inject_frame(AMQPFrame::Method(
+            channel.id(),
+            AMQPClass::Basic(basic::AMQPMethod::Cancel(basic::Cancel {
+                consumer_tag: consumer_tag.clone(),
+                nowait: false,
+            })),
+        ))
+        .unwrap();
+        match conn.peek_outgoing().as_slice() {
+            [AMQPFrame::Method(_, AMQPClass::Basic(basic::AMQPMethod::CancelOk(cancel_ok)))] => {
+                assert_eq!(cancel_ok.consumer_tag, consumer_tag)
+            }
+            frames => panic!("unexpected frames: {:?}", frames),
+        }
+        assert!(consumer.is_cancelled());
+        assert_eq!(consumer.cancelled_by(), Some(CancelledBy::Server));
+        assert!(consumer.into_iter().next().is_none());
+        assert!(conn.status().connected());
+    }
+
+    #[test]
+    fn nowait_declarations() {
+        let _ = env_logger::try_init();
+
+        use crate::options::{ExchangeDeclareOptions, QueueBindOptions, QueuePurgeOptions};
+        use crate::types::FieldTable;
+        use crate::ExchangeKind;
+        use amq_protocol::protocol::queue;
+
+        let (conn, channel) = Connection::connected_channel();
+
+        let declare = channel.exchange_declare(
+            "exchange",
+            ExchangeKind::Topic,
+            ExchangeDeclareOptions {
+                nowait: true,
+                ..ExchangeDeclareOptions::default()
+            },
+            FieldTable::default(),
+        );
+        assert!(declare.try_wait().unwrap().is_ok());
+        let bind = channel.queue_bind(
+            "queue",
+            "exchange",
+            "key.#",
+            QueueBindOptions {
+                nowait: true,
+                ..QueueBindOptions::default()
+            },
+            FieldTable::default(),
+        );
+        assert!(bind.try_wait().unwrap().is_ok());
+        assert_eq!(conn.peek_outgoing().len(), 2);
+
+        // The next reply from the server still gets matched with its method
+        let purge = channel.queue_purge("queue", QueuePurgeOptions::default());
+        conn.inject_frame(AMQPFrame::Method(
+            channel.id(),
+            AMQPClass::Queue(queue::AMQPMethod::PurgeOk(queue::PurgeOk {
+                message_count: 3,
+            })),
+        ))
+        .unwrap();
+        assert_eq!(purge.wait().unwrap(), 3);
+        assert!(channel.status().is_connected());
+    }
+
+    #[test]
+    fn generated_consumer_tags() {
+        let _ = env_logger::try_init();
+
+        use crate::options::BasicConsumeOptions;
+        use crate::queue::{Queue, QueueState};
+        use crate::types::FieldTable;
+
+        let (conn, channel) = Connection::connected_channel();
+        let queue = Queue::new("consumed".into(), 0, 0);
+        channel.register_queue(QueueState::from(queue.clone()));
+
+        // The server generates the tag
+        let consumer = channel.basic_consume(
+            &queue,
+            "",
+            BasicConsumeOptions::default(),
+            FieldTable::default(),
+        );
+        conn.peek_outgoing();
+        let server_tag = ShortString::from("amq.ctag-generated");
+        conn.inject_frame(AMQPFrame::Method(
+            channel.id(),
+            AMQPClass::Basic(basic::AMQPMethod::ConsumeOk(basic::ConsumeOk {
+                consumer_tag: server_tag.clone(),
+            })),
+        ))
+        .unwrap();
+        let consumer = consumer.wait().unwrap();
+        assert_eq!(consumer.tag(), server_tag);
+        let frames = delivery_frames(channel.id(), &server_tag, 1, b"data");
+        assert!(conn.parse_all(&frames).1.is_ok());
+        assert_eq!(consumer.inner().buffered_deliveries(), 1);
+
+        // Without a reply, we generate it
+        let consumer = channel
+            .basic_consume(
+                &queue,
+                "",
+                BasicConsumeOptions {
+                    nowait: true,
+                    ..BasicConsumeOptions::default()
+                },
+                FieldTable::default(),
+            )
+            .wait()
+            .unwrap();
+        let client_tag = consumer.tag();
+        assert!(client_tag.as_str().starts_with("lapin-ctag-"));
+        match conn.peek_outgoing().as_slice() {
+            [AMQPFrame::Method(_, AMQPClass::Basic(basic::AMQPMethod::Consume(consume)))] => {
+                assert_eq!(consume.consumer_tag, client_tag)
+            }
+            frames => panic!("unexpected frames: {:?}", frames),
+        }
+        let frames = delivery_frames(channel.id(), &client_tag, 2, b"data");
+        assert!(conn.parse_all(&frames).1.is_ok());
+        assert_eq!(consumer.inner().buffered_deliveries(), 1);
+    }
+
+    #[test]
+    fn exchange_declare_and_delete() {
+        let _ = env_logger::try_init();
+
+        use crate::options::{ExchangeDeclareOptions, ExchangeDeleteOptions};
+        use crate::types::FieldTable;
+        use crate::ExchangeKind;
+        use amq_protocol::protocol::{channel, exchange, AMQPSoftError};
+
+        let (conn, chan) = Connection::connected_channel();
+
+        let declare = chan.exchange_declare(
+            "logs",
+            ExchangeKind::Topic,
+            ExchangeDeclareOptions {
+                durable: true,
+                ..ExchangeDeclareOptions::default()
+            },
+            FieldTable::default(),
+        );
+        match conn.peek_outgoing().as_slice() {
+            [AMQPFrame::Method(_, AMQPClass::Exchange(exchange::AMQPMethod::Declare(declare)))] => {
+                assert_eq!(declare.exchange.as_str(), "logs");
+                assert_eq!(declare.kind.as_str(), "topic");
+                assert!(declare.durable);
+                assert!(!declare.passive);
+            }
+            frames => panic!("unexpected frames: {:?}", frames),
+        }
+        assert!(declare.try_wait().is_none());
+        conn.inject_frame(AMQPFrame::Method(
+            chan.id(),
+            AMQPClass::Exchange(exchange::AMQPMethod::DeclareOk(exchange::DeclareOk {})),
+        ))
+        .unwrap();
+        assert!(declare.wait().is_ok());
+
+        let delete = chan.exchange_delete(
+            "logs",
+            ExchangeDeleteOptions {
+                if_unused: true,
+                nowait: false,
+            },
+        );
+        match conn.peek_outgoing().as_slice() {
+            [AMQPFrame::Method(_, AMQPClass::Exchange(exchange::AMQPMethod::Delete(delete)))] => {
+                assert_eq!(delete.exchange.as_str(), "logs");
+                assert!(delete.if_unused);
+            }
+            frames => panic!("unexpected frames: {:?}", frames),
+        }
+        conn.inject_frame(AMQPFrame::Method(
+            chan.id(),
+            AMQPClass::Exchange(exchange::AMQPMethod::DeleteOk(exchange::DeleteOk {})),
+        ))
+        .unwrap();
+        assert!(delete.wait().is_ok());
+
+        // The exchange is gone now
+        let declare = chan.exchange_declare_passive("logs");
+        conn.peek_outgoing();
+        conn.inject_frame(AMQPFrame::Method(
+            chan.id(),
+            AMQPClass::Channel(channel::AMQPMethod::Close(channel::Close {
+                reply_code: AMQPSoftError::NOTFOUND.get_id(),
+                reply_text: "NOT_FOUND - no exchange 'logs' in vhost '/'".into(),
+                class_id: 40,
+                method_id: 10,
+            })),
+        ))
+        .unwrap();
+        match declare.wait() {
+            Err(error) => assert!(error.not_found(), "unexpected error: {:?}", error),
+            Ok(()) => panic!("passive declare of a missing exchange succeeded"),
+        }
+    }
+
+    #[test]
+    fn queue_declare_arguments() {
+        let _ = env_logger::try_init();
+
+        use crate::options::QueueDeclareOptions;
+        use crate::types::{AMQPValue, FieldTable};
+        use amq_protocol::protocol::queue;
+
+        let (conn, channel) = Connection::connected_channel();
+
+        let mut arguments = FieldTable::default();
+        arguments.insert("x-message-ttl".into(), AMQPValue::LongInt(60_000));
+        arguments.insert("x-max-length".into(), AMQPValue::LongInt(1_000));
+        arguments.insert(
+            "x-dead-letter-exchange".into(),
+            AMQPValue::LongString("dead-letters".into()),
+        );
+        let _ = channel.queue_declare("work", QueueDeclareOptions::default(), arguments.clone());
+        let frames = conn.peek_outgoing();
+        match frames.as_slice() {
+            [AMQPFrame::Method(_, AMQPClass::Queue(queue::AMQPMethod::Declare(declare)))] => {
+                assert_eq!(declare.arguments, arguments)
+            }
+            frames => panic!("unexpected frames: {:?}", frames),
+        }
+
+        // The arguments keep their types on the wire
+        let data = gen_frame(&frames[0])(Vec::new().into())
+            .unwrap()
+            .into_inner()
+            .0;
+        match parse_frame(data.as_slice()) {
+            Ok((
+                _,
+                AMQPFrame::Method(_, AMQPClass::Queue(queue::AMQPMethod::Declare(declare))),
+            )) => assert_eq!(declare.arguments, arguments),
+            res => panic!("unexpected parsing result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn queue_declare_quorum() {
+        let _ = env_logger::try_init();
+
+        use crate::options::QueueDeclareOptions;
+        use crate::types::{AMQPValue, FieldTable};
+        use amq_protocol::protocol::queue;
+
+        let (conn, channel) = Connection::connected_channel();
+
+        let declare = channel.queue_declare_quorum(
+            "replicated",
+            QueueDeclareOptions {
+                exclusive: true,
+                ..QueueDeclareOptions::default()
+            },
+            FieldTable::default(),
+        );
+        match declare.wait() {
+            Err(Error::InvalidQueueOptions(_)) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        assert!(conn.peek_outgoing().is_empty());
+
+        let mut arguments = FieldTable::default();
+        arguments.insert(
+            "x-dead-letter-exchange".into(),
+            AMQPValue::LongString("dead-letters".into()),
+        );
+        let _ = channel.queue_declare_quorum(
+            "replicated",
+            QueueDeclareOptions::default(),
+            arguments.clone(),
+        );
+        arguments.insert(
+            "x-queue-type".into(),
+            AMQPValue::LongString("quorum".into()),
+        );
+        match conn.peek_outgoing().as_slice() {
+            [AMQPFrame::Method(_, AMQPClass::Queue(queue::AMQPMethod::Declare(declare)))] => {
+                assert_eq!(declare.queue.as_str(), "replicated");
+                assert!(declare.durable);
+                assert_eq!(declare.arguments, arguments);
+            }
+            frames => panic!("unexpected frames: {:?}", frames),
+        }
+    }
+
+    #[test]
+    fn queue_management() {
+        let _ = env_logger::try_init();
+
+        use crate::options::{QueueBindOptions, QueueDeclareOptions, QueueDeleteOptions};
+        use crate::types::{AMQPValue, FieldTable};
+        use amq_protocol::protocol::queue;
+
+        let (conn, channel) = Connection::connected_channel();
+
+        // The server names the queue
+        let declare = channel.queue_declare(
+            "",
+            QueueDeclareOptions {
+                exclusive: true,
+                ..QueueDeclareOptions::default()
+            },
+            FieldTable::default(),
+        );
+        conn.peek_outgoing();
+        conn.inject_frame(AMQPFrame::Method(
+            channel.id(),
+            AMQPClass::Queue(queue::AMQPMethod::DeclareOk(queue::DeclareOk {
+                queue: "amq.gen-generated".into(),
+                message_count: 0,
+                consumer_count: 0,
+            })),
+        ))
+        .unwrap();
+        let queue = declare.wait().unwrap();
+        assert_eq!(queue.name().as_str(), "amq.gen-generated");
+
+        let mut arguments = FieldTable::default();
+        arguments.insert("x-match".into(), AMQPValue::LongString("all".into()));
+        let bind = channel.queue_bind(
+            queue.name().as_str(),
+            "amq.headers",
+            "",
+            QueueBindOptions::default(),
+            arguments.clone(),
+        );
+        match conn.peek_outgoing().as_slice() {
+            [AMQPFrame::Method(_, AMQPClass::Queue(queue::AMQPMethod::Bind(bind)))] => {
+                assert_eq!(bind.queue.as_str(), "amq.gen-generated");
+                assert_eq!(bind.exchange.as_str(), "amq.headers");
+                assert_eq!(bind.arguments, arguments);
+            }
+            frames => panic!("unexpected frames: {:?}", frames),
+        }
+        conn.inject_frame(AMQPFrame::Method(
+            channel.id(),
+            AMQPClass::Queue(queue::AMQPMethod::BindOk(queue::BindOk {})),
+        ))
+        .unwrap();
+        assert!(bind.wait().is_ok());
+
+        let unbind = channel.queue_unbind(queue.name().as_str(), "amq.headers", "", arguments);
+        conn.peek_outgoing();
+        conn.inject_frame(AMQPFrame::Method(
+            channel.id(),
+            AMQPClass::Queue(queue::AMQPMethod::UnbindOk(queue::UnbindOk {})),
+        ))
+        .unwrap();
+        assert!(unbind.wait().is_ok());
+
+        let delete = channel.queue_delete(
+            queue.name().as_str(),
+            QueueDeleteOptions {
+                if_unused: true,
+                if_empty: false,
+                nowait: false,
+            },
+        );
+        match conn.peek_outgoing().as_slice() {
+            [AMQPFrame::Method(_, AMQPClass::Queue(queue::AMQPMethod::Delete(delete)))] => {
+                assert!(delete.if_unused);
+                assert!(!delete.if_empty);
+            }
+            frames => panic!("unexpected frames: {:?}", frames),
+        }
+        conn.inject_frame(AMQPFrame::Method(
+            channel.id(),
+            AMQPClass::Queue(queue::AMQPMethod::DeleteOk(queue::DeleteOk {
+                message_count: 5,
+            })),
+        ))
+        .unwrap();
+        assert_eq!(delete.wait().unwrap(), 5);
+    }
+
+    #[test]
+    fn exchange_kinds() {
+        let _ = env_logger::try_init();
+
+        use crate::options::ExchangeDeclareOptions;
+        use crate::types::FieldTable;
+        use crate::ExchangeKind;
+        use amq_protocol::protocol::exchange;
+
+        let (conn, channel) = Connection::connected_channel();
+
+        for (kind, name) in vec![
+            (ExchangeKind::Direct, "direct"),
+            (ExchangeKind::Fanout, "fanout"),
+            (ExchangeKind::Headers, "headers"),
+            (ExchangeKind::Topic, "topic"),
+            (
+                ExchangeKind::Custom("x-delayed-message".into()),
+                "x-delayed-message",
+            ),
+        ] {
+            assert_eq!(kind.as_str(), name);
+            let _ = channel.exchange_declare(
+                "exchange",
+                kind,
+                ExchangeDeclareOptions::default(),
+                FieldTable::default(),
+            );
+            match conn.peek_outgoing().as_slice() {
+                [AMQPFrame::Method(
+                    _,
+                    AMQPClass::Exchange(exchange::AMQPMethod::Declare(declare)),
+                )] => assert_eq!(declare.kind.as_str(), name),
+                frames => panic!("unexpected frames: {:?}", frames),
+            }
+            conn.inject_frame(AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Exchange(exchange::AMQPMethod::DeclareOk(exchange::DeclareOk {})),
+            ))
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn queue_declare_queue_type() {
+        let _ = env_logger::try_init();
+
+        use crate::options::QueueDeclareOptions;
+        use crate::types::{QueueArguments, QueueMode, QueueType};
+        use amq_protocol::protocol::queue;
+
+        let (conn, channel) = Connection::connected_channel();
+
+        let declare = channel.queue_declare(
+            "replicated",
+            QueueDeclareOptions::default(),
+            QueueArguments::quorum()
+                .with_queue_mode(QueueMode::Lazy)
+                .into(),
+        );
+        match declare.wait() {
+            Err(Error::InvalidQueueOptions(_)) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        assert!(conn.peek_outgoing().is_empty());
+
+        let declared = channel.queue_declare(
+            "replicated",
+            QueueDeclareOptions {
+                durable: true,
+                ..QueueDeclareOptions::default()
+            },
+            QueueArguments::quorum().into(),
+        );
+        assert_eq!(conn.peek_outgoing().len(), 1);
+        let declare_ok_frame = AMQPFrame::Method(
+            channel.id(),
+            AMQPClass::Queue(queue::AMQPMethod::DeclareOk(queue::DeclareOk {
+                queue: "replicated".into(),
+                message_count: 0,
+                consumer_count: 0,
+            })),
+        );
+        conn.handle_frame(declare_ok_frame).unwrap();
+        let queue = declared.try_wait().unwrap().unwrap();
+        assert_eq!(queue.queue_type(), Some(QueueType::Quorum));
+    }
+
+    #[test]
+    fn basic_recover_unacked_deliveries() {
+        let _ = env_logger::try_init();
+
+        use crate::options::{BasicRecoverAsyncOptions, BasicRecoverOptions};
+
+        let (conn, channel, _) = Connection::channel_with_consumer("consumer-tag");
+        let consumer_tag = ShortString::from("consumer-tag");
+        let deliver = |delivery_tag| {
+            let frames = delivery_frames(channel.id(), &consumer_tag, delivery_tag, b"data");
+            assert!(conn.parse_all(&frames).1.is_ok());
+        };
+
+        deliver(1);
+        deliver(2);
+        assert_eq!(channel.unacked_count(), 2);
+        let recover = channel.basic_recover(BasicRecoverOptions { requeue: true });
+        conn.peek_outgoing();
+        // The deliveries are still to be acked until the server confirms
+        assert_eq!(channel.unacked_count(), 2);
+        conn.inject_frame(AMQPFrame::Method(
+            channel.id(),
+            AMQPClass::Basic(basic::AMQPMethod::RecoverOk(basic::RecoverOk {})),
+        ))
+        .unwrap();
+        assert!(recover.wait().is_ok());
+        assert_eq!(channel.unacked_count(), 0);
+
+        deliver(3);
+        let recover = channel.basic_recover_async(BasicRecoverAsyncOptions { requeue: true });
+        match conn.peek_outgoing().as_slice() {
+            [AMQPFrame::Method(_, AMQPClass::Basic(basic::AMQPMethod::RecoverAsync(recover)))] => {
+                assert!(recover.requeue)
+            }
+            frames => panic!("unexpected frames: {:?}", frames),
+        }
+        assert!(recover.wait().is_ok());
+        assert_eq!(channel.unacked_count(), 0);
+    }
+
+    #[test]
+    fn channel_flow_per_channel() {
+        let _ = env_logger::try_init();
+
+        use crate::options::BasicPublishOptions;
+        use amq_protocol::protocol::channel;
+
+        let (conn, paused) = Connection::connected_channel();
+        let running = conn.create_open_channel().unwrap();
+        let publish = |chan: &Channel, payload: &[u8]| {
+            let _ = chan.basic_publish(
+                "",
+                "queue",
+                BasicPublishOptions::default(),
+                payload.to_vec(),
+                BasicProperties::default(),
+            );
+        };
+        let published = |frames: Vec<AMQPFrame>| {
+            frames
+                .into_iter()
+                .filter_map(|frame| match frame {
+                    AMQPFrame::Body(id, payload) => Some((id, payload)),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        publish(&paused, b"before");
+        conn.inject_frame(AMQPFrame::Method(
+            paused.id(),
+            AMQPClass::Channel(channel::AMQPMethod::Flow(channel::Flow { active: false })),
+        ))
+        .unwrap();
+        publish(&running, b"running");
+        publish(&paused, b"paused");
+        // Only the publishes of the paused channel are held back
+        assert_eq!(
+            published(conn.peek_outgoing()),
+            vec![(running.id(), b"running".to_vec())]
+        );
+        assert!(!paused.status().flow());
+        assert!(running.status().flow());
+
+        conn.inject_frame(AMQPFrame::Method(
+            paused.id(),
+            AMQPClass::Channel(channel::AMQPMethod::Flow(channel::Flow { active: true })),
+        ))
+        .unwrap();
+        assert_eq!(
+            published(conn.peek_outgoing()),
+            vec![
+                (paused.id(), b"before".to_vec()),
+                (paused.id(), b"paused".to_vec())
+            ]
+        );
+    }
+}
@@ -1,4 +1,7 @@
-use crate::types::ShortString;
+use crate::{
+    error::CloseReason,
+    types::{Boolean, ShortString, ShortUInt},
+};
 use log::trace;
 use parking_lot::RwLock;
 use std::sync::Arc;
@@ -36,6 +39,16 @@ impl ChannelStatus {
         trace!("Publisher confirms activated");
     }
 
+    /// Whether the channel is in transactional mode, see `Channel::tx_select`
+    pub fn transactional(&self) -> bool {
+        self.inner.read().transactional
+    }
+
+    pub(crate) fn set_transactional(&self) {
+        self.inner.write().transactional = true;
+        trace!("Transactions activated");
+    }
+
     pub fn state(&self) -> ChannelState {
         self.inner.read().state.clone()
     }
@@ -44,15 +57,54 @@ impl ChannelStatus {
         self.inner.write().state = state
     }
 
+    /// Why the server closed the channel, if it did
+    pub fn close_reason(&self) -> Option<CloseReason> {
+        self.inner.read().close_reason.clone()
+    }
+
+    pub(crate) fn set_close_reason(&self, reason: CloseReason) {
+        self.inner.write().close_reason = Some(reason);
+    }
+
+    /// The last quality of service accepted by the server, if any
+    pub fn qos(&self) -> Option<Qos> {
+        self.inner.read().qos
+    }
+
+    pub(crate) fn set_qos(&self, qos: Qos) {
+        self.inner.write().qos = Some(qos);
+        trace!("Quality of service set: {:?}", qos);
+    }
+
+    /// Whether a message is being published chunk by chunk on the channel, see
+    /// `Channel::start_streaming_publish`
+    pub fn streaming(&self) -> bool {
+        self.inner.read().streaming
+    }
+
+    pub(crate) fn set_streaming(&self, streaming: bool) {
+        self.inner.write().streaming = streaming;
+    }
+
     pub(crate) fn set_send_flow(&self, flow: bool) {
         self.inner.write().send_flow = flow;
     }
 
-    pub(crate) fn flow(&self) -> bool {
+    /// Whether we can publish on the channel, the server pausing the publishes with
+    /// channel.flow, in which case they are kept until it resumes them
+    pub fn flow(&self) -> bool {
         self.inner.read().send_flow
     }
 }
 
+/// The quality of service of a channel, as set with `basic_qos`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Qos {
+    pub prefetch_count: ShortUInt,
+    /// Whether the limit is shared by all the consumers of the channel
+    pub global: Boolean,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum ChannelState {
     Initial,
@@ -74,16 +126,24 @@ impl Default for ChannelState {
 #[derive(Debug)]
 struct Inner {
     confirm: bool,
+    transactional: bool,
     send_flow: bool,
+    streaming: bool,
     state: ChannelState,
+    close_reason: Option<CloseReason>,
+    qos: Option<Qos>,
 }
 
 impl Default for Inner {
     fn default() -> Self {
         Self {
             confirm: false,
+            transactional: false,
             send_flow: true,
+            streaming: false,
             state: ChannelState::default(),
+            close_reason: None,
+            qos: None,
         }
     }
 }
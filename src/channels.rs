@@ -1,9 +1,9 @@
 use crate::{
     connection::Connection, executor::Executor, frames::Frames, id_sequence::IdSequence,
-    BasicProperties, Channel, ChannelState, Error, Result,
+    state_dump::ChannelDump, BasicProperties, Channel, ChannelState, Error, Payload, Result,
 };
-use amq_protocol::protocol::AMQPClass;
-use log::debug;
+use amq_protocol::protocol::{self, AMQPClass};
+use log::{debug, trace};
 use parking_lot::Mutex;
 use std::{collections::HashMap, sync::Arc};
 
@@ -47,6 +47,15 @@ impl Channels {
 
     pub(crate) fn receive_method(&self, id: u16, method: AMQPClass) -> Result<()> {
         if let Some(channel) = self.get(id) {
+            let closing = match method {
+                AMQPClass::Channel(protocol::channel::AMQPMethod::Close(_))
+                | AMQPClass::Channel(protocol::channel::AMQPMethod::CloseOk(_)) => false,
+                _ => Self::discards_frames(&channel),
+            };
+            if closing {
+                trace!("channel {} is closing, discarding {:?}", id, method);
+                return Ok(());
+            }
             channel.receive_method(method)
         } else {
             Err(Error::InvalidChannel(id))
@@ -60,58 +69,105 @@ impl Channels {
         properties: BasicProperties,
     ) -> Result<()> {
         if let Some(channel) = self.get(id) {
+            if Self::discards_frames(&channel) {
+                trace!("channel {} is closing, discarding content header", id);
+                return Ok(());
+            }
             channel.handle_content_header_frame(size, properties)
         } else {
             Err(Error::InvalidChannel(id))
         }
     }
 
-    pub(crate) fn handle_body_frame(&self, id: u16, payload: Vec<u8>) -> Result<()> {
+    pub(crate) fn handle_body_frame(&self, id: u16, payload: Payload) -> Result<()> {
         if let Some(channel) = self.get(id) {
+            if Self::discards_frames(&channel) {
+                trace!("channel {} is closing, discarding body frame", id);
+                return Ok(());
+            }
             channel.handle_body_frame(payload)
         } else {
             Err(Error::InvalidChannel(id))
         }
     }
 
+    /// Once channel.close has been sent, everything but channel.close and channel.close-ok
+    /// must be discarded until the server confirms it, as the frames already in flight would
+    /// otherwise fail the whole connection
+    fn discards_frames(channel: &Channel) -> bool {
+        channel.id() != 0 && channel.status().is_closing()
+    }
+
     pub(crate) fn set_closing(&self) {
         for channel in self.inner.lock().channels.values() {
             channel.set_state(ChannelState::Closing);
         }
     }
 
-    pub(crate) fn set_closed(&self) -> Result<()> {
+    /// Close every channel along with the connection, error failing their pending operations
+    pub(crate) fn set_closed(&self, error: &dyn Fn() -> Error) -> Result<()> {
         self.inner
             .lock()
             .channels
             .drain()
             .map(|(id, channel)| {
-                self.frames.clear_expected_replies(id, ChannelState::Closed);
+                self.frames.fail_expected_replies(id, error);
+                channel.fail_pending(error);
                 channel.set_state(ChannelState::Closed);
                 channel.cancel_consumers()
             })
             .fold(Ok(()), Result::and)
     }
 
-    pub(crate) fn set_error(&self) -> Result<()> {
+    /// Fail every channel along with the connection, error failing their pending operations
+    pub(crate) fn set_error(&self, error: &dyn Fn() -> Error) -> Result<()> {
         self.inner
             .lock()
             .channels
             .drain()
             .map(|(id, channel)| {
-                self.frames.clear_expected_replies(id, ChannelState::Error);
+                self.frames.fail_expected_replies(id, error);
+                channel.fail_pending(error);
                 channel.set_state(ChannelState::Error);
                 channel.error_consumers()
             })
             .fold(Ok(()), Result::and)
     }
 
-    pub(crate) fn flow(&self) -> bool {
-        self.inner
+    pub(crate) fn dump_state(&self) -> Vec<ChannelDump> {
+        let mut channels = self
+            .inner
             .lock()
             .channels
             .values()
-            .all(|c| c.status().flow())
+            .map(|channel| channel.dump_state(self.frames.expected_replies_labels(channel.id())))
+            .collect::<Vec<_>>();
+        channels.sort_by_key(|channel| channel.id);
+        channels
+    }
+
+    /// The ids of the channels in use, without the connection one (0), sorted
+    pub(crate) fn ids(&self) -> Vec<u16> {
+        let mut ids = self
+            .inner
+            .lock()
+            .channels
+            .keys()
+            .cloned()
+            .filter(|id| *id != 0)
+            .collect::<Vec<_>>();
+        ids.sort();
+        ids
+    }
+
+    /// The number of channels in use, without the connection one (0)
+    pub(crate) fn count(&self) -> usize {
+        self.inner
+            .lock()
+            .channels
+            .keys()
+            .filter(|id| **id != 0)
+            .count()
     }
 }
 
@@ -142,20 +198,49 @@ impl Inner {
 
     fn create(&mut self, connection: Connection) -> Result<Channel> {
         debug!("create channel");
-        self.channel_id
-            .set_max(connection.configuration().channel_max());
-        let first_id = self.channel_id.next();
-        let mut looped = false;
-        let mut id = first_id;
-        while !looped || id < first_id {
-            if id == 1 {
-                looped = true;
-            }
+        let channel_max = match connection.configuration().channel_max() {
+            0 => u16::max_value(),
+            channel_max => channel_max,
+        };
+        self.channel_id.set_max(channel_max);
+        // The ids of the closed channels are reused once the sequence wraps around
+        for _ in 0..channel_max {
+            let id = self.channel_id.next();
             if !self.channels.contains_key(&id) {
                 return Ok(self.create_channel(id, connection));
             }
-            id = self.channel_id.next();
         }
-        Err(Error::ChannelLimitReached)
+        Err(Error::ChannelLimitReached(channel_max))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{connection::Connection, connection_status::ConnectionState, Channel, Error};
+
+    #[test]
+    fn channel_id_recycling() {
+        let _ = env_logger::try_init();
+
+        let conn = Connection::default();
+        conn.set_state(ConnectionState::Connected);
+        conn.configuration().set_channel_max(3);
+        for _ in 0..10 {
+            let channels = (0..3)
+                .map(|_| conn.channels().create(conn.clone()).unwrap())
+                .collect::<Vec<_>>();
+            let mut ids = channels.iter().map(Channel::id).collect::<Vec<_>>();
+            ids.sort();
+            assert_eq!(ids, vec![1, 2, 3]);
+            match conn.channels().create(conn.clone()) {
+                Err(Error::ChannelLimitReached(3)) => {}
+                res => panic!("unexpected result: {:?}", res),
+            }
+            for channel in channels {
+                conn.remove_channel(channel.id()).unwrap();
+            }
+            // Only channel 0 is left
+            assert_eq!(conn.channels().dump_state().len(), 1);
+        }
     }
 }
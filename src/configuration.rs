@@ -1,5 +1,9 @@
 use parking_lot::RwLock;
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
+
+/// The bytes a frame adds around its payload: its type (1), channel (2) and size (4), and the
+/// end marker (1)
+pub(crate) const FRAME_OVERHEAD: usize = 8;
 
 #[derive(Clone, Debug, Default)]
 pub struct Configuration {
@@ -23,6 +27,19 @@ impl Configuration {
         self.inner.write().frame_max = frame_max;
     }
 
+    /// The largest payload a content body frame can carry, `frame_max` minus the 8 bytes of
+    /// overhead of a frame (1 for the type, 2 for the channel, 4 for the size and 1 for the end
+    /// marker)
+    ///
+    /// The publishes are split in body frames of this size. `usize::max_value()` until
+    /// `frame_max` is negotiated, there being no limit.
+    pub fn max_body_chunk(&self) -> usize {
+        match self.frame_max() {
+            0 => usize::max_value(),
+            frame_max => frame_max as usize - FRAME_OVERHEAD,
+        }
+    }
+
     pub fn heartbeat(&self) -> u16 {
         self.inner.read().heartbeat
     }
@@ -30,6 +47,75 @@ impl Configuration {
     pub(crate) fn set_heartbeat(&self, heartbeat: u16) {
         self.inner.write().heartbeat = heartbeat;
     }
+
+    /// The maximum number of publish frames waiting to be sent, 0 meaning unbounded
+    pub fn send_queue_capacity(&self) -> usize {
+        self.inner.read().send_queue_capacity
+    }
+
+    /// Bound the number of publish frames waiting to be sent.
+    ///
+    /// Once the limit is reached, publishing fails with `Error::SendQueueFull` or blocks until the
+    /// io loop catches up, depending on the `SendQueueFullPolicy`. Use 0 to disable the limit.
+    pub fn set_send_queue_capacity(&self, send_queue_capacity: usize) {
+        self.inner.write().send_queue_capacity = send_queue_capacity;
+    }
+
+    /// What publishing does when the send queue is full
+    pub fn send_queue_full_policy(&self) -> SendQueueFullPolicy {
+        self.inner.read().send_queue_full_policy
+    }
+
+    pub fn set_send_queue_full_policy(&self, send_queue_full_policy: SendQueueFullPolicy) {
+        self.inner.write().send_queue_full_policy = send_queue_full_policy;
+    }
+
+    /// How the frames of the different channels are ordered when sending them
+    pub fn frame_scheduling(&self) -> FrameScheduling {
+        self.inner.read().frame_scheduling
+    }
+
+    pub fn set_frame_scheduling(&self, frame_scheduling: FrameScheduling) {
+        self.inner.write().frame_scheduling = frame_scheduling;
+    }
+
+    /// The maximum size of the messages we accept to receive, 0 meaning unlimited
+    pub fn max_message_size(&self) -> usize {
+        self.inner.read().max_message_size
+    }
+
+    /// Refuse the deliveries announcing a larger body, not to let a server make us allocate
+    /// unbounded memory. Use 0 to disable the limit.
+    pub fn set_max_message_size(&self, max_message_size: usize) {
+        self.inner.write().max_message_size = max_message_size;
+    }
+
+    /// The locale negotiated with the server
+    pub fn locale(&self) -> String {
+        self.inner.read().locale.clone()
+    }
+
+    pub(crate) fn set_locale(&self, locale: String) {
+        self.inner.write().locale = locale;
+    }
+
+    /// The maximum number of frames processed in a row by the io loop, 0 meaning unlimited
+    pub fn max_frames_per_poll(&self) -> usize {
+        self.inner.read().max_frames_per_poll
+    }
+
+    pub(crate) fn set_max_frames_per_poll(&self, max_frames_per_poll: usize) {
+        self.inner.write().max_frames_per_poll = max_frames_per_poll;
+    }
+
+    /// How long the io loop waits for the connection to be open, `None` meaning forever
+    pub fn handshake_timeout(&self) -> Option<Duration> {
+        self.inner.read().handshake_timeout
+    }
+
+    pub(crate) fn set_handshake_timeout(&self, handshake_timeout: Option<Duration>) {
+        self.inner.write().handshake_timeout = handshake_timeout;
+    }
 }
 
 #[derive(Debug, Default)]
@@ -37,4 +123,64 @@ struct Inner {
     channel_max: u16,
     frame_max: u32,
     heartbeat: u16,
+    send_queue_capacity: usize,
+    send_queue_full_policy: SendQueueFullPolicy,
+    frame_scheduling: FrameScheduling,
+    max_frames_per_poll: usize,
+    locale: String,
+    max_message_size: usize,
+    handshake_timeout: Option<Duration>,
+}
+
+/// What publishing does once the send queue capacity is reached
+///
+/// `Channel::try_basic_publish` never blocks, and lapin-futures waits for room without blocking
+/// whatever the policy.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SendQueueFullPolicy {
+    /// Fail with `Error::SendQueueFull`
+    Fail,
+    /// Block the publishing thread until the io loop catches up
+    ///
+    /// Only `Channel::basic_publish` and `Channel::basic_publish_streaming` block, the other
+    /// publish methods failing with `Error::SendQueueFull`. Don't call them from async code with
+    /// this policy: the executor thread would be blocked, and with an `IoDriver` on the same
+    /// executor, the queue would never drain.
+    Block,
+}
+
+impl Default for SendQueueFullPolicy {
+    fn default() -> Self {
+        SendQueueFullPolicy::Fail
+    }
+}
+
+/// How the frames of the different channels are ordered when sending them
+///
+/// The frames of a given channel are always sent in order, and heartbeats before anything else.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameScheduling {
+    /// In the order they were queued, a channel sending a lot can delay the other ones
+    Fifo,
+    /// One frame, or one message for publishes, per channel in turn
+    RoundRobin,
+}
+
+impl Default for FrameScheduling {
+    fn default() -> Self {
+        FrameScheduling::RoundRobin
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn max_body_chunk() {
+        let configuration = Configuration::default();
+        assert_eq!(configuration.max_body_chunk(), usize::max_value());
+        configuration.set_frame_max(4096);
+        assert_eq!(configuration.max_body_chunk(), 4088);
+    }
 }
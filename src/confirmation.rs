@@ -1,19 +1,29 @@
 pub use crate::wait::NotifyReady;
 use crate::{wait::Wait, Error, Result};
-use std::fmt;
+use std::{fmt, time::Duration};
 
 #[must_use = "Confirmation should be used or you can miss errors"]
 pub struct Confirmation<T, I = ()> {
     kind: ConfirmationKind<T, I>,
+    // Only the futures integration needs to remember it completed
+    #[cfg_attr(not(feature = "futures"), allow(dead_code))]
+    terminated: bool,
 }
 
 impl<T, I> Confirmation<T, I> {
     pub(crate) fn new(wait: Wait<T>) -> Self {
         Self {
             kind: ConfirmationKind::Wait(wait),
+            terminated: false,
         }
     }
 
+    pub(crate) fn new_ok(value: T) -> Self {
+        let (wait, wait_handle) = Wait::new();
+        wait_handle.finish(value);
+        Self::new(wait)
+    }
+
     pub(crate) fn new_error(error: Error) -> Self {
         let (wait, wait_handle) = Wait::new();
         wait_handle.error(error);
@@ -25,6 +35,7 @@ impl<T, I> Confirmation<T, I> {
         self.try_wait().transpose().map(|_| ())
     }
 
+    /// Register the task to notify on completion, replacing the previous one
     pub fn subscribe(&self, task: Box<dyn NotifyReady + Send>) {
         match &self.kind {
             ConfirmationKind::Wait(wait) => wait.subscribe(task),
@@ -46,6 +57,18 @@ impl<T, I> Confirmation<T, I> {
         }
     }
 
+    /// Block until the confirmation completes or `timeout` elapses, returning `Ok(None)` in the
+    /// latter case.
+    ///
+    /// Spurious wakeups don't make this return early, and the confirmation stays pending after a
+    /// timeout, so this can be called again, or followed by `wait`.
+    pub fn wait_timeout(&self, timeout: Duration) -> Result<Option<T>> {
+        match &self.kind {
+            ConfirmationKind::Wait(wait) => wait.wait_timeout(timeout).transpose(),
+            ConfirmationKind::Map(wait, f) => wait.wait_timeout(timeout).map(|res| res.map(f)),
+        }
+    }
+
     pub fn wait(self) -> Result<T> {
         match self.kind {
             ConfirmationKind::Wait(wait) => wait.wait(),
@@ -58,10 +81,14 @@ impl<T> Confirmation<T> {
     pub(crate) fn map<M>(self, f: Box<dyn Fn(T) -> M + Send + 'static>) -> Confirmation<M, T> {
         Confirmation {
             kind: ConfirmationKind::Map(Box::new(self), f),
+            terminated: false,
         }
     }
 }
 
+// We never project the pin to our fields
+impl<T, I> Unpin for Confirmation<T, I> {}
+
 enum ConfirmationKind<T, I> {
     Wait(Wait<T>),
     Map(Box<Confirmation<I>>, Box<dyn Fn(I) -> T + Send + 'static>),
@@ -86,6 +113,7 @@ impl<T> fmt::Debug for Confirmation<T> {
 pub(crate) mod futures {
     use super::*;
 
+    use futures_core::future::FusedFuture;
     use std::{
         future::Future,
         pin::Pin,
@@ -95,11 +123,26 @@ pub(crate) mod futures {
     impl<T, I> Future for Confirmation<T, I> {
         type Output = Result<T>;
 
-        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-            if !self.has_subscriber() {
-                self.subscribe(Box::new(Watcher(cx.waker().clone())));
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            // Combinators such as select! may poll us again once we're done, stay pending
+            if self.terminated {
+                return Poll::Pending;
+            }
+            // The task may have moved since the last poll, always register the current waker
+            self.subscribe(Box::new(Watcher(cx.waker().clone())));
+            match self.try_wait() {
+                Some(res) => {
+                    self.terminated = true;
+                    Poll::Ready(res)
+                }
+                None => Poll::Pending,
             }
-            self.try_wait().map(Poll::Ready).unwrap_or(Poll::Pending)
+        }
+    }
+
+    impl<T, I> FusedFuture for Confirmation<T, I> {
+        fn is_terminated(&self) -> bool {
+            self.terminated
         }
     }
 
@@ -110,4 +153,131 @@ pub(crate) mod futures {
             self.0.wake_by_ref();
         }
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        use futures_util::task::{noop_waker, waker, ArcWake};
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        };
+
+        #[test]
+        fn poll_past_completion() {
+            let (wait, wait_handle) = Wait::new();
+            let mut confirmation = Confirmation::<u8>::new(wait);
+            let waker = noop_waker();
+            let mut cx = Context::from_waker(&waker);
+
+            assert!(!confirmation.is_terminated());
+            assert!(Pin::new(&mut confirmation).poll(&mut cx).is_pending());
+            wait_handle.finish(42);
+            match Pin::new(&mut confirmation).poll(&mut cx) {
+                Poll::Ready(Ok(42)) => {}
+                res => panic!("unexpected result: {:?}", res.map(|res| res.is_ok())),
+            }
+            assert!(confirmation.is_terminated());
+            assert!(Pin::new(&mut confirmation).poll(&mut cx).is_pending());
+            assert!(confirmation.is_terminated());
+        }
+
+        #[derive(Default)]
+        struct Wakeups(AtomicUsize);
+
+        impl ArcWake for Wakeups {
+            fn wake_by_ref(arc_self: &Arc<Self>) {
+                arc_self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        #[test]
+        fn poll_from_another_task() {
+            let (wait, wait_handle) = Wait::new();
+            let mut confirmation = Confirmation::<u8>::new(wait);
+            let first = Arc::new(Wakeups::default());
+            let second = Arc::new(Wakeups::default());
+            let first_waker = waker(first.clone());
+            let second_waker = waker(second.clone());
+
+            assert!(Pin::new(&mut confirmation)
+                .poll(&mut Context::from_waker(&first_waker))
+                .is_pending());
+            // The future moved to another task, which must be the one woken up
+            assert!(Pin::new(&mut confirmation)
+                .poll(&mut Context::from_waker(&second_waker))
+                .is_pending());
+            wait_handle.finish(42);
+            assert_eq!(first.0.load(Ordering::SeqCst), 0);
+            assert_eq!(second.0.load(Ordering::SeqCst), 1);
+            assert!(Pin::new(&mut confirmation)
+                .poll(&mut Context::from_waker(&second_waker))
+                .is_ready());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::connection::Connection;
+    use amq_protocol::frame::AMQPFrame;
+    use amq_protocol::protocol::AMQPClass;
+
+    #[test]
+    fn confirmation_wait_timeout() {
+        let _ = env_logger::try_init();
+
+        use crate::options::QueueDeclareOptions;
+        use crate::types::FieldTable;
+        use amq_protocol::protocol::queue;
+        use std::{thread, time::Duration};
+
+        let (conn, channel) = Connection::connected_channel();
+        let declare_ok_frame = |name: &str| {
+            AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Queue(queue::AMQPMethod::DeclareOk(queue::DeclareOk {
+                    queue: name.into(),
+                    message_count: 0,
+                    consumer_count: 0,
+                })),
+            )
+        };
+
+        // Times out, stays pending, and completes later on
+        let declared = channel.queue_declare(
+            "late",
+            QueueDeclareOptions::default(),
+            FieldTable::default(),
+        );
+        assert!(declared
+            .wait_timeout(Duration::from_millis(10))
+            .unwrap()
+            .is_none());
+        conn.handle_frame(declare_ok_frame("late")).unwrap();
+        let queue = declared
+            .wait_timeout(Duration::from_millis(10))
+            .unwrap()
+            .unwrap();
+        assert_eq!(queue.name().as_str(), "late");
+
+        // Completes from another thread before the deadline
+        let declared = channel.queue_declare(
+            "early",
+            QueueDeclareOptions::default(),
+            FieldTable::default(),
+        );
+        let frame = declare_ok_frame("early");
+        let handle = {
+            let conn = conn.clone();
+            thread::spawn(move || conn.handle_frame(frame))
+        };
+        let queue = declared
+            .wait_timeout(Duration::from_secs(5))
+            .unwrap()
+            .unwrap();
+        assert_eq!(queue.name().as_str(), "early");
+        handle.join().unwrap().unwrap();
+    }
 }
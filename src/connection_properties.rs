@@ -1,23 +1,84 @@
-use crate::{auth::SASLMechanism, executor::Executor, types::FieldTable};
-use std::sync::Arc;
+#[cfg(feature = "io-driver")]
+use crate::driver::Runtime;
+use crate::{
+    auth::SASLMechanism, configuration::SendQueueFullPolicy, dead_letters::DeadLetterStore,
+    executor::Executor, metrics::FrameObserver, types::FieldTable,
+};
+use std::{sync::Arc, time::Duration};
+
+/// The locale every server has to support
+pub(crate) const DEFAULT_LOCALE: &str = "en_US";
 
 #[derive(Clone, Debug)]
 pub struct ConnectionProperties {
     pub mechanism: SASLMechanism,
-    pub locale: String,
+    /// The locales to use for the server messages, by order of preference
+    ///
+    /// The first one advertised by the server is used, falling back to `en_US`.
+    pub locales: Vec<String>,
     pub client_properties: FieldTable,
+    /// Where the consumer delegates get called, off the io loop thread
+    ///
+    /// Defaults to a `DefaultExecutor` with `max_executor_threads` threads. Whatever the
+    /// executor, the deliveries of a given consumer reach its delegate one at a time, in delivery
+    /// tag order; several consumers can be called concurrently.
     pub executor: Option<Arc<dyn Executor>>,
     pub max_executor_threads: usize,
+    pub dead_letter_store: Option<Arc<dyn DeadLetterStore>>,
+    /// How many frames the io loop parses or serializes in a row before checking for other
+    /// events, 0 meaning unlimited
+    pub max_frames_per_poll: usize,
+    /// The maximum number of publish frames waiting to be sent, 0 meaning unbounded
+    pub send_queue_capacity: usize,
+    /// What publishing does once send_queue_capacity is reached
+    pub send_queue_full_policy: SendQueueFullPolicy,
+    /// The maximum size of the messages we accept to receive, 0 meaning unlimited
+    pub max_message_size: usize,
+    /// Called for every frame, starting with the handshake ones
+    pub frame_observer: Option<FrameObserver>,
+    /// How long to wait for the server to open the connection before failing with
+    /// `Error::HandshakeTimeout`, `None` meaning forever
+    pub handshake_timeout: Option<Duration>,
+    /// How many of the last frames to keep for `Connection::dump_frame_trace`, 0 disabling the
+    /// trace
+    #[cfg(feature = "frame_trace")]
+    pub frame_trace_capacity: usize,
+    /// The async runtime opening the TCP connection and running its `IoDriver`, instead of the
+    /// io loop thread
+    ///
+    /// TLS isn't handled then, `Connection::connect` failing for amqps:// URIs.
+    #[cfg(feature = "io-driver")]
+    pub runtime: Option<Arc<dyn Runtime>>,
 }
 
 impl Default for ConnectionProperties {
     fn default() -> Self {
         Self {
             mechanism: SASLMechanism::default(),
-            locale: "en_US".into(),
+            locales: vec![DEFAULT_LOCALE.into()],
             client_properties: FieldTable::default(),
             executor: None,
             max_executor_threads: 1,
+            dead_letter_store: None,
+            max_frames_per_poll: 256,
+            send_queue_capacity: 0,
+            send_queue_full_policy: SendQueueFullPolicy::default(),
+            max_message_size: 128 * 1024 * 1024,
+            frame_observer: None,
+            handshake_timeout: None,
+            #[cfg(feature = "frame_trace")]
+            frame_trace_capacity: crate::frame_trace::DEFAULT_CAPACITY,
+            #[cfg(feature = "io-driver")]
+            runtime: None,
         }
     }
 }
+
+impl ConnectionProperties {
+    /// Connect and run the connection on `runtime` instead of the io loop thread
+    #[cfg(feature = "io-driver")]
+    pub fn with_runtime<R: Runtime>(mut self, runtime: R) -> Self {
+        self.runtime = Some(Arc::new(runtime));
+        self
+    }
+}
@@ -1,4 +1,6 @@
-use crate::{auth::Credentials, wait::WaitHandle, Connection, ConnectionProperties};
+use crate::{
+    auth::Credentials, error::CloseReason, wait::WaitHandle, Connection, ConnectionProperties,
+};
 use parking_lot::RwLock;
 use std::sync::Arc;
 
@@ -12,8 +14,9 @@ impl ConnectionStatus {
         self.inner.read().state.clone()
     }
 
-    pub(crate) fn set_state(&self, state: ConnectionState) {
-        self.inner.write().state = state
+    /// Replace the state, returning the previous one
+    pub(crate) fn set_state(&self, state: ConnectionState) -> ConnectionState {
+        std::mem::replace(&mut self.inner.write().state, state)
     }
 
     pub fn vhost(&self) -> String {
@@ -32,6 +35,15 @@ impl ConnectionStatus {
         self.inner.write().username = username.into();
     }
 
+    /// Why the server closed the connection, if it did
+    pub fn close_reason(&self) -> Option<CloseReason> {
+        self.inner.read().close_reason.clone()
+    }
+
+    pub(crate) fn set_close_reason(&self, reason: Option<CloseReason>) {
+        self.inner.write().close_reason = reason;
+    }
+
     pub(crate) fn block(&self) {
         self.inner.write().blocked = true;
     }
@@ -103,6 +115,7 @@ struct Inner {
     vhost: String,
     username: String,
     blocked: bool,
+    close_reason: Option<CloseReason>,
 }
 
 impl Default for Inner {
@@ -112,6 +125,7 @@ impl Default for Inner {
             vhost: "/".into(),
             username: "guest".into(),
             blocked: false,
+            close_reason: None,
         }
     }
 }
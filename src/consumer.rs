@@ -1,18 +1,39 @@
 use crate::{
     executor::Executor,
     message::{Delivery, DeliveryResult},
-    types::ShortString,
+    types::{LongLongUInt, ShortString},
     wait::NotifyReady,
-    BasicProperties, Error, Result,
+    BasicProperties, Error, Payload, Result,
 };
-use crossbeam_channel::{Receiver, Sender};
-use log::trace;
+use crossbeam_channel::{Receiver, Sender, TrySendError};
+use log::{error, trace, warn};
 use parking_lot::{Mutex, MutexGuard};
-use std::{fmt, sync::Arc};
+use std::{
+    collections::VecDeque,
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
 
 pub trait ConsumerDelegate: Send + Sync {
     fn on_new_delivery(&self, delivery: DeliveryResult);
     fn drop_prefetched_messages(&self) {}
+    /// Called when the consumer gets cancelled, right before `on_new_delivery(Ok(None))`
+    fn on_cancel(&self, _cancelled_by: CancelledBy) {}
+}
+
+/// Who cancelled a consumer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CancelledBy {
+    /// We did, with `basic_cancel`, or because the channel or the connection got closed or
+    /// failed
+    Client,
+    /// The server did, for example because the queue got deleted, see the
+    /// `consumer_cancel_notify` capability. The consumer usually needs to be declared again.
+    Server,
 }
 
 impl<DeliveryHandler: Fn(DeliveryResult) + Send + Sync> ConsumerDelegate for DeliveryHandler {
@@ -21,6 +42,110 @@ impl<DeliveryHandler: Fn(DeliveryResult) + Send + Sync> ConsumerDelegate for Del
     }
 }
 
+/// A ready-made delegate buffering up to `capacity` deliveries, to be popped with `try_pop`
+///
+/// The deliveries received while the buffer is full are dropped and counted, see `dropped`.
+/// They won't be acknowledged, so either consume with `no_ack` or set a prefetch count no
+/// larger than the capacity with `basic_qos`. Keep a clone of it to pop the deliveries once
+/// given to `Consumer::set_delegate`.
+#[derive(Clone)]
+pub struct BufferedDelegate {
+    inner: Arc<BufferedDelegateInner>,
+}
+
+struct BufferedDelegateInner {
+    sender: Sender<Delivery>,
+    receiver: Receiver<Delivery>,
+    // The cancellation or the error, once the buffered deliveries got popped
+    end: Mutex<Option<DeliveryResult>>,
+    dropped: AtomicU64,
+}
+
+impl BufferedDelegate {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, receiver) = crossbeam_channel::bounded(capacity);
+        Self {
+            inner: Arc::new(BufferedDelegateInner {
+                sender,
+                receiver,
+                end: Mutex::new(None),
+                dropped: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// The oldest buffered delivery, if any
+    ///
+    /// Once the consumer got cancelled or failed, yields `Ok(None)` or the error after the
+    /// buffered deliveries.
+    pub fn try_pop(&self) -> Option<DeliveryResult> {
+        self.inner
+            .receiver
+            .try_recv()
+            .ok()
+            .map(|delivery| Ok(Some(delivery)))
+            .or_else(|| self.inner.end.lock().take())
+    }
+
+    /// Pop the buffered deliveries until there is none left
+    pub fn try_iter(&self) -> impl Iterator<Item = DeliveryResult> + '_ {
+        std::iter::from_fn(move || self.try_pop())
+    }
+
+    /// The number of deliveries waiting to be popped
+    pub fn len(&self) -> usize {
+        self.inner.receiver.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.receiver.is_empty()
+    }
+
+    /// The number of deliveries dropped because the buffer was full
+    pub fn dropped(&self) -> u64 {
+        self.inner.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl ConsumerDelegate for BufferedDelegate {
+    fn on_new_delivery(&self, delivery: DeliveryResult) {
+        match delivery {
+            Ok(Some(delivery)) => {
+                if let Err(TrySendError::Full(delivery)) = self.inner.sender.try_send(delivery) {
+                    warn!(
+                        "consumer buffer full, dropping delivery; delivery_tag={}",
+                        delivery.delivery_tag
+                    );
+                    self.inner.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            end => {
+                let mut current = self.inner.end.lock();
+                // Keep the error over the cancellation which follows it
+                if current.as_ref().map_or(true, Result::is_ok) {
+                    *current = Some(end);
+                }
+            }
+        }
+    }
+
+    fn drop_prefetched_messages(&self) {
+        // The server delivers them again
+        while self.inner.receiver.try_recv().is_ok() {}
+    }
+}
+
+impl fmt::Debug for BufferedDelegate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "BufferedDelegate({} buffered, {} dropped)",
+            self.len(),
+            self.dropped()
+        )
+    }
+}
+
 #[derive(Clone)]
 pub struct Consumer {
     inner: Arc<Mutex<ConsumerInner>>,
@@ -37,6 +162,21 @@ impl Consumer {
         self.inner.lock()
     }
 
+    /// The consumer tag, as generated by the server if we didn't pick one
+    pub fn tag(&self) -> ShortString {
+        self.inner().tag().clone()
+    }
+
+    /// Whether this consumer got cancelled, by us or by the server, see `cancelled_by`
+    pub fn is_cancelled(&self) -> bool {
+        self.inner().cancelled_by().is_some()
+    }
+
+    /// Who cancelled this consumer, if it got cancelled
+    pub fn cancelled_by(&self) -> Option<CancelledBy> {
+        self.inner().cancelled_by()
+    }
+
     pub fn set_delegate(&self, delegate: Box<dyn ConsumerDelegate>) {
         let mut inner = self.inner();
         while let Some(delivery) = inner.next_delivery() {
@@ -55,7 +195,7 @@ impl Consumer {
         }
     }
 
-    pub(crate) fn receive_delivery_content(&mut self, payload: Vec<u8>) {
+    pub(crate) fn receive_delivery_content(&mut self, payload: Payload) {
         if let Some(delivery) = self.inner().current_message.as_mut() {
             delivery.receive_content(payload);
         }
@@ -73,8 +213,12 @@ impl Consumer {
         self.inner().drop_prefetched_messages()
     }
 
-    pub(crate) fn cancel(&self) -> Result<()> {
-        self.inner().cancel()
+    pub(crate) fn recover(&self) -> Result<()> {
+        self.inner().recover()
+    }
+
+    pub(crate) fn cancel(&self, cancelled_by: CancelledBy) -> Result<()> {
+        self.inner().cancel(cancelled_by)
     }
 
     pub(crate) fn set_error(&self, error: Error) -> Result<()> {
@@ -89,9 +233,105 @@ pub struct ConsumerInner {
     task: Option<Box<dyn NotifyReady + Send>>,
     tag: ShortString,
     delegate: Option<Arc<Box<dyn ConsumerDelegate>>>,
+    dispatcher: DelegateDispatcher,
+    dispatched: DeliveryOrder,
+    yielded: DeliveryOrder,
+    cancelled_by: Option<CancelledBy>,
+}
+
+/// Checks that delivery tags keep increasing
+///
+/// A violation panics in debug builds and is only counted in release builds.
+#[derive(Debug, Default)]
+struct DeliveryOrder {
+    last_delivery_tag: Option<LongLongUInt>,
+    violations: u64,
+}
+
+impl DeliveryOrder {
+    fn check(&mut self, consumer_tag: &ShortString, stage: &str, delivery_tag: LongLongUInt) {
+        if let Some(last_delivery_tag) = self.last_delivery_tag {
+            if delivery_tag <= last_delivery_tag {
+                self.violations += 1;
+                error!(
+                    "delivery order violation when {} deliveries; consumer_tag={}, delivery_tag={}, previous={}",
+                    stage, consumer_tag, delivery_tag, last_delivery_tag
+                );
+                debug_assert!(
+                    false,
+                    "delivery order violation when {} deliveries: {} came after {}",
+                    stage, delivery_tag, last_delivery_tag
+                );
+            }
+        }
+        self.last_delivery_tag = Some(delivery_tag);
+    }
+
+    fn start_new_epoch(&mut self) {
+        self.last_delivery_tag = None;
+    }
+}
+
+/// Runs the delegate calls of a consumer on the executor, one at a time and in order
+///
+/// The executor may run several jobs concurrently, so only one job draining the queued calls
+/// gets spawned at a time. The deliveries thus reach the delegate in delivery tag order, off
+/// the io loop thread.
+#[derive(Clone)]
+struct DelegateDispatcher {
     executor: Arc<dyn Executor>,
+    queue: Arc<Mutex<DispatchQueue>>,
 }
 
+#[derive(Default)]
+struct DispatchQueue {
+    jobs: VecDeque<Box<dyn FnOnce() + Send>>,
+    running: bool,
+}
+
+impl DelegateDispatcher {
+    fn new(executor: Arc<dyn Executor>) -> Self {
+        Self {
+            executor,
+            queue: Default::default(),
+        }
+    }
+
+    fn dispatch(&self, job: Box<dyn FnOnce() + Send>) -> Result<()> {
+        {
+            let mut queue = self.queue.lock();
+            queue.jobs.push_back(job);
+            if queue.running {
+                return Ok(());
+            }
+            queue.running = true;
+        }
+        let queue = self.queue.clone();
+        let res = self.executor.execute(Box::new(move || loop {
+            let job = {
+                let mut queue = queue.lock();
+                match queue.jobs.pop_front() {
+                    Some(job) => job,
+                    None => {
+                        queue.running = false;
+                        return;
+                    }
+                }
+            };
+            // A panicking delegate must neither stop the drain, stalling the consumer, nor
+            // take the executor thread down
+            if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                error!("consumer delegate panicked");
+            }
+        }));
+        if res.is_err() {
+            self.queue.lock().running = false;
+        }
+        res
+    }
+}
+
+/// Block on the deliveries of a `Consumer`, until it gets cancelled
 pub struct ConsumerIterator {
     receiver: Receiver<DeliveryResult>,
 }
@@ -131,12 +371,27 @@ impl ConsumerInner {
             task: None,
             tag: consumer_tag,
             delegate: None,
-            executor,
+            dispatcher: DelegateDispatcher::new(executor),
+            dispatched: DeliveryOrder::default(),
+            yielded: DeliveryOrder::default(),
+            cancelled_by: None,
         }
     }
 
     pub fn next_delivery(&mut self) -> Option<DeliveryResult> {
-        self.deliveries_out.try_recv().ok()
+        let delivery = self.deliveries_out.try_recv().ok();
+        if let Some(Ok(Some(delivery))) = delivery.as_ref() {
+            self.yielded
+                .check(&self.tag, "yielding", delivery.delivery_tag);
+        }
+        delivery
+    }
+
+    /// The number of deliveries which were dispatched or yielded out of order
+    ///
+    /// This can only be non zero in release builds, debug builds panic instead.
+    pub fn delivery_order_violations(&self) -> u64 {
+        self.dispatched.violations + self.yielded.violations
     }
 
     pub fn set_task(&mut self, task: Box<dyn NotifyReady + Send>) {
@@ -151,11 +406,18 @@ impl ConsumerInner {
         &self.tag
     }
 
+    /// The number of deliveries received but not yet consumed
+    pub fn buffered_deliveries(&self) -> usize {
+        self.deliveries_out.len()
+    }
+
     fn new_delivery(&mut self, delivery: Delivery) -> Result<()> {
         trace!("new_delivery; consumer_tag={}", self.tag);
+        self.dispatched
+            .check(&self.tag, "dispatching", delivery.delivery_tag);
         if let Some(delegate) = self.delegate.as_ref() {
             let delegate = delegate.clone();
-            self.executor.execute(Box::new(move || {
+            self.dispatcher.dispatch(Box::new(move || {
                 delegate.on_new_delivery(Ok(Some(delivery)))
             }))?;
         } else {
@@ -170,33 +432,56 @@ impl ConsumerInner {
     }
 
     fn drop_deliveries(&mut self) {
-        while let Some(_) = self.next_delivery() {}
+        while self.next_delivery().is_some() {}
     }
 
     fn drop_prefetched_messages(&mut self) -> Result<()> {
         trace!("drop_prefetched_messages; consumer_tag={}", self.tag);
         if let Some(delegate) = self.delegate.as_ref() {
             let delegate = delegate.clone();
-            self.executor
-                .execute(Box::new(move || delegate.drop_prefetched_messages()))?;
+            self.dispatcher
+                .dispatch(Box::new(move || delegate.drop_prefetched_messages()))?;
         }
         self.drop_deliveries();
         Ok(())
     }
 
-    fn cancel(&mut self) -> Result<()> {
-        trace!("cancel; consumer_tag={}", self.tag);
+    /// The server delivers the unacked messages again after a recovery, their delivery tags
+    /// aren't compared to the ones of the previous deliveries
+    fn recover(&mut self) -> Result<()> {
+        self.drop_prefetched_messages()?;
+        self.dispatched.start_new_epoch();
+        self.yielded.start_new_epoch();
+        Ok(())
+    }
+
+    /// Who cancelled this consumer, if it got cancelled
+    pub fn cancelled_by(&self) -> Option<CancelledBy> {
+        self.cancelled_by
+    }
+
+    /// Signal that no delivery is coming anymore, after the ones already buffered
+    fn cancel(&mut self, cancelled_by: CancelledBy) -> Result<()> {
+        trace!(
+            "cancel; consumer_tag={}, cancelled_by={:?}",
+            self.tag,
+            cancelled_by
+        );
+        self.cancelled_by = Some(cancelled_by);
         if let Some(delegate) = self.delegate.as_ref() {
             let delegate = delegate.clone();
-            self.executor
-                .execute(Box::new(move || delegate.on_new_delivery(Ok(None))))?;
+            self.dispatcher.dispatch(Box::new(move || {
+                delegate.on_cancel(cancelled_by);
+                delegate.on_new_delivery(Ok(None))
+            }))?;
         } else {
             self.deliveries_in
                 .send(Ok(None))
                 .expect("failed to send cancel to consumer");
         }
-        self.drop_deliveries();
-        self.task.take();
+        if let Some(task) = self.task.take() {
+            task.notify();
+        }
         Ok(())
     }
 
@@ -204,14 +489,14 @@ impl ConsumerInner {
         trace!("set_error; consumer_tag={}", self.tag);
         if let Some(delegate) = self.delegate.as_ref() {
             let delegate = delegate.clone();
-            self.executor
-                .execute(Box::new(move || delegate.on_new_delivery(Err(error))))?;
+            self.dispatcher
+                .dispatch(Box::new(move || delegate.on_new_delivery(Err(error))))?;
         } else {
             self.deliveries_in
                 .send(Err(error))
                 .expect("failed to send error to consumer");
         }
-        self.cancel()
+        self.cancel(CancelledBy::Client)
     }
 }
 
@@ -244,9 +529,8 @@ mod futures {
                 "consumer poll; acquired inner lock, consumer_tag={}",
                 inner.tag()
             );
-            if !inner.has_task() {
-                inner.set_task(Box::new(Watcher(cx.waker().clone())));
-            }
+            // The task may have moved since the last poll, always register the current waker
+            inner.set_task(Box::new(Watcher(cx.waker().clone())));
             if let Some(delivery) = inner.next_delivery() {
                 match delivery {
                     Ok(Some(delivery)) => {
@@ -270,3 +554,113 @@ mod futures {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::{tests::delivery_frames, Connection};
+
+    #[test]
+    fn buffered_delegate() {
+        let _ = env_logger::try_init();
+
+        use crate::executor::Executor;
+
+        // Dispatch the deliveries synchronously
+        #[derive(Debug)]
+        struct InlineExecutor;
+
+        impl Executor for InlineExecutor {
+            fn execute(&self, f: Box<dyn FnOnce() + Send>) -> Result<()> {
+                f();
+                Ok(())
+            }
+        }
+
+        let (conn, channel, consumer) =
+            Connection::channel_with_consumer_on("consumer-tag", Arc::new(InlineExecutor));
+        let consumer_tag = consumer.tag();
+        let buffer = BufferedDelegate::new(2);
+        consumer.set_delegate(Box::new(buffer.clone()));
+
+        for delivery_tag in 1..=3 {
+            let frames = delivery_frames(channel.id(), &consumer_tag, delivery_tag, b"data");
+            assert!(conn.parse_all(&frames).1.is_ok());
+        }
+        assert_eq!(buffer.len(), 2);
+        assert_eq!(buffer.dropped(), 1);
+
+        conn.set_closed().unwrap();
+        let delivery_tags = buffer
+            .try_iter()
+            .map(|res| res.unwrap().map(|delivery| delivery.delivery_tag))
+            .collect::<Vec<_>>();
+        assert_eq!(delivery_tags, vec![Some(1), Some(2), None]);
+        assert!(buffer.try_pop().is_none());
+    }
+
+    #[test]
+    fn delegate_delivery_order() {
+        let _ = env_logger::try_init();
+
+        use crate::executor::DefaultExecutor;
+        use crate::message::DeliveryResult;
+        use parking_lot::Mutex;
+        use std::time::{Duration, Instant};
+
+        let (conn, channel, consumer) =
+            Connection::channel_with_consumer_on("consumer-tag", DefaultExecutor::new(4));
+        let consumer_tag = consumer.tag();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let delegate_received = received.clone();
+        consumer.set_delegate(Box::new(move |delivery: DeliveryResult| {
+            if let Ok(Some(delivery)) = delivery {
+                // Give the other executor threads a chance to overtake this one
+                std::thread::sleep(Duration::from_millis(delivery.delivery_tag % 3));
+                delegate_received.lock().push(delivery.delivery_tag);
+            }
+        }));
+
+        for delivery_tag in 1..=50 {
+            let frames = delivery_frames(channel.id(), &consumer_tag, delivery_tag, b"data");
+            assert!(conn.parse_all(&frames).1.is_ok());
+        }
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while received.lock().len() < 50 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(*received.lock(), (1..=50).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn delegate_panic() {
+        let _ = env_logger::try_init();
+
+        use crate::message::DeliveryResult;
+        use parking_lot::Mutex;
+        use std::time::{Duration, Instant};
+
+        let (conn, channel, consumer) = Connection::channel_with_consumer("consumer-tag");
+        let consumer_tag = consumer.tag();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let delegate_received = received.clone();
+        consumer.set_delegate(Box::new(move |delivery: DeliveryResult| {
+            if let Ok(Some(delivery)) = delivery {
+                if delivery.delivery_tag == 1 {
+                    panic!("delegate failure");
+                }
+                delegate_received.lock().push(delivery.delivery_tag);
+            }
+        }));
+
+        for delivery_tag in 1..=3 {
+            let frames = delivery_frames(channel.id(), &consumer_tag, delivery_tag, b"data");
+            assert!(conn.parse_all(&frames).1.is_ok());
+        }
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while received.lock().len() < 2 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(*received.lock(), vec![2, 3]);
+    }
+}
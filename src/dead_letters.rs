@@ -0,0 +1,201 @@
+use crate::{options::BasicPublishOptions, BasicProperties, Error};
+use parking_lot::Mutex;
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+/// A publish which got rejected locally, before reaching the network
+#[derive(Debug)]
+pub struct RejectedPublish {
+    pub exchange: String,
+    pub routing_key: String,
+    pub options: BasicPublishOptions,
+    pub payload: Vec<u8>,
+    pub properties: BasicProperties,
+    pub error: Error,
+    pub rejected_at: SystemTime,
+}
+
+/// Storage for the publishes rejected locally, for later inspection or retry
+pub trait DeadLetterStore: fmt::Debug + Send + Sync {
+    fn store(&self, message: RejectedPublish);
+    fn drain(&self) -> Vec<RejectedPublish>;
+}
+
+/// In-memory `DeadLetterStore` bounded by messages count, payload bytes and age
+///
+/// When a bound is reached, the oldest messages get evicted first.
+#[derive(Clone, Debug)]
+pub struct LocalDeadLetterStore {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl LocalDeadLetterStore {
+    pub fn new(max_messages: usize, max_bytes: usize, max_age: Duration) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                messages: VecDeque::default(),
+                bytes: 0,
+                max_messages,
+                max_bytes,
+                max_age,
+            })),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        let mut inner = self.inner.lock();
+        inner.evict_expired();
+        inner.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl DeadLetterStore for LocalDeadLetterStore {
+    fn store(&self, message: RejectedPublish) {
+        self.inner.lock().store(message);
+    }
+
+    fn drain(&self) -> Vec<RejectedPublish> {
+        let mut inner = self.inner.lock();
+        inner.evict_expired();
+        inner.bytes = 0;
+        inner.messages.drain(..).collect()
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    messages: VecDeque<RejectedPublish>,
+    bytes: usize,
+    max_messages: usize,
+    max_bytes: usize,
+    max_age: Duration,
+}
+
+impl Inner {
+    fn store(&mut self, message: RejectedPublish) {
+        if self.max_messages == 0 || message.payload.len() > self.max_bytes {
+            return;
+        }
+        self.evict_expired();
+        while self.messages.len() >= self.max_messages
+            || self.bytes + message.payload.len() > self.max_bytes
+        {
+            self.evict_oldest();
+        }
+        self.bytes += message.payload.len();
+        self.messages.push_back(message);
+    }
+
+    fn evict_oldest(&mut self) {
+        if let Some(message) = self.messages.pop_front() {
+            self.bytes -= message.payload.len();
+        }
+    }
+
+    fn evict_expired(&mut self) {
+        while let Some(message) = self.messages.front() {
+            let expired = message
+                .rejected_at
+                .elapsed()
+                .map(|age| age > self.max_age)
+                .unwrap_or(false);
+            if !expired {
+                break;
+            }
+            self.evict_oldest();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        channel_status::ChannelState, connection::Connection, executor::DefaultExecutor,
+        BasicProperties, Channel, Error,
+    };
+
+    fn rejected(payload: &[u8], rejected_at: SystemTime) -> RejectedPublish {
+        RejectedPublish {
+            exchange: "".into(),
+            routing_key: "queue".into(),
+            options: BasicPublishOptions::default(),
+            payload: payload.to_vec(),
+            properties: BasicProperties::default(),
+            error: Error::NotConnected,
+            rejected_at,
+        }
+    }
+
+    #[test]
+    fn bounds() {
+        let store = LocalDeadLetterStore::new(2, 10, Duration::from_secs(60));
+        let now = SystemTime::now();
+        store.store(rejected(b"a", now));
+        store.store(rejected(b"b", now));
+        store.store(rejected(b"c", now));
+        assert_eq!(store.len(), 2);
+        store.store(rejected(b"0123456789", now));
+        assert_eq!(store.len(), 1);
+        store.store(rejected(b"too large payload", now));
+        let messages = store.drain();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].payload, b"0123456789");
+        assert!(store.is_empty());
+        store.store(rejected(b"old", now - Duration::from_secs(120)));
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn basic_publish_dead_letters() {
+        let _ = env_logger::try_init();
+
+        use crate::options::BasicPublishOptions;
+        use std::time::Duration;
+
+        let store = LocalDeadLetterStore::new(10, 1024, Duration::from_secs(60));
+        let (conn, _) = Connection::connected_channel_from(Connection::new(
+            DefaultExecutor::default(),
+            Some(Arc::new(store.clone())),
+        ));
+        conn.configuration().set_frame_max(8192);
+        conn.configuration().set_send_queue_capacity(1);
+        let publish = |channel: &Channel, payload: &[u8]| {
+            channel.basic_publish(
+                "",
+                "queue",
+                BasicPublishOptions::default(),
+                payload.to_vec(),
+                BasicProperties::default(),
+            )
+        };
+        let channel = conn.channels().create(conn.clone()).unwrap();
+        // The channel is not open yet
+        assert!(publish(&channel, b"initial").try_wait().unwrap().is_err());
+        channel.set_state(ChannelState::Connected);
+        assert!(publish(&channel, b"sent").try_wait().is_none());
+        // The send queue is now full
+        assert!(publish(&channel, b"full").try_wait().unwrap().is_err());
+        // The connection got closed
+        conn.set_closed().unwrap();
+        assert!(publish(&channel, b"closed").try_wait().unwrap().is_err());
+
+        let messages = store.drain();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[0].payload, b"initial");
+        assert_eq!(messages[1].payload, b"full");
+        assert_eq!(messages[2].payload, b"closed");
+        match (&messages[0].error, &messages[1].error, &messages[2].error) {
+            (Error::NotConnected, Error::SendQueueFull, Error::ChannelClosed(_)) => {}
+            errors => panic!("unexpected rejection errors: {:?}", errors),
+        }
+    }
+}
@@ -0,0 +1,270 @@
+use crate::{confirmation::futures::Watcher, Connection, Error, FrameBatch, Progress, Result};
+use futures_io::{AsyncRead, AsyncWrite};
+use log::trace;
+use std::{
+    fmt,
+    future::Future,
+    io,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+/// How many frames are taken from the connection at once
+const FRAMES_PER_BATCH: usize = 64;
+const RECEIVE_BUFFER_SIZE: usize = 128 * 1024;
+
+/// A future completing after some time, as provided by a `Runtime`
+pub type Sleep = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// A stream to the server, as opened by a `Runtime`
+pub trait RuntimeStream: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<S: AsyncRead + AsyncWrite + Send + Unpin> RuntimeStream for S {}
+
+/// A future resolving to a TCP stream to the server, as provided by a `Runtime`
+pub type TcpConnect = Pin<Box<dyn Future<Output = io::Result<Box<dyn RuntimeStream>>> + Send>>;
+
+/// What an `IoDriver` needs from the async runtime it runs on
+///
+/// The connection itself doesn't depend on any runtime, only its driver does. An implementation
+/// for async-std is provided with the `async-std-driver` feature, other runtimes can implement
+/// it along with a futures-io compatible stream. Setting it with
+/// `ConnectionProperties::with_runtime` makes `Connection::connect` use it instead of the io
+/// loop thread.
+pub trait Runtime: fmt::Debug + Send + Sync + 'static {
+    /// Open a TCP connection to the server, without TLS
+    fn connect(&self, host: &str, port: u16) -> TcpConnect;
+
+    /// Return a future completing once `duration` elapsed, used for heartbeats
+    fn sleep(&self, duration: Duration) -> Sleep;
+
+    /// Run the `IoDriver` of a connection in the background
+    fn spawn(&self, driver: Pin<Box<dyn Future<Output = ()> + Send>>);
+}
+
+impl<R: Runtime + ?Sized> Runtime for Arc<R> {
+    fn connect(&self, host: &str, port: u16) -> TcpConnect {
+        (**self).connect(host, port)
+    }
+
+    fn sleep(&self, duration: Duration) -> Sleep {
+        (**self).sleep(duration)
+    }
+
+    fn spawn(&self, driver: Pin<Box<dyn Future<Output = ()> + Send>>) {
+        (**self).spawn(driver)
+    }
+}
+
+/// Drive a connection over a futures-io stream, instead of using the io loop thread
+///
+/// It sends the queued frames, handles the received ones and sends heartbeats, and resolves
+/// once the connection is closed. On io errors, or if nothing was received for two heartbeat
+/// intervals, the connection goes in error, failing all the pending operations. See `Connection::connect_stream`.
+pub struct IoDriver<S, R> {
+    connection: Connection,
+    stream: S,
+    runtime: R,
+    receive_buffer: Vec<u8>,
+    received: usize,
+    parse_pending: bool,
+    batch: FrameBatch,
+    flushed: bool,
+    heartbeat: Option<(Duration, Sleep)>,
+}
+
+// We never project the pin to our fields, the stream being required to be Unpin
+impl<S: Unpin, R> Unpin for IoDriver<S, R> {}
+
+impl<S: AsyncRead + AsyncWrite + Unpin, R: Runtime> IoDriver<S, R> {
+    pub(crate) fn new(connection: Connection, stream: S, runtime: R) -> Self {
+        Self {
+            connection,
+            stream,
+            runtime,
+            receive_buffer: vec![0; RECEIVE_BUFFER_SIZE],
+            received: 0,
+            parse_pending: false,
+            batch: FrameBatch::default(),
+            flushed: true,
+            heartbeat: None,
+        }
+    }
+
+    fn poll_heartbeat(&mut self, cx: &mut Context<'_>) -> Result<()> {
+        if self.heartbeat.is_none() && self.connection.status().connected() {
+            if let Some(interval) = self.connection.heartbeat_interval() {
+                trace!("io driver: start heartbeat");
+                self.heartbeat = Some((interval, self.runtime.sleep(interval)));
+            }
+        }
+        if let Some((interval, sleep)) = self.heartbeat.as_mut() {
+            while sleep.as_mut().poll(cx).is_ready() {
+                // The server sends heartbeats too, nothing for that long means it's gone
+                if Instant::now() - self.connection.last_progress() > *interval * 2 {
+                    return Err(self.connection.io_error(io::ErrorKind::TimedOut.into()));
+                }
+                trace!("io driver: send heartbeat");
+                self.connection.send_heartbeat()?;
+                *sleep = self.runtime.sleep(*interval);
+            }
+        }
+        Ok(())
+    }
+
+    /// Write as many frames as the stream accepts, returning whether anything was written
+    fn poll_write(&mut self, cx: &mut Context<'_>) -> Result<bool> {
+        let mut progress = false;
+        while !self.connection.status().blocked() {
+            if self.batch.is_empty() {
+                self.batch = self.connection.next_frames(FRAMES_PER_BATCH)?;
+                if self.batch.is_empty() {
+                    break;
+                }
+            }
+            let res = Pin::new(&mut self.stream).poll_write_vectored(cx, &self.batch.io_slices());
+            match res {
+                Poll::Ready(Ok(0)) => {
+                    return Err(self.connection.io_error(io::ErrorKind::WriteZero.into()))
+                }
+                Poll::Ready(Ok(written)) => {
+                    trace!("io driver: wrote {} bytes", written);
+                    self.connection.frames_written(&mut self.batch, written);
+                    self.flushed = false;
+                    progress = true;
+                }
+                Poll::Ready(Err(e)) => return Err(self.connection.io_error(e)),
+                Poll::Pending => break,
+            }
+        }
+        if !self.flushed {
+            match Pin::new(&mut self.stream).poll_flush(cx) {
+                Poll::Ready(Ok(())) => self.flushed = true,
+                Poll::Ready(Err(e)) => return Err(self.connection.io_error(e)),
+                Poll::Pending => {}
+            }
+        }
+        Ok(progress)
+    }
+
+    /// Read and handle the available frames, up to max_frames_per_poll per read, returning
+    /// whether anything was read or handled
+    fn poll_read(&mut self, cx: &mut Context<'_>) -> Result<bool> {
+        if self.parse_pending {
+            self.parse_received()?;
+            if self.parse_pending {
+                return Ok(true);
+            }
+        }
+        let mut progress = false;
+        loop {
+            if self.received == self.receive_buffer.len() {
+                // A frame larger than the buffer, up to frame_max
+                let size = self.receive_buffer.len() * 2;
+                self.receive_buffer.resize(size, 0);
+            }
+            let res =
+                Pin::new(&mut self.stream).poll_read(cx, &mut self.receive_buffer[self.received..]);
+            match res {
+                Poll::Ready(Ok(0)) => {
+                    if self.connection.status().closed() {
+                        return Ok(progress);
+                    }
+                    return Err(self
+                        .connection
+                        .io_error(io::ErrorKind::UnexpectedEof.into()));
+                }
+                Poll::Ready(Ok(size)) => {
+                    trace!("io driver: read {} bytes", size);
+                    self.connection.bytes_read(size);
+                    self.received += size;
+                    self.parse_received()?;
+                    progress = true;
+                    if self.parse_pending {
+                        return Ok(progress);
+                    }
+                }
+                Poll::Ready(Err(e)) => return Err(self.connection.io_error(e)),
+                Poll::Pending => return Ok(progress),
+            }
+        }
+    }
+
+    /// Handle the frames from the receive buffer, keeping what wasn't consumed
+    fn parse_received(&mut self) -> Result<()> {
+        let (consumed, progress) = match self
+            .connection
+            .handle_data(&self.receive_buffer[..self.received])
+        {
+            Ok(handled) => handled,
+            Err(Error::WouldBlock) => {
+                // No complete frame yet, wait for more data
+                self.parse_pending = false;
+                return Ok(());
+            }
+            Err(error) => return Err(error),
+        };
+        self.receive_buffer.copy_within(consumed..self.received, 0);
+        self.received -= consumed;
+        self.parse_pending = progress == Progress::Pending;
+        Ok(())
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin, R: Runtime> Future for IoDriver<S, R> {
+    type Output = Result<()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        // Subscribe first not to miss frames queued while polling
+        this.connection
+            .subscribe_frames(Box::new(Watcher(cx.waker().clone())));
+        loop {
+            this.poll_heartbeat(cx)?;
+            let wrote = this.poll_write(cx)?;
+            let read = this.poll_read(cx)?;
+            let status = this.connection.status();
+            if status.errored() {
+                return Poll::Ready(Err(Error::InvalidConnectionState(status.state())));
+            }
+            if status.closed() && this.batch.is_empty() && this.flushed {
+                trace!("io driver: connection closed");
+                return Poll::Ready(Ok(()));
+            }
+            if this.parse_pending {
+                // Let the other tasks run before handling the frames left by the work budget
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            if !wrote && !read {
+                return Poll::Pending;
+            }
+        }
+    }
+}
+
+/// Run connections on the async-std runtime
+#[cfg(feature = "async-std-driver")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AsyncStdRuntime;
+
+#[cfg(feature = "async-std-driver")]
+impl Runtime for AsyncStdRuntime {
+    fn connect(&self, host: &str, port: u16) -> TcpConnect {
+        let host = host.to_string();
+        Box::pin(async move {
+            let stream = async_std::net::TcpStream::connect((host.as_str(), port)).await?;
+            Ok(Box::new(stream) as Box<dyn RuntimeStream>)
+        })
+    }
+
+    fn sleep(&self, duration: Duration) -> Sleep {
+        Box::pin(async_std::task::sleep(duration))
+    }
+
+    fn spawn(&self, driver: Pin<Box<dyn Future<Output = ()> + Send>>) {
+        async_std::task::spawn(driver);
+    }
+}
@@ -1,5 +1,12 @@
-use crate::{channel_status::ChannelState, connection_status::ConnectionState};
-use amq_protocol::{frame::GenError, protocol::AMQPClass};
+use crate::{
+    acknowledgement::DeliveryTag, channel_status::ChannelState, connection_status::ConnectionState,
+    message::BasicReturnMessage,
+};
+use amq_protocol::{
+    frame::GenError,
+    protocol::{AMQPClass, AMQPError, AMQPSoftError},
+    types::{ShortString, ShortUInt},
+};
 use std::{error, fmt, io};
 
 /// A std Result with a lapin::Error error type
@@ -13,11 +20,34 @@ pub type Result<T> = std::result::Result<T, Error>;
 pub enum Error {
     InvalidMethod(AMQPClass),
     InvalidChannel(u16),
+    ChannelClosed(u16),
+    ChannelClosedByServer(u16, CloseReason),
+    ChannelModeConflict(u16),
+    NotTransactional(u16),
+    InvalidQueueOptions(String),
+    InvalidRoutingKey(String),
     ConnectionRefused,
+    ConnectionClosedByServer(CloseReason),
+    AuthenticationFailed(String),
+    HandshakeTimeout,
     NotConnected,
     UnexpectedReply,
     PreconditionFailed,
-    ChannelLimitReached,
+    PublishNacked(DeliveryTag),
+    MessageReturned(Box<BasicReturnMessage>),
+    BodySizeMismatch(u64, u64),
+    StreamingPublishInProgress(u16),
+    FrameTooLarge(usize, u32),
+    MessageTooLarge(u64, usize),
+    InvalidUri(String),
+    ChannelLimitReached(u16),
+    SendQueueFull,
+    ConcurrentFrameWriter,
+    /// The socket isn't ready, or not enough data was received to parse a frame: retry later
+    WouldBlock,
+    /// The buffer handed to `Connection::serialize` can't hold the next frame, which was
+    /// requeued: flush the buffer and retry
+    BufferTooSmall,
     InvalidChannelState(ChannelState),
     InvalidConnectionState(ConnectionState),
     ParsingError(String),
@@ -34,14 +64,103 @@ pub enum Error {
     __Nonexhaustive,
 }
 
+/// Why the server closed a channel or the connection
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CloseReason {
+    pub reply_code: ShortUInt,
+    pub reply_text: ShortString,
+    /// The class of the method which caused the close, 0 if none
+    pub class_id: ShortUInt,
+    /// The method which caused the close, 0 if none
+    pub method_id: ShortUInt,
+}
+
+impl CloseReason {
+    /// The AMQP error matching the reply code, if any
+    pub fn amqp_error(&self) -> Option<AMQPError> {
+        AMQPError::from_id(self.reply_code)
+    }
+}
+
+impl fmt::Display for CloseReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} (class {}, method {})",
+            self.reply_code, self.reply_text, self.class_id, self.method_id
+        )
+    }
+}
+
 impl Error {
+    /// Whether the operation can't progress yet and should be retried once the socket is ready
+    /// or more data got received
     pub fn wouldblock(&self) -> bool {
-        if let Error::IOError(e) = self {
-            e.kind() == io::ErrorKind::WouldBlock
+        match self {
+            Error::WouldBlock => true,
+            Error::IOError(e) => e.kind() == io::ErrorKind::WouldBlock,
+            _ => false,
+        }
+    }
+
+    /// Whether the buffer handed to `Connection::serialize` was too small for the next frame
+    pub fn buffer_too_small(&self) -> bool {
+        if let Error::BufferTooSmall = self {
+            true
         } else {
             false
         }
     }
+
+    /// Whether a publish was refused because the outgoing frames queue is full, in which case
+    /// it can be retried once some space got freed (see `SendQueueFullPolicy`)
+    pub fn send_queue_full(&self) -> bool {
+        if let Error::SendQueueFull = self {
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Whether the whole connection is gone, closed by the server or after an io error, a new
+    /// one being needed
+    pub fn connection_lost(&self) -> bool {
+        match self {
+            Error::IOError(e) => e.kind() != io::ErrorKind::WouldBlock,
+            Error::ConnectionRefused
+            | Error::ConnectionClosedByServer(_)
+            | Error::AuthenticationFailed(_)
+            | Error::HandshakeTimeout => true,
+            Error::InvalidConnectionState(state) => match state {
+                ConnectionState::Closing | ConnectionState::Closed | ConnectionState::Error => true,
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// Whether only the channel is gone, a new one being usable on the same connection
+    pub fn channel_lost(&self) -> bool {
+        match self {
+            Error::ChannelClosed(_) | Error::ChannelClosedByServer(..) => true,
+            Error::InvalidChannelState(state) => match state {
+                ChannelState::Closing | ChannelState::Closed | ChannelState::Error => true,
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// Whether the server closed the channel because something didn't exist, like a queue or
+    /// an exchange checked with a passive declare
+    pub fn not_found(&self) -> bool {
+        match self {
+            Error::ChannelClosedByServer(_, reason) => {
+                reason.reply_code == AMQPSoftError::NOTFOUND.get_id()
+            }
+            _ => false,
+        }
+    }
 }
 
 impl fmt::Display for Error {
@@ -49,14 +168,74 @@ impl fmt::Display for Error {
         match self {
             Error::InvalidMethod(method) => write!(f, "invalid protocol method: {:?}", method),
             Error::InvalidChannel(channel) => write!(f, "invalid channel: {}", channel),
+            Error::ChannelClosed(channel) => write!(f, "channel {} is closed", channel),
+            Error::ChannelClosedByServer(channel, reason) => {
+                write!(f, "channel {} closed by the server: {}", channel, reason)
+            }
+            Error::ChannelModeConflict(channel) => write!(
+                f,
+                "channel {} cannot use both publisher confirms and transactions",
+                channel
+            ),
+            Error::NotTransactional(channel) => {
+                write!(f, "channel {} is not in transactional mode", channel)
+            }
+            Error::InvalidQueueOptions(e) => write!(f, "invalid queue options: {}", e),
+            Error::InvalidRoutingKey(e) => write!(f, "invalid routing key: {}", e),
             Error::ConnectionRefused => write!(f, "connection refused"),
+            Error::ConnectionClosedByServer(reason) => {
+                write!(f, "connection closed by the server: {}", reason)
+            }
+            Error::AuthenticationFailed(e) => write!(f, "authentication failed: {}", e),
+            Error::HandshakeTimeout => {
+                write!(f, "timed out waiting for the server to open the connection")
+            }
             Error::NotConnected => write!(f, "not connected"),
             Error::UnexpectedReply => write!(f, "unexpected reply"),
             Error::PreconditionFailed => write!(f, "precondition failed"),
-            Error::ChannelLimitReached => write!(
+            Error::PublishNacked(delivery_tag) => {
+                write!(f, "publish nacked by the server: {}", delivery_tag)
+            }
+            Error::MessageReturned(message) => write!(
+                f,
+                "message returned by the server: {} {} (exchange: {}, routing key: {})",
+                message.reply_code,
+                message.reply_text,
+                message.delivery.exchange,
+                message.delivery.routing_key
+            ),
+            Error::ChannelLimitReached(channel_max) => write!(
+                f,
+                "The maximum number of channels for this connection has been reached ({})",
+                channel_max
+            ),
+            Error::SendQueueFull => write!(f, "the outgoing frames queue is full"),
+            Error::ConcurrentFrameWriter => {
+                write!(f, "the frames are already being sent by another writer")
+            }
+            Error::WouldBlock => write!(f, "the operation would block"),
+            Error::BufferTooSmall => write!(f, "the buffer is too small for the next frame"),
+            Error::BodySizeMismatch(expected, actual) => write!(
+                f,
+                "message body size mismatch: announced {} bytes, got {}",
+                expected, actual
+            ),
+            Error::StreamingPublishInProgress(channel) => write!(
+                f,
+                "another message is being streamed on channel {}",
+                channel
+            ),
+            Error::FrameTooLarge(size, frame_max) => write!(
                 f,
-                "The maximum number of channels for this connection has been reached"
+                "received a frame of {} bytes, larger than frame_max ({})",
+                size, frame_max
             ),
+            Error::MessageTooLarge(size, max_message_size) => write!(
+                f,
+                "received a message of {} bytes, larger than max_message_size ({})",
+                size, max_message_size
+            ),
+            Error::InvalidUri(e) => write!(f, "invalid AMQP URI: {}", e),
             Error::InvalidChannelState(state) => write!(f, "invalid channel state: {:?}", state),
             Error::InvalidConnectionState(state) => {
                 write!(f, "invalid connection state: {:?}", state)
@@ -72,6 +251,16 @@ impl fmt::Display for Error {
     }
 }
 
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        if error.kind() == io::ErrorKind::WouldBlock {
+            Error::WouldBlock
+        } else {
+            Error::IOError(error)
+        }
+    }
+}
+
 impl error::Error for Error {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match self {
@@ -81,3 +270,47 @@ impl error::Error for Error {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wouldblock() {
+        let wouldblock = Error::IOError(io::Error::from(io::ErrorKind::WouldBlock));
+        assert!(wouldblock.wouldblock());
+        assert!(Error::WouldBlock.wouldblock());
+        let reset = Error::IOError(io::Error::from(io::ErrorKind::ConnectionReset));
+        assert!(!reset.wouldblock());
+        assert!(!Error::BufferTooSmall.wouldblock());
+        assert!(!Error::ConnectionRefused.wouldblock());
+        // Only io errors, the io loops retrying them
+        assert!(!Error::SendQueueFull.wouldblock());
+    }
+
+    #[test]
+    fn send_queue_full() {
+        assert!(Error::SendQueueFull.send_queue_full());
+        let wouldblock = Error::IOError(io::Error::from(io::ErrorKind::WouldBlock));
+        assert!(!wouldblock.send_queue_full());
+        assert!(!Error::NotConnected.send_queue_full());
+    }
+
+    #[test]
+    fn from_io_error() {
+        let wouldblock = Error::from(io::Error::from(io::ErrorKind::WouldBlock));
+        assert!(match wouldblock {
+            Error::WouldBlock => true,
+            _ => false,
+        });
+        assert!(!wouldblock.connection_lost());
+        let reset = Error::from(io::Error::from(io::ErrorKind::ConnectionReset));
+        assert!(match &reset {
+            Error::IOError(e) => e.kind() == io::ErrorKind::ConnectionReset,
+            _ => false,
+        });
+        assert!(reset.connection_lost());
+        assert!(Error::BufferTooSmall.buffer_too_small());
+        assert!(!Error::BufferTooSmall.connection_lost());
+    }
+}
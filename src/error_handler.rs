@@ -1,7 +1,9 @@
+use crate::error::CloseReason;
 use parking_lot::Mutex;
 use std::{fmt, sync::Arc};
 
 type ErrorFn = Box<dyn Fn() + Send + 'static>;
+type ChannelErrorFn = Box<dyn Fn(CloseReason) + Send + 'static>;
 
 #[derive(Clone)]
 pub(crate) struct ErrorHandler {
@@ -33,3 +35,34 @@ impl fmt::Debug for ErrorHandler {
         write!(f, "ErrorHandler")
     }
 }
+
+#[derive(Clone)]
+pub(crate) struct ChannelErrorHandler {
+    handler: Arc<Mutex<Option<ChannelErrorFn>>>,
+}
+
+impl ChannelErrorHandler {
+    pub(crate) fn set_handler<E: Fn(CloseReason) + Send + 'static>(&self, handler: Box<E>) {
+        *self.handler.lock() = Some(handler);
+    }
+
+    pub(crate) fn on_error(&self, reason: CloseReason) {
+        if let Some(handler) = self.handler.lock().as_ref() {
+            handler(reason)
+        }
+    }
+}
+
+impl Default for ChannelErrorHandler {
+    fn default() -> Self {
+        Self {
+            handler: Arc::new(Mutex::new(None)),
+        }
+    }
+}
+
+impl fmt::Debug for ChannelErrorHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ChannelErrorHandler")
+    }
+}
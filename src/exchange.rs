@@ -1,5 +1,8 @@
+/// The type of an exchange, see `Channel::exchange_declare`
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum ExchangeKind {
+    /// An exchange type provided by a plugin, such as `x-delayed-message` or
+    /// `x-consistent-hash`
     Custom(String),
     Direct,
     Fanout,
@@ -14,7 +17,8 @@ impl Default for ExchangeKind {
 }
 
 impl ExchangeKind {
-    pub(crate) fn kind(&self) -> &str {
+    /// The name of the type, as sent to the server
+    pub fn as_str(&self) -> &str {
         match self {
             Self::Custom(c) => c.as_str(),
             Self::Direct => "direct",
@@ -6,6 +6,7 @@ use std::{
     thread::{Builder as ThreadBuilder, JoinHandle},
 };
 
+/// Runs the calls to the consumer delegates, see `ConnectionProperties::executor`
 pub trait Executor: std::fmt::Debug + Send + Sync {
     fn execute(&self, f: Box<dyn FnOnce() + Send>) -> Result<()>;
 }
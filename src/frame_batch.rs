@@ -0,0 +1,82 @@
+use crate::frames::{OutboundFrame, SendId, FRAME_END};
+use std::{cmp, collections::VecDeque, fmt, io::IoSlice};
+
+/// Frames serialized each in their own buffer, to be sent with a vectored write
+///
+/// Get one with `Connection::next_frames`, write its `io_slices`, then report the number of
+/// bytes written with `Connection::frames_written` until it is empty. The frames which were not
+/// written can be handed back with `Connection::requeue_frames`.
+///
+/// The payloads of the body frames aren't copied: the slices point to the published buffers.
+#[derive(Default)]
+pub struct FrameBatch {
+    /* The serialized frames, only the header of the body frames, whose payload is written from their own buffer */
+    pub(crate) frames: VecDeque<(SendId, OutboundFrame, Vec<u8>)>,
+    /// The number of bytes of the first frame which were already written
+    pub(crate) offset: usize,
+}
+
+impl FrameBatch {
+    /// The number of frames which were not fully written yet
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// The number of bytes left to write
+    pub fn remaining(&self) -> usize {
+        self.frames
+            .iter()
+            .map(|(_, frame, data)| frame_size(frame, data))
+            .sum::<usize>()
+            - self.offset
+    }
+
+    /// The buffers left to write, in order
+    pub fn io_slices(&self) -> Vec<IoSlice<'_>> {
+        self.remaining_parts(self.frames.len())
+            .into_iter()
+            .map(IoSlice::new)
+            .collect()
+    }
+
+    /// The parts of the first `count` frames which are left to write, in order
+    pub(crate) fn remaining_parts(&self, count: usize) -> Vec<&[u8]> {
+        let mut skip = self.offset;
+        let mut remaining = Vec::new();
+        for (_, frame, data) in self.frames.iter().take(count) {
+            for &part in parts(frame, data).iter() {
+                let skipped = cmp::min(skip, part.len());
+                skip -= skipped;
+                if skipped < part.len() {
+                    remaining.push(&part[skipped..]);
+                }
+            }
+        }
+        remaining
+    }
+}
+
+/// The bytes of a frame: its serialized data, or the header, payload and end of a body frame
+fn parts<'a>(frame: &'a OutboundFrame, data: &'a [u8]) -> [&'a [u8]; 3] {
+    match frame {
+        OutboundFrame::Body(_, payload) => [data, payload.as_slice(), &[FRAME_END]],
+        OutboundFrame::Frame(_) => [data, &[], &[]],
+    }
+}
+
+pub(crate) fn frame_size(frame: &OutboundFrame, data: &[u8]) -> usize {
+    parts(frame, data).iter().map(|part| part.len()).sum()
+}
+
+impl fmt::Debug for FrameBatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FrameBatch")
+            .field("frames", &self.frames.len())
+            .field("remaining", &self.remaining())
+            .finish()
+    }
+}
@@ -0,0 +1,117 @@
+use crate::{frames::redact_credentials, metrics::FrameDirection};
+use amq_protocol::frame::AMQPFrame;
+use parking_lot::Mutex;
+use std::{collections::VecDeque, sync::Arc, time::SystemTime};
+
+/// A frame recorded in the trace of a connection, see `Connection::dump_frame_trace`
+#[derive(Clone, Debug)]
+pub struct TracedFrame {
+    pub timestamp: SystemTime,
+    pub direction: FrameDirection,
+    pub frame: AMQPFrame,
+}
+
+/// The last frames sent and received, the oldest ones being dropped once full
+#[derive(Clone, Debug)]
+pub(crate) struct FrameTrace {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Default for FrameTrace {
+    fn default() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                frames: VecDeque::new(),
+                capacity: DEFAULT_CAPACITY,
+            })),
+        }
+    }
+}
+
+/// The number of frames kept unless configured otherwise
+pub(crate) const DEFAULT_CAPACITY: usize = 256;
+
+impl FrameTrace {
+    pub(crate) fn set_capacity(&self, capacity: usize) {
+        let mut inner = self.inner.lock();
+        inner.capacity = capacity;
+        inner.truncate();
+    }
+
+    /// Whether frames get recorded, not to build the ones which would be dropped
+    pub(crate) fn recording(&self) -> bool {
+        self.inner.lock().capacity != 0
+    }
+
+    pub(crate) fn record(&self, direction: FrameDirection, frame: &AMQPFrame) {
+        let mut inner = self.inner.lock();
+        if inner.capacity == 0 {
+            return;
+        }
+        inner.frames.push_back(TracedFrame {
+            timestamp: SystemTime::now(),
+            direction,
+            // Don't keep the credentials around
+            frame: redact_credentials(frame).into_owned(),
+        });
+        inner.truncate();
+    }
+
+    pub(crate) fn dump(&self) -> Vec<TracedFrame> {
+        self.inner.lock().frames.iter().cloned().collect()
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    frames: VecDeque<TracedFrame>,
+    capacity: usize,
+}
+
+impl Inner {
+    fn truncate(&mut self) {
+        while self.frames.len() > self.capacity {
+            self.frames.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::Connection;
+    use amq_protocol::protocol::{basic, AMQPClass};
+
+    #[test]
+    fn frame_trace() {
+        let _ = env_logger::try_init();
+
+        use crate::options::BasicQosOptions;
+
+        let (conn, channel) = Connection::connected_channel();
+        conn.metrics().frame_trace().set_capacity(2);
+        for prefetch_count in 1..=2 {
+            let _ = channel.basic_qos(prefetch_count, BasicQosOptions::default());
+        }
+        conn.peek_outgoing();
+        conn.inject_frame(AMQPFrame::Method(
+            channel.id(),
+            AMQPClass::Basic(basic::AMQPMethod::QosOk(basic::QosOk {})),
+        ))
+        .unwrap();
+
+        // Only the last two frames are kept
+        match conn.dump_frame_trace().as_slice() {
+            [TracedFrame {
+                direction: FrameDirection::Outbound,
+                frame: AMQPFrame::Method(_, AMQPClass::Basic(basic::AMQPMethod::Qos(qos))),
+                ..
+            }, TracedFrame {
+                direction: FrameDirection::Inbound,
+                frame: AMQPFrame::Method(_, AMQPClass::Basic(basic::AMQPMethod::QosOk(_))),
+                ..
+            }] => assert_eq!(qos.prefetch_count, 2),
+            frames => panic!("unexpected frames: {:?}", frames),
+        }
+    }
+}
@@ -1,22 +1,213 @@
 use crate::{
     channel::Reply,
     channel_status::ChannelState,
+    configuration::FrameScheduling,
     id_sequence::IdSequence,
-    wait::{Cancellable, Wait, WaitHandle},
-    Error,
+    state_dump::operation_label,
+    wait::{Cancellable, NotifyReady, Wait, WaitHandle},
+    Error, Payload,
+};
+use amq_protocol::{
+    frame::{gen_frame, AMQPFrame, GenError},
+    protocol::{connection, AMQPClass},
 };
-use amq_protocol::frame::AMQPFrame;
 use log::trace;
 use parking_lot::Mutex;
 use std::{
-    collections::{HashMap, VecDeque},
-    sync::Arc,
+    borrow::Cow,
+    collections::{HashMap, HashSet, VecDeque},
+    fmt, ptr,
+    sync::{
+        atomic::{compiler_fence, Ordering},
+        Arc,
+    },
 };
 
 pub(crate) type ExpectedReply = (Reply, Box<dyn Cancellable + Send>);
 
 pub(crate) type SendId = u64;
 
+/// The frames of a publish, each optionally followed by its header frame
+pub(crate) type PublishFrames = Vec<(OutboundFrame, Option<OutboundFrame>)>;
+
+pub(crate) const FRAME_BODY: u8 = 3;
+pub(crate) const FRAME_END: u8 = 0xCE;
+
+/// A frame waiting to be sent
+///
+/// Body frames carry a view on the published payload instead of a copy of it, which is only
+/// copied when serialized to the send buffer, or not at all with vectored writes.
+#[derive(Clone)]
+pub(crate) enum OutboundFrame {
+    Frame(AMQPFrame),
+    Body(u16, Payload),
+}
+
+impl OutboundFrame {
+    /// Copy the frame to an AMQPFrame, for the observers and the tests
+    pub(crate) fn to_frame(&self) -> AMQPFrame {
+        match self {
+            OutboundFrame::Frame(frame) => frame.clone(),
+            OutboundFrame::Body(channel_id, payload) => {
+                AMQPFrame::Body(*channel_id, payload.to_vec())
+            }
+        }
+    }
+
+    pub(crate) fn into_frame(self) -> AMQPFrame {
+        match self {
+            OutboundFrame::Frame(frame) => frame,
+            OutboundFrame::Body(channel_id, payload) => {
+                AMQPFrame::Body(channel_id, payload.into_vec())
+            }
+        }
+    }
+
+    /// Serialize the frame at the start of `buffer`, returning its size
+    pub(crate) fn serialize(&self, buffer: &mut [u8]) -> Result<usize, GenError> {
+        match self {
+            OutboundFrame::Frame(frame) => {
+                gen_frame(frame)(buffer.into()).map(|w| w.into_inner().1 as usize)
+            }
+            OutboundFrame::Body(channel_id, payload) => {
+                let size = payload.len() + 8;
+                if buffer.len() < size {
+                    return Err(GenError::BufferTooSmall(size - buffer.len()));
+                }
+                buffer[..7].copy_from_slice(&body_frame_header(*channel_id, payload.len()));
+                buffer[7..size - 1].copy_from_slice(payload);
+                buffer[size - 1] = FRAME_END;
+                Ok(size)
+            }
+        }
+    }
+
+    /// Overwrite the credentials carried by the frame, see `wipe_credentials`
+    pub(crate) fn wipe_credentials(&mut self) -> bool {
+        match self {
+            OutboundFrame::Frame(frame) => wipe_credentials(frame),
+            OutboundFrame::Body(..) => false,
+        }
+    }
+}
+
+impl From<AMQPFrame> for OutboundFrame {
+    fn from(frame: AMQPFrame) -> Self {
+        OutboundFrame::Frame(frame)
+    }
+}
+
+impl fmt::Debug for OutboundFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutboundFrame::Frame(frame) => write!(f, "{:?}", redact_credentials(frame)),
+            OutboundFrame::Body(channel_id, payload) => {
+                write!(f, "Body({}, {} bytes)", channel_id, payload.len())
+            }
+        }
+    }
+}
+
+/// The type, channel and payload size starting a body frame
+pub(crate) fn body_frame_header(channel_id: u16, payload_size: usize) -> [u8; 7] {
+    let channel_id = channel_id.to_be_bytes();
+    let payload_size = (payload_size as u32).to_be_bytes();
+    [
+        FRAME_BODY,
+        channel_id[0],
+        channel_id[1],
+        payload_size[0],
+        payload_size[1],
+        payload_size[2],
+        payload_size[3],
+    ]
+}
+
+/// The channel and payload size of the body frame at the start of `data`, if it was fully
+/// received and is well formed, for its payload to be taken without going through the parser
+pub(crate) fn body_frame(data: &[u8]) -> Option<(u16, usize)> {
+    if data.len() < 7 || data[0] != FRAME_BODY {
+        return None;
+    }
+    let payload_size = u32::from_be_bytes([data[3], data[4], data[5], data[6]]) as usize;
+    // Let the parser wait for the rest of it or report it as invalid
+    if data.len() < payload_size + 8 || data[payload_size + 7] != FRAME_END {
+        return None;
+    }
+    Some((u16::from_be_bytes([data[1], data[2]]), payload_size))
+}
+
+/// Blank the credentials carried by a frame before logging or recording it
+pub(crate) fn redact_credentials(frame: &AMQPFrame) -> Cow<'_, AMQPFrame> {
+    match frame {
+        AMQPFrame::Method(channel_id, AMQPClass::Connection(method)) => {
+            let method = match method {
+                connection::AMQPMethod::StartOk(start_ok) => {
+                    connection::AMQPMethod::StartOk(connection::StartOk {
+                        response: "".into(),
+                        ..start_ok.clone()
+                    })
+                }
+                connection::AMQPMethod::SecureOk(_) => {
+                    connection::AMQPMethod::SecureOk(connection::SecureOk {
+                        response: "".into(),
+                    })
+                }
+                connection::AMQPMethod::UpdateSecret(update_secret) => {
+                    connection::AMQPMethod::UpdateSecret(connection::UpdateSecret {
+                        new_secret: "".into(),
+                        reason: update_secret.reason.clone(),
+                    })
+                }
+                _ => return Cow::Borrowed(frame),
+            };
+            Cow::Owned(AMQPFrame::Method(
+                *channel_id,
+                AMQPClass::Connection(method),
+            ))
+        }
+        _ => Cow::Borrowed(frame),
+    }
+}
+
+/// Overwrite the credentials carried by a frame with zeroes once it has been written, returning
+/// whether it carried any
+pub(crate) fn wipe_credentials(frame: &mut AMQPFrame) -> bool {
+    match frame {
+        AMQPFrame::Method(_, AMQPClass::Connection(method)) => match method {
+            connection::AMQPMethod::StartOk(connection::StartOk { response, .. })
+            | connection::AMQPMethod::SecureOk(connection::SecureOk { response }) => {
+                wipe_string(response)
+            }
+            connection::AMQPMethod::UpdateSecret(update_secret) => {
+                wipe_string(&mut update_secret.new_secret)
+            }
+            _ => return false,
+        },
+        _ => return false,
+    }
+    true
+}
+
+/// Overwrite a secret with zeroes and empty it, its allocation being kept
+pub(crate) fn wipe_string(secret: &mut String) {
+    // Zeroes are valid UTF-8
+    wipe_bytes(unsafe { secret.as_bytes_mut() });
+    secret.clear();
+}
+
+/// Overwrite a secret with zeroes, the volatile writes not being optimized away even though the
+/// buffer is about to be freed
+pub(crate) fn wipe_bytes(secret: &mut [u8]) {
+    for byte in secret.iter_mut() {
+        unsafe { ptr::write_volatile(byte, 0) };
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
+/// The method, header and body frames of a single publish, which must be sent contiguously
+type ContentFrames = VecDeque<(SendId, OutboundFrame)>;
+
 #[derive(Clone, Debug)]
 pub(crate) enum Priority {
     NORMAL,
@@ -47,20 +238,139 @@ impl Frames {
             .push(channel_id, priority, frame, expected_reply)
     }
 
-    pub(crate) fn push_frames(
+    pub(crate) fn push_frames(&self, channel_id: u16, frames: PublishFrames) -> Wait<()> {
+        self.inner.lock().push_frames(channel_id, frames)
+    }
+
+    /// Queue the frames of a publish unless `capacity` publish frames are already waiting to be
+    /// sent, in which case they are given back
+    ///
+    /// A `capacity` of 0 means no limit.
+    pub(crate) fn try_push_frames(
+        &self,
+        channel_id: u16,
+        frames: PublishFrames,
+        capacity: usize,
+    ) -> std::result::Result<Wait<()>, PublishFrames> {
+        let mut inner = self.inner.lock();
+        if capacity != 0 && inner.publish_frames >= capacity {
+            return Err(frames);
+        }
+        Ok(inner.push_frames(channel_id, frames))
+    }
+
+    /// Queue a frame behind the content already queued on the channel, for the methods applying
+    /// to the messages published before them
+    pub(crate) fn push_after_content(
         &self,
         channel_id: u16,
-        frames: Vec<(AMQPFrame, Option<AMQPFrame>)>,
+        frame: AMQPFrame,
+        expected_reply: Option<ExpectedReply>,
     ) -> Wait<()> {
-        self.inner.lock().push_frames(channel_id, frames)
+        let mut inner = self.inner.lock();
+        inner.expect_reply(channel_id, expected_reply);
+        inner.push_frames(channel_id, vec![(frame.into(), None)])
+    }
+
+    /// Hold back the publishes of the channel while the server paused them with channel.flow
+    pub(crate) fn set_send_flow(&self, channel_id: u16, active: bool) {
+        let mut inner = self.inner.lock();
+        if active {
+            inner.paused_channels.remove(&channel_id);
+        } else {
+            inner.paused_channels.insert(channel_id);
+        }
+    }
+
+    /// Hold back the other frames of the channel while a message is streamed on it, as nothing
+    /// can come in between its content frames
+    pub(crate) fn start_streaming(&self, channel_id: u16) {
+        self.inner.lock().streams.insert(channel_id, true);
+    }
+
+    /// Release the other frames of the channel once the last frames of the stream are sent
+    pub(crate) fn end_streaming(&self, channel_id: u16) {
+        if let Some(active) = self.inner.lock().streams.get_mut(&channel_id) {
+            *active = false;
+        }
+    }
+
+    pub(crate) fn retry(&self, send_id: SendId, frame: OutboundFrame) {
+        let mut inner = self.inner.lock();
+        inner.in_flight = inner.in_flight.saturating_sub(1);
+        inner.retry(send_id, frame);
+    }
+
+    /// Pop the next frame to send, the publishes of the channels paused by the server with
+    /// channel.flow being kept for later
+    pub(crate) fn pop(&self, scheduling: FrameScheduling) -> Option<(SendId, OutboundFrame)> {
+        let mut inner = self.inner.lock();
+        let frame = inner.pop(scheduling);
+        if frame.is_some() {
+            inner.in_flight += 1;
+        }
+        frame
+    }
+
+    /// Wait until all the frames queued so far, and the ones queued in the meantime, are sent
+    pub(crate) fn drain(&self) -> Wait<()> {
+        let (wait, wait_handle) = Wait::new();
+        let mut inner = self.inner.lock();
+        if inner.drained() {
+            wait_handle.finish(());
+        } else {
+            inner.drain_waiters.push(wait_handle);
+        }
+        wait
+    }
+
+    /// The number of frames taken to be sent and not yet marked as sent or retried
+    pub(crate) fn in_flight(&self) -> usize {
+        self.inner.lock().in_flight
+    }
+
+    pub(crate) fn pending_frames(&self) -> usize {
+        let inner = self.inner.lock();
+        inner.retry_frames.len()
+            + inner.priority_frames.len()
+            + inner.frames.len()
+            + inner.publish_frames
+    }
+
+    pub(crate) fn expected_replies_labels(&self, channel_id: u16) -> Vec<String> {
+        self.inner
+            .lock()
+            .expected_replies
+            .get(&channel_id)
+            .map(|replies| {
+                replies
+                    .iter()
+                    .map(|(reply, _)| operation_label(reply))
+                    .collect()
+            })
+            .unwrap_or_default()
     }
 
-    pub(crate) fn retry(&self, send_id: SendId, frame: AMQPFrame) {
-        self.inner.lock().retry(send_id, frame);
+    pub(crate) fn pending_publish_frames(&self) -> usize {
+        self.inner.lock().publish_frames
     }
 
-    pub(crate) fn pop(&self, flow: bool) -> Option<(SendId, AMQPFrame)> {
-        self.inner.lock().pop(flow)
+    pub(crate) fn subscribe_send_queue(&self, task: Box<dyn NotifyReady + Send>) {
+        self.inner.lock().send_queue_waiters.0.push(task);
+    }
+
+    pub(crate) fn notify_send_queue(&self) {
+        self.inner.lock().send_queue_waiters.notify();
+    }
+
+    pub(crate) fn subscribe_frames(&self, task: Box<dyn NotifyReady + Send>) {
+        let mut inner = self.inner.lock();
+        inner.frames_waiter.0.clear();
+        inner.frames_waiter.0.push(task);
+    }
+
+    pub(crate) fn notify_frames(&self) {
+        self.inner.lock().frames_waiter.notify();
     }
 
     pub(crate) fn next_expected_reply(&self, channel_id: u16) -> Option<Reply> {
@@ -73,44 +383,96 @@ impl Frames {
     }
 
     pub(crate) fn mark_sent(&self, send_id: SendId) {
-        if let Some((_, send)) = self.inner.lock().outbox.remove(&send_id) {
+        let mut inner = self.inner.lock();
+        inner.in_flight = inner.in_flight.saturating_sub(1);
+        if let Some((_, send)) = inner.outbox.remove(&send_id) {
             send.finish(());
         }
+        if inner.drained() {
+            for wait_handle in inner.drain_waiters.drain(..) {
+                wait_handle.finish(());
+            }
+        }
     }
 
-    pub(crate) fn drop_pending(&self) {
-        self.inner.lock().drop_pending();
+    /// Drop the frames waiting to be sent, failing the operations waiting for a reply
+    pub(crate) fn drop_pending(&self, error: &dyn Fn() -> Error) {
+        self.inner.lock().drop_pending(error);
     }
 
     pub(crate) fn clear_expected_replies(&self, channel_id: u16, channel_state: ChannelState) {
-        self.inner
-            .lock()
-            .clear_expected_replies(channel_id, channel_state);
+        self.fail_expected_replies(channel_id, &|| {
+            Error::InvalidChannelState(channel_state.clone())
+        });
+    }
+
+    /// Fail the operations of the channel waiting to be sent or for a reply
+    pub(crate) fn fail_expected_replies(&self, channel_id: u16, error: &dyn Fn() -> Error) {
+        self.inner.lock().fail_expected_replies(channel_id, error);
     }
 }
 
 #[derive(Debug)]
 struct Inner {
-    /* Header frames must follow basic.publish frames directly, otherwise rabbitmq-server send us an UNEXPECTED_FRAME */
-    header_frames: VecDeque<(SendId, AMQPFrame)>,
-    priority_frames: VecDeque<(SendId, AMQPFrame)>,
-    frames: VecDeque<(SendId, AMQPFrame)>,
-    low_prio_frames: VecDeque<(SendId, AMQPFrame, Option<AMQPFrame>)>,
+    /* Frames which didn't fit in the send buffer must be sent before anything else to preserve ordering */
+    retry_frames: VecDeque<(SendId, OutboundFrame)>,
+    /* The publish being sent, nothing can come in between its frames, otherwise rabbitmq-server send us an UNEXPECTED_FRAME */
+    current_content: ContentFrames,
+    priority_frames: VecDeque<(SendId, OutboundFrame)>,
+    frames: ChannelQueues<(SendId, OutboundFrame)>,
+    low_prio_frames: ChannelQueues<ContentFrames>,
+    /* The number of frames in current_content and low_prio_frames */
+    publish_frames: usize,
+    /* The channels streaming a message, whose other frames are held back until the stream is over and fully sent */
+    streams: HashMap<u16, bool>,
+    /* The channels on which the server paused our publishes with channel.flow */
+    paused_channels: HashSet<u16>,
     expected_replies: HashMap<u16, VecDeque<ExpectedReply>>,
     outbox: HashMap<SendId, (u16, WaitHandle<()>)>,
     send_id: IdSequence<SendId>,
+    send_queue_waiters: Waiters,
+    /* The number of frames popped but neither marked as sent nor requeued yet */
+    in_flight: usize,
+    drain_waiters: Vec<WaitHandle<()>>,
+    /* Only one io driver polls the frames, only its last task is kept */
+    frames_waiter: Waiters,
+}
+
+#[derive(Default)]
+struct Waiters(Vec<Box<dyn NotifyReady + Send>>);
+
+impl Waiters {
+    fn notify(&mut self) {
+        for task in self.0.drain(..) {
+            task.notify();
+        }
+    }
+}
+
+impl fmt::Debug for Waiters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Waiters({})", self.0.len())
+    }
 }
 
 impl Default for Inner {
     fn default() -> Self {
         Self {
-            header_frames: VecDeque::default(),
+            retry_frames: VecDeque::default(),
+            current_content: VecDeque::default(),
             priority_frames: VecDeque::default(),
-            frames: VecDeque::default(),
-            low_prio_frames: VecDeque::default(),
+            frames: ChannelQueues::default(),
+            low_prio_frames: ChannelQueues::default(),
+            publish_frames: 0,
+            streams: HashMap::default(),
+            paused_channels: HashSet::default(),
             expected_replies: HashMap::default(),
             outbox: HashMap::default(),
             send_id: IdSequence::new(false),
+            send_queue_waiters: Waiters::default(),
+            in_flight: 0,
+            drain_waiters: Vec::default(),
+            frames_waiter: Waiters::default(),
         }
     }
 }
@@ -129,11 +491,16 @@ impl Inner {
             self.send_id.next()
         };
         match priority {
-            Priority::NORMAL => self.frames.push_back((send_id, frame)),
-            Priority::CRITICAL => self.priority_frames.push_front((send_id, frame)),
+            Priority::NORMAL => self.frames.push(channel_id, (send_id, frame.into())),
+            Priority::CRITICAL => self.priority_frames.push_front((send_id, frame.into())),
         }
         let (wait, wait_handle) = Wait::new();
         self.outbox.insert(send_id, (channel_id, wait_handle));
+        self.expect_reply(channel_id, expected_reply);
+        wait
+    }
+
+    fn expect_reply(&mut self, channel_id: u16, expected_reply: Option<ExpectedReply>) {
         if let Some(reply) = expected_reply {
             trace!(
                 "channel {} state is now waiting for {:?}",
@@ -145,24 +512,22 @@ impl Inner {
                 .or_default()
                 .push_back(reply);
         }
-        wait
     }
 
-    fn push_frames(
-        &mut self,
-        channel_id: u16,
-        mut frames: Vec<(AMQPFrame, Option<AMQPFrame>)>,
-    ) -> Wait<()> {
+    fn push_frames(&mut self, channel_id: u16, frames: PublishFrames) -> Wait<()> {
         let send_id = self.send_id.next();
         let (wait, wait_handle) = Wait::new();
-        let last_frame = frames.pop();
+        let mut content: ContentFrames = frames
+            .into_iter()
+            .flat_map(|(frame, header)| Some(frame).into_iter().chain(header))
+            .map(|frame| (0, frame))
+            .collect();
 
-        for frame in frames {
-            self.low_prio_frames.push_back((0, frame.0, frame.1));
-        }
-        if let Some(last_frame) = last_frame {
-            self.low_prio_frames
-                .push_back((send_id, last_frame.0, last_frame.1));
+        // The publish is sent once its last frame is
+        if let Some(last_frame) = content.back_mut() {
+            last_frame.0 = send_id;
+            self.publish_frames += content.len();
+            self.low_prio_frames.push(channel_id, content);
         } else {
             wait_handle.finish(());
         }
@@ -172,54 +537,88 @@ impl Inner {
         wait
     }
 
-    fn pop(&mut self, flow: bool) -> Option<(SendId, AMQPFrame)> {
-        if let Some(frame) = self
-            .header_frames
-            .pop_front()
-            .or_else(|| self.priority_frames.pop_front())
-            .or_else(|| self.frames.pop_front())
-        {
+    fn pop(&mut self, scheduling: FrameScheduling) -> Option<(SendId, OutboundFrame)> {
+        if let Some(frame) = self.retry_frames.pop_front() {
             return Some(frame);
         }
-        if flow {
-            if let Some(mut frame) = self.low_prio_frames.pop_front() {
-                if let Some(next_frame) = frame.2 {
-                    self.header_frames.push_back((frame.0, next_frame));
-                    frame.0 = 0;
-                }
-                return Some((frame.0, frame.1));
-            }
+        if let Some(frame) = self.current_content.pop_front() {
+            self.publish_frames -= 1;
+            self.send_queue_waiters.notify();
+            return Some(frame);
+        }
+        if !self.streams.is_empty() {
+            let low_prio_frames = &self.low_prio_frames;
+            self.streams
+                .retain(|channel_id, active| *active || low_prio_frames.contains(*channel_id));
+        }
+        if let Some(frame) = self.priority_frames.pop_front() {
+            return Some(frame);
+        }
+        let streams = &self.streams;
+        if let Some(frame) = self.frames.pop(
+            scheduling,
+            |frame| frame.0,
+            |channel_id| !streams.contains_key(&channel_id),
+        ) {
+            return Some(frame);
+        }
+        let paused_channels = &self.paused_channels;
+        if let Some(content) = self.low_prio_frames.pop(
+            scheduling,
+            |content| content.back().map(|frame| frame.0).unwrap_or_default(),
+            |channel_id| !paused_channels.contains(&channel_id),
+        ) {
+            self.current_content = content;
+            return self.pop(scheduling);
         }
         None
     }
 
-    fn retry(&mut self, send_id: SendId, frame: AMQPFrame) {
-        if let AMQPFrame::Header(..) = &frame {
-            self.header_frames.push_front((send_id, frame));
-        } else {
-            self.priority_frames.push_back((send_id, frame));
-        }
+    fn retry(&mut self, send_id: SendId, frame: OutboundFrame) {
+        self.retry_frames.push_front((send_id, frame));
+    }
+
+    fn drained(&self) -> bool {
+        self.in_flight == 0
+            && self.retry_frames.is_empty()
+            && self.current_content.is_empty()
+            && self.priority_frames.is_empty()
+            && self.frames.len() == 0
+            && self.low_prio_frames.len() == 0
     }
 
-    fn drop_pending(&mut self) {
-        self.header_frames.clear();
+    fn drop_pending(&mut self, error: &dyn Fn() -> Error) {
+        self.retry_frames.clear();
+        self.current_content.clear();
         self.priority_frames.clear();
         self.frames.clear();
         self.low_prio_frames.clear();
+        self.publish_frames = 0;
+        self.streams.clear();
+        self.paused_channels.clear();
+        self.send_queue_waiters.notify();
         for (_, replies) in self.expected_replies.drain() {
-            Self::cancel_expected_replies(replies, ChannelState::Closed);
+            for (_, cancel) in replies {
+                cancel.cancel(error());
+            }
         }
         for (_, (_, wait_handle)) in self.outbox.drain() {
             wait_handle.finish(());
         }
+        self.in_flight = 0;
+        for wait_handle in self.drain_waiters.drain(..) {
+            wait_handle.finish(());
+        }
     }
 
-    fn clear_expected_replies(&mut self, channel_id: u16, channel_state: ChannelState) {
+    fn fail_expected_replies(&mut self, channel_id: u16, error: &dyn Fn() -> Error) {
+        self.streams.remove(&channel_id);
+        self.paused_channels.remove(&channel_id);
         let mut outbox = HashMap::default();
 
         for (send_id, (chan_id, wait_handle)) in self.outbox.drain() {
             if chan_id == channel_id {
-                wait_handle.error(Error::InvalidChannelState(channel_state.clone()))
+                wait_handle.error(error())
             } else {
                 outbox.insert(send_id, (chan_id, wait_handle));
             }
@@ -228,13 +627,368 @@ impl Inner {
         self.outbox = outbox;
 
         if let Some(replies) = self.expected_replies.remove(&channel_id) {
-            Self::cancel_expected_replies(replies, channel_state);
+            for (_, cancel) in replies {
+                cancel.cancel(error());
+            }
+        }
+    }
+}
+
+/// Items waiting to be sent, queued per channel
+#[derive(Debug)]
+struct ChannelQueues<T> {
+    queues: HashMap<u16, VecDeque<T>>,
+    /* The channels with something queued, in the order they get served */
+    order: VecDeque<u16>,
+    len: usize,
+}
+
+impl<T> Default for ChannelQueues<T> {
+    fn default() -> Self {
+        Self {
+            queues: HashMap::default(),
+            order: VecDeque::default(),
+            len: 0,
         }
     }
+}
 
-    fn cancel_expected_replies(replies: VecDeque<ExpectedReply>, channel_state: ChannelState) {
-        for (_, cancel) in replies {
-            cancel.cancel(Error::InvalidChannelState(channel_state.clone()));
+impl<T> ChannelQueues<T> {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn contains(&self, channel_id: u16) -> bool {
+        self.queues
+            .get(&channel_id)
+            .map_or(false, |queue| !queue.is_empty())
+    }
+
+    fn push(&mut self, channel_id: u16, item: T) {
+        let queue = self.queues.entry(channel_id).or_default();
+        if queue.is_empty() {
+            self.order.push_back(channel_id);
+        }
+        queue.push_back(item);
+        self.len += 1;
+    }
+
+    /// Pop the next item of the channels which are ready, send_id giving the order in which the
+    /// items were queued
+    ///
+    /// Whatever the scheduling, items from channel 0 are only popped once everything queued
+    /// before them was, so that closing the connection doesn't overtake pending frames.
+    fn pop<F: Fn(&T) -> SendId, R: Fn(u16) -> bool>(
+        &mut self,
+        scheduling: FrameScheduling,
+        send_id: F,
+        ready: R,
+    ) -> Option<T> {
+        let oldest = |queues: &HashMap<u16, VecDeque<T>>, order: &VecDeque<u16>| {
+            order
+                .iter()
+                .enumerate()
+                .filter(|(_, channel_id)| ready(**channel_id))
+                .min_by_key(|(_, channel_id)| queues[channel_id].front().map(&send_id))
+                .map(|(idx, _)| idx)
+        };
+        let idx = match scheduling {
+            FrameScheduling::Fifo => oldest(&self.queues, &self.order)?,
+            FrameScheduling::RoundRobin => {
+                match self.order.iter().position(|channel_id| ready(*channel_id)) {
+                    Some(idx) if self.order[idx] == 0 => oldest(&self.queues, &self.order)?,
+                    Some(idx) => idx,
+                    None => return None,
+                }
+            }
+        };
+        let channel_id = self.order.remove(idx)?;
+        let queue = self.queues.get_mut(&channel_id)?;
+        let item = queue.pop_front()?;
+        self.len -= 1;
+        if queue.is_empty() {
+            self.queues.remove(&channel_id);
+        } else {
+            // Go to the back of the line
+            self.order.push_back(channel_id);
+        }
+        Some(item)
+    }
+
+    fn clear(&mut self) {
+        self.queues.clear();
+        self.order.clear();
+        self.len = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OutboundFrame;
+    use crate::{
+        channel_status::ChannelState,
+        connection::{tests::delivery_frames, Connection},
+        BasicProperties, Error, Payload, SerializeOutcome,
+    };
+    use amq_protocol::{
+        frame::{gen_frame, parse_frame, AMQPFrame, GenError},
+        protocol::{basic, connection, AMQPClass},
+    };
+
+    #[test]
+    fn body_frame_serialization() {
+        let _ = env_logger::try_init();
+
+        let payload = Payload::from(vec![42; 100]);
+        let frame = OutboundFrame::Body(3, payload.slice(10, 60));
+        let mut buffer = [0; 64];
+        match frame.serialize(&mut buffer[..40]) {
+            Err(GenError::BufferTooSmall(18)) => {}
+            res => panic!("unexpected serialization result: {:?}", res),
+        }
+        let size = frame.serialize(&mut buffer).unwrap();
+        let expected = gen_frame(&AMQPFrame::Body(3, vec![42; 50]))(Vec::new().into())
+            .unwrap()
+            .into_inner()
+            .0;
+        assert_eq!(&buffer[..size], expected.as_slice());
+        assert_eq!(super::body_frame(&buffer[..size]), Some((3, 50)));
+        // Incomplete
+        assert_eq!(super::body_frame(&buffer[..size - 1]), None);
+    }
+
+    #[test]
+    fn wipe_update_secret() {
+        let _ = env_logger::try_init();
+
+        let mut frame = AMQPFrame::Method(
+            0,
+            AMQPClass::Connection(connection::AMQPMethod::UpdateSecret(
+                connection::UpdateSecret {
+                    new_secret: "new-token".into(),
+                    reason: "token refresh".into(),
+                },
+            )),
+        );
+        assert!(super::wipe_credentials(&mut frame));
+        match frame {
+            AMQPFrame::Method(
+                0,
+                AMQPClass::Connection(connection::AMQPMethod::UpdateSecret(update_secret)),
+            ) => {
+                assert!(update_secret.new_secret.is_empty());
+                assert_eq!(update_secret.reason.as_str(), "token refresh");
+                // The allocation is kept, the secret having been overwritten in place
+                let secret = unsafe {
+                    std::slice::from_raw_parts(update_secret.new_secret.as_ptr(), "new-token".len())
+                };
+                assert!(secret.iter().all(|byte| *byte == 0));
+            }
+            frame => panic!("unexpected frame: {:?}", frame),
+        }
+        assert!(!super::wipe_credentials(&mut AMQPFrame::Heartbeat(0)));
+    }
+
+    #[test]
+    fn requeued_frames_ordering() {
+        let _ = env_logger::try_init();
+
+        use crate::options::{BasicPublishOptions, BasicQosOptions};
+
+        let (conn, channel) = Connection::connected_channel();
+        conn.configuration().set_frame_max(8192);
+        let _ = channel.basic_publish(
+            "",
+            "queue",
+            BasicPublishOptions::default(),
+            b"payload".to_vec(),
+            BasicProperties::default(),
+        );
+        let _ = channel.basic_qos(10, BasicQosOptions::default());
+
+        let mut tiny_buffer = [0; 8];
+        let mut big_buffer = [0; 8192];
+        let mut frames = Vec::new();
+        loop {
+            match conn.serialize(&mut tiny_buffer) {
+                Err(Error::BufferTooSmall) => {}
+                Ok(SerializeOutcome::Idle) => break,
+                res => panic!("unexpected serialization result: {:?}", res),
+            }
+            // The frame we just requeued must be the next one
+            let size = match conn.serialize(&mut big_buffer).unwrap() {
+                SerializeOutcome::Wrote(size) => size,
+                outcome => panic!("unexpected serialization outcome: {:?}", outcome),
+            };
+            match parse_frame(&big_buffer[..size]) {
+                Ok((_, frame)) => frames.push(frame),
+                Err(e) => panic!("failed to parse the serialized frame: {:?}", e),
+            }
+        }
+        assert_eq!(frames.len(), 4);
+        match &frames[0] {
+            AMQPFrame::Method(_, AMQPClass::Basic(basic::AMQPMethod::Qos(_))) => {}
+            frame => panic!("unexpected frame: {:?}", frame),
+        }
+        match &frames[1] {
+            AMQPFrame::Method(_, AMQPClass::Basic(basic::AMQPMethod::Publish(_))) => {}
+            frame => panic!("unexpected frame: {:?}", frame),
+        }
+        match &frames[2] {
+            AMQPFrame::Header(..) => {}
+            frame => panic!("unexpected frame: {:?}", frame),
+        }
+        match &frames[3] {
+            AMQPFrame::Body(_, body) => assert_eq!(body.as_slice(), b"payload"),
+            frame => panic!("unexpected frame: {:?}", frame),
+        }
+    }
+
+    #[test]
+    fn content_frames_contiguous() {
+        let _ = env_logger::try_init();
+
+        use crate::options::{BasicPublishOptions, BasicQosOptions};
+
+        let (conn, channel) = Connection::connected_channel();
+        conn.configuration().set_frame_max(4096);
+        let _ = channel.basic_publish(
+            "",
+            "queue",
+            BasicPublishOptions::default(),
+            vec![42; 10_000],
+            BasicProperties::default(),
+        );
+        let mut frames = Vec::new();
+        for _ in 0..3 {
+            let (send_id, frame) = conn.next_frame().unwrap();
+            conn.mark_sent(send_id);
+            frames.push(frame);
+        }
+        // The second body frame doesn't fit in the send buffer
+        let (send_id, frame) = conn.next_frame().unwrap();
+        conn.requeue_frame(send_id, frame.into()).unwrap();
+        // Meanwhile, a heartbeat is due and another method gets sent on the same channel
+        conn.send_heartbeat().unwrap();
+        let _ = channel.basic_qos(10, BasicQosOptions::default());
+        while let Some((send_id, frame)) = conn.next_frame() {
+            conn.mark_sent(send_id);
+            frames.push(frame);
+        }
+
+        assert_eq!(frames.len(), 7);
+        match &frames[0] {
+            AMQPFrame::Method(_, AMQPClass::Basic(basic::AMQPMethod::Publish(_))) => {}
+            frame => panic!("unexpected frame: {:?}", frame),
+        }
+        match &frames[1] {
+            AMQPFrame::Header(..) => {}
+            frame => panic!("unexpected frame: {:?}", frame),
+        }
+        let body_sizes = frames[2..5]
+            .iter()
+            .map(|frame| match frame {
+                AMQPFrame::Body(_, payload) => payload.len(),
+                frame => panic!("unexpected frame: {:?}", frame),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(body_sizes, vec![4088, 4088, 1824]);
+        match &frames[5] {
+            AMQPFrame::Heartbeat(_) => {}
+            frame => panic!("unexpected frame: {:?}", frame),
+        }
+        match &frames[6] {
+            AMQPFrame::Method(_, AMQPClass::Basic(basic::AMQPMethod::Qos(_))) => {}
+            frame => panic!("unexpected frame: {:?}", frame),
+        }
+    }
+
+    #[test]
+    fn streaming_publish_frames_ordering() {
+        let _ = env_logger::try_init();
+
+        use crate::options::{BasicAckOptions, BasicPublishOptions};
+
+        let (conn, channel, consumer) = Connection::channel_with_consumer("consumer-tag");
+        conn.configuration().set_frame_max(8192);
+        let consumer_tag = consumer.tag();
+
+        // A delivery is being received when the stream starts
+        let data = delivery_frames(channel.id(), &consumer_tag, 1, b"data");
+        let body_start = data.len() - 12;
+        let (consumed, res) = conn.parse_all(&data[..body_start]);
+        assert_eq!(consumed, body_start);
+        assert!(res.is_ok());
+        let mut publish = channel
+            .start_streaming_publish(
+                "",
+                "queue",
+                BasicPublishOptions::default(),
+                4,
+                BasicProperties::default(),
+            )
+            .unwrap();
+        assert!(channel.status().streaming());
+        let (consumed, res) = conn.parse_all(&data[body_start..]);
+        assert_eq!(consumed, 12);
+        assert!(res.is_ok());
+        assert_eq!(consumer.inner().buffered_deliveries(), 1);
+        assert_eq!(channel.status().state(), ChannelState::Connected);
+        assert!(channel.status().streaming());
+
+        // The ack waits for the end of the streamed message
+        let _ = channel.basic_ack(1, BasicAckOptions::default());
+        match conn.peek_outgoing().as_slice() {
+            [AMQPFrame::Method(_, AMQPClass::Basic(basic::AMQPMethod::Publish(_))), AMQPFrame::Header(..)] =>
+                {}
+            frames => panic!("unexpected frames: {:?}", frames),
+        }
+        publish.send_chunk(b"da").unwrap();
+        match conn.peek_outgoing().as_slice() {
+            [AMQPFrame::Body(..)] => {}
+            frames => panic!("unexpected frames: {:?}", frames),
+        }
+        publish.send_chunk(b"ta").unwrap();
+        let published = publish.finish();
+        assert!(!channel.status().streaming());
+        match conn.peek_outgoing().as_slice() {
+            [AMQPFrame::Body(..), AMQPFrame::Method(_, AMQPClass::Basic(basic::AMQPMethod::Ack(_)))] =>
+                {}
+            frames => panic!("unexpected frames: {:?}", frames),
+        }
+        assert!(published.try_wait().unwrap().is_ok());
+    }
+
+    #[test]
+    fn frame_scheduling_fairness() {
+        let _ = env_logger::try_init();
+
+        use crate::configuration::FrameScheduling;
+        use crate::options::{BasicAckOptions, BasicQosOptions};
+
+        for (scheduling, ack_position) in &[
+            (FrameScheduling::RoundRobin, 1),
+            (FrameScheduling::Fifo, 10_000),
+        ] {
+            let (conn, flooding) = Connection::connected_channel();
+            conn.configuration().set_frame_scheduling(*scheduling);
+            let acking = conn.create_open_channel().unwrap();
+            for _ in 0..10_000 {
+                let _ = flooding.basic_qos(10, BasicQosOptions::default());
+            }
+            let _ = acking.basic_ack(1, BasicAckOptions::default());
+
+            let mut position = 0;
+            while let Some((send_id, frame)) = conn.next_frame() {
+                conn.mark_sent(send_id);
+                if let AMQPFrame::Method(channel_id, _) = frame {
+                    if channel_id == acking.id() {
+                        break;
+                    }
+                }
+                position += 1;
+            }
+            assert_eq!(position, *ack_position, "{:?}", scheduling);
         }
     }
 }
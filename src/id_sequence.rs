@@ -20,6 +20,11 @@ impl<T: Default + Copy + AddAssign<T> + PartialEq<T> + PartialOrd<T> + From<u8>>
     pub(crate) fn set_max(&self, max: T) {
         self.inner.lock().set_max(max)
     }
+
+    /// Hand out the given id again on the next call to next, it must be the last one returned
+    pub(crate) fn rollback(&self, id: T) {
+        self.inner.lock().id = id;
+    }
 }
 
 #[derive(Debug)]
@@ -48,22 +53,25 @@ impl<T: Default + Copy + AddAssign<T> + PartialEq<T> + PartialOrd<T> + From<u8>>
 
     // FIXME: use Step trait once stable (https://github.com/rust-lang/rust/issues/42168)
     fn next(&mut self) -> T {
+        if !self.check_max() {
+            self.id = self.zero;
+        }
         if !self.allow_zero && self.id == self.zero {
             self.id += self.one;
         }
-        if self.check_max() {
-            let id = self.id;
-            self.id += self.one;
-            id
-        } else {
+        let id = self.id;
+        // max is included, wrap around instead of going past it
+        if self.max == Some(id) {
             self.id = self.zero;
-            self.next()
+        } else {
+            self.id += self.one;
         }
+        id
     }
 
     fn check_max(&self) -> bool {
         if let Some(max) = self.max {
-            self.id < max
+            self.id <= max
         } else {
             true
         }
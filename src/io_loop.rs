@@ -1,7 +1,9 @@
 use crate::{
-    buffer::Buffer, connection::Connection, connection_status::ConnectionState, Error, Result,
+    buffer::Buffer,
+    connection::{Connection, Progress, SerializeOutcome},
+    connection_status::ConnectionState,
+    Error, Result,
 };
-use amq_protocol::frame::{gen_frame, parse_frame, AMQPFrame, GenError, Offset};
 use log::{error, trace};
 use mio::{Evented, Events, Poll, PollOpt, Ready, Registration, SetReadiness, Token};
 use parking_lot::Mutex;
@@ -20,33 +22,51 @@ const DATA: Token = Token(2);
 const CONTINUE: Token = Token(3);
 
 const FRAMES_STORAGE: usize = 32;
+/// The deliveries share the receive buffer, which gets replaced if they still do on the next
+/// read: it only holds a frame and the start of the next one, not to keep too much memory alive
+const RECEIVE_FRAMES_STORAGE: usize = 2;
 
 type ThreadHandle = JoinHandle<Result<()>>;
 
 #[derive(Clone, Debug)]
 pub(crate) struct IoLoopHandle {
     handle: Arc<Mutex<Option<ThreadHandle>>>,
+    // Whether an io loop ever got started, the handle being taken once waited for
+    started: Arc<AtomicBool>,
 }
 
 impl Default for IoLoopHandle {
     fn default() -> Self {
         Self {
             handle: Arc::new(Mutex::new(None)),
+            started: Arc::new(AtomicBool::new(false)),
         }
     }
 }
 
 impl IoLoopHandle {
     pub(crate) fn register(&self, handle: JoinHandle<Result<()>>) {
+        self.started.store(true, Ordering::SeqCst);
         *self.handle.lock() = Some(handle);
     }
 
+    pub(crate) fn started(&self) -> bool {
+        self.started.load(Ordering::SeqCst)
+    }
+
     pub(crate) fn wait(&self) -> Result<()> {
         if let Some(handle) = self.handle.lock().take() {
             handle.join().expect("io loop")?
         }
         Ok(())
     }
+
+    /// Wait for the io loop to be over, to start a new one
+    pub(crate) fn reset(&self) -> Result<()> {
+        let res = self.wait();
+        self.started.store(false, Ordering::SeqCst);
+        res
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -72,6 +92,9 @@ pub struct IoLoop<T> {
     has_data: bool,
     send_heartbeat: Arc<AtomicBool>,
     poll_timeout: Option<Duration>,
+    handshake_deadline: Option<Instant>,
+    budget_exhausted: bool,
+    skip: usize,
 }
 
 impl<T: Evented + Read + Write + Send + 'static> IoLoop<T> {
@@ -87,6 +110,10 @@ impl<T: Evented + Read + Write + Send + 'static> IoLoop<T> {
         })?;
         let frame_size = std::cmp::max(8192, connection.configuration().frame_max() as usize);
         let (registration, set_readiness) = Registration::new2();
+        let handshake_deadline = connection
+            .configuration()
+            .handshake_timeout()
+            .map(|timeout| Instant::now() + timeout);
         let inner = Self {
             connection,
             socket,
@@ -96,13 +123,16 @@ impl<T: Evented + Read + Write + Send + 'static> IoLoop<T> {
             set_readiness,
             hb_handle: None,
             frame_size,
-            receive_buffer: Buffer::with_capacity(FRAMES_STORAGE * frame_size),
+            receive_buffer: Buffer::with_capacity(RECEIVE_FRAMES_STORAGE * frame_size),
             send_buffer: Buffer::with_capacity(FRAMES_STORAGE * frame_size),
             can_write: false,
             can_read: false,
             has_data: false,
             send_heartbeat: Arc::new(AtomicBool::new(false)),
             poll_timeout: None,
+            handshake_deadline,
+            budget_exhausted: false,
+            skip: 0,
         };
         if registered {
             inner
@@ -171,12 +201,13 @@ impl<T: Evented + Read + Write + Send + 'static> IoLoop<T> {
         if self.status != Status::Setup && self.connection.status().connected() {
             let frame_max = self.connection.configuration().frame_max() as usize;
             self.frame_size = std::cmp::max(self.frame_size, frame_max);
-            self.receive_buffer.grow(FRAMES_STORAGE * self.frame_size);
+            self.receive_buffer
+                .grow(RECEIVE_FRAMES_STORAGE * self.frame_size);
             self.send_buffer.grow(FRAMES_STORAGE * self.frame_size);
-            let heartbeat = self.connection.configuration().heartbeat();
-            if heartbeat != 0 {
+            self.handshake_deadline = None;
+            self.poll_timeout = None;
+            if let Some(heartbeat) = self.connection.heartbeat_interval() {
                 trace!("io_loop: start heartbeat");
-                let heartbeat = Duration::from_secs(u64::from(heartbeat));
                 self.start_heartbeat(heartbeat)?;
                 self.poll_timeout = Some(heartbeat);
                 trace!("io_loop: heartbeat started");
@@ -253,6 +284,14 @@ impl<T: Evented + Read + Write + Send + 'static> IoLoop<T> {
     fn run(&mut self, events: &mut Events) -> Result<()> {
         trace!("io_loop run");
         self.ensure_setup()?;
+        if let Some(deadline) = self.handshake_deadline {
+            let now = Instant::now();
+            if now >= deadline {
+                self.status = Status::Stop;
+                return self.connection.fail_handshake();
+            }
+            self.poll_timeout = Some(deadline - now);
+        }
         self.poll(events)?;
         self.do_run()
     }
@@ -265,6 +304,7 @@ impl<T: Evented + Read + Write + Send + 'static> IoLoop<T> {
             self.has_data
         );
         loop {
+            self.budget_exhausted = false;
             self.heartbeat()?;
             self.write()?;
             if self.connection.status().closed() {
@@ -274,7 +314,8 @@ impl<T: Evented + Read + Write + Send + 'static> IoLoop<T> {
                 self.read()?;
             }
             self.parse()?;
-            if self.stop_looping() {
+            // Give the other events a chance to be handled
+            if self.stop_looping() || self.budget_exhausted {
                 self.maybe_continue()?;
                 break;
             }
@@ -297,7 +338,8 @@ impl<T: Evented + Read + Write + Send + 'static> IoLoop<T> {
     }
 
     fn has_pending_operations(&self) -> bool {
-        self.status != Status::Stop && (self.can_read() || self.can_parse() || self.can_write())
+        self.status != Status::Stop
+            && (self.budget_exhausted || self.can_read() || self.can_parse() || self.can_write())
     }
 
     fn maybe_continue(&mut self) -> Result<()> {
@@ -358,15 +400,16 @@ impl<T: Evented + Read + Write + Send + 'static> IoLoop<T> {
     }
 
     fn write_to_stream(&mut self) -> Result<()> {
-        self.serialize()?;
+        self.serialize_frames()?;
 
         self.socket
             .write(&self.send_buffer.data())
             .map(|sz| {
                 trace!("wrote {} bytes", sz);
+                self.connection.metrics().on_bytes_written(sz);
                 self.send_buffer.consume(sz);
             })
-            .map_err(Error::IOError)
+            .map_err(Error::from)
     }
 
     fn read_from_stream(&mut self) -> Result<()> {
@@ -378,71 +421,77 @@ impl<T: Evented + Read + Write + Send + 'static> IoLoop<T> {
                 .read(&mut self.receive_buffer.space())
                 .map(|sz| {
                     trace!("read {} bytes", sz);
+                    self.connection.metrics().on_bytes_read(sz);
                     self.receive_buffer.fill(sz);
                 })
-                .map_err(Error::IOError),
+                .map_err(Error::from),
         }
     }
 
-    fn serialize(&mut self) -> Result<()> {
-        if let Some((send_id, next_msg)) = self.connection.next_frame() {
-            trace!("will write to buffer: {:?}", next_msg);
-            let checkpoint = self.send_buffer.checkpoint();
-            let res = gen_frame(&next_msg)((&mut self.send_buffer).into());
-            match res.map(|w| w.into_inner().1) {
-                Ok(_) => {
-                    self.connection.mark_sent(send_id);
-                    Ok(())
-                }
-                Err(e) => {
-                    self.send_buffer.rollback(checkpoint);
-                    match e {
-                        GenError::BufferTooSmall(_) => {
-                            // Requeue msg
-                            self.connection.requeue_frame(send_id, next_msg)?;
-                            self.send_buffer.shift();
-                            Ok(())
-                        }
-                        e => {
-                            error!("error generating frame: {:?}", e);
-                            self.connection.set_error()?;
-                            Err(Error::SerialisationError(e))
-                        }
-                    }
-                }
+    /// Serialize as many frames as the send buffer can hold, up to max_frames_per_poll,
+    /// returning the number of bytes written
+    fn serialize_frames(&mut self) -> Result<usize> {
+        let budget = self.connection.configuration().max_frames_per_poll();
+        let mut written = 0;
+        let mut frames = 0;
+        while let Some(size) = self.serialize()? {
+            written += size;
+            frames += 1;
+            if budget != 0 && frames == budget {
+                trace!("serialize budget exhausted after {} frames", frames);
+                self.budget_exhausted = true;
+                break;
             }
-        } else {
-            self.has_data = false;
-            Ok(())
         }
+        trace!("serialized {} bytes", written);
+        Ok(written)
     }
 
-    fn parse(&mut self) -> Result<()> {
-        if self.can_parse() {
-            if let Some(frame) = self.do_parse()? {
-                self.connection.handle_frame(frame)?;
+    /// Serialize the next frame if there is one and it fits in the send buffer, returning its size
+    fn serialize(&mut self) -> Result<Option<usize>> {
+        match self.connection.serialize_frame(self.send_buffer.space()) {
+            Ok(SerializeOutcome::Wrote(size)) => {
+                self.send_buffer.fill(size);
+                Ok(Some(size))
             }
+            Ok(SerializeOutcome::Idle) => {
+                self.has_data = false;
+                Ok(None)
+            }
+            Err(Error::BufferTooSmall) => {
+                self.send_buffer.shift();
+                Ok(None)
+            }
+            Err(error) => Err(error),
         }
-        Ok(())
     }
 
-    fn do_parse(&mut self) -> Result<Option<AMQPFrame>> {
-        match parse_frame(self.receive_buffer.data()) {
-            Ok((i, f)) => {
-                let consumed = self.receive_buffer.data().offset(i);
-                self.receive_buffer.consume(consumed);
-                Ok(Some(f))
-            }
-            Err(e) => {
-                if e.is_incomplete() {
-                    self.receive_buffer.shift();
-                    Ok(None)
-                } else {
-                    error!("parse error: {:?}", e);
-                    self.connection.set_error()?;
-                    Err(Error::ParsingError(format!("{:?}", e)))
+    fn parse(&mut self) -> Result<()> {
+        if self.skip > 0 {
+            // Drop the end of an oversized frame
+            self.skip -= self.receive_buffer.consume(self.skip);
+        }
+        if self.can_parse() {
+            // The payloads of the body frames are views on the receive buffer
+            let (consumed, res) = self
+                .connection
+                .parse_shared(&self.receive_buffer.shared_data());
+            self.skip = consumed - self.receive_buffer.consume(consumed);
+            let progress = match res {
+                Ok((_, progress)) => progress,
+                Err(Error::FrameTooLarge(..)) => {
+                    // The connection is being closed, keep going to send connection.close
+                    Progress::Done
                 }
+                Err(error) => return Err(error),
+            };
+            if progress == Progress::Pending {
+                self.budget_exhausted = true;
+            } else if self.can_parse() {
+                // Incomplete trailing frame
+                self.receive_buffer.shift();
             }
         }
+        Ok(())
     }
 }
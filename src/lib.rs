@@ -4,6 +4,24 @@
 //!
 //! This project follows the AMQP 0.9.1 specifications, targetting especially RabbitMQ.
 //!
+//! ## Blocking usage
+//!
+//! No async runtime is needed: every method returns a `Confirmation` which can be blocked on with
+//! `wait`, or `wait_timeout`, while the io loop thread handles the socket. A `Consumer` can be
+//! iterated over to block on its deliveries, as done in `examples/consumer.rs`.
+//!
+//! To avoid the io loop thread too, `BlockingClient` drives a connection over a std `TcpStream`
+//! from the calling thread, only while one of its methods blocks.
+//!
+//! ## Runtimes
+//!
+//! By default, the network I/O runs on a dedicated io loop thread. With the `io-driver` feature,
+//! `Connection::connect_stream` instead drives the connection over a futures-io stream from an
+//! `IoDriver` task spawned on the async `Runtime` of your choice. Setting the runtime with
+//! `ConnectionProperties::with_runtime` makes `Connection::connect` open the TCP connection with
+//! it too. The `async-std-driver` feature provides `AsyncStdRuntime` and
+//! `Connection::connect_async_std`.
+//!
 //! ## Example
 //!
 //! ```rust,no_run
@@ -25,7 +43,7 @@
 //! impl ConsumerDelegate for Subscriber {
 //!   fn on_new_delivery(&self, delivery: DeliveryResult) {
 //!     if let Some(delivery) = delivery.unwrap() {
-//!       self.channel.basic_ack(delivery.delivery_tag, BasicAckOptions::default()).wait().expect("basic_ack");
+//!       delivery.ack(&self.channel, BasicAckOptions::default()).wait().expect("basic_ack");
 //!     }
 //!   }
 //! }
@@ -58,25 +76,47 @@
 pub use amq_protocol::{
     auth,
     protocol::{self, BasicProperties},
-    tcp, types, uri,
+    tcp, uri,
 };
 
+pub use blocking::BlockingClient;
 pub use channel::{options, Channel};
-pub use channel_status::{ChannelState, ChannelStatus};
-pub use configuration::Configuration;
-pub use connection::{Connect, Connection};
+pub use channel_status::{ChannelState, ChannelStatus, Qos};
+pub use configuration::{Configuration, FrameScheduling, SendQueueFullPolicy};
+pub use connection::{Connect, Connection, Progress, SerializeOutcome};
 pub use connection_properties::ConnectionProperties;
 pub use connection_status::{ConnectionState, ConnectionStatus};
-pub use consumer::{Consumer, ConsumerDelegate, ConsumerIterator};
-pub use error::{Error, Result};
+pub use consumer::{BufferedDelegate, CancelledBy, Consumer, ConsumerDelegate, ConsumerIterator};
+pub use dead_letters::{DeadLetterStore, LocalDeadLetterStore, RejectedPublish};
+#[cfg(feature = "async-std-driver")]
+pub use driver::AsyncStdRuntime;
+#[cfg(feature = "io-driver")]
+pub use driver::{IoDriver, Runtime, RuntimeStream, Sleep, TcpConnect};
+pub use error::{CloseReason, Error, Result};
 pub use exchange::ExchangeKind;
+pub use frame_batch::FrameBatch;
+#[cfg(feature = "frame_trace")]
+pub use frame_trace::TracedFrame;
+pub use metrics::{ConnectionMetrics, ConnectionStats, FrameDirection, FrameObserver};
+pub use payload::Payload;
 pub use queue::Queue;
+pub use state_dump::StateDump;
+pub use streaming_publish::StreamingPublish;
+pub use temporary_queue::TemporaryQueue;
 
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub mod bench;
 pub mod confirmation;
 pub mod executor;
 pub mod message;
+#[cfg(any(test, feature = "scenario"))]
+pub mod scenario;
+pub mod state_dump;
+pub mod types;
 
 mod acknowledgement;
+mod blocking;
 mod buffer;
 mod channel;
 mod channel_status;
@@ -86,14 +126,26 @@ mod connection;
 mod connection_properties;
 mod connection_status;
 mod consumer;
+mod dead_letters;
+#[cfg(feature = "io-driver")]
+mod driver;
 mod error;
 mod error_handler;
 mod exchange;
+mod frame_batch;
+#[cfg(feature = "frame_trace")]
+mod frame_trace;
 mod frames;
 mod id_sequence;
 mod io_loop;
+mod metrics;
+mod payload;
 mod queue;
 mod queues;
 mod registration;
 mod returned_messages;
+mod streaming_publish;
+mod temporary_queue;
+mod unacked_deliveries;
+mod uri_validation;
 mod wait;
@@ -1,7 +1,10 @@
 use crate::{
+    confirmation::Confirmation,
+    options::{BasicAckOptions, BasicNackOptions, BasicRejectOptions},
     types::{LongLongUInt, LongUInt, ShortString, ShortUInt},
-    BasicProperties, Result,
+    BasicProperties, Channel, Payload, Result,
 };
+use std::mem;
 
 /// Type wrapping the output of a consumer
 ///
@@ -10,6 +13,16 @@ use crate::{
 /// - Err(error) carries the error and is always followed by Ok(None)
 pub type DeliveryResult = Result<Option<Delivery>>;
 
+/// The publishes which weren't successfully handled by the server, see
+/// `Channel::wait_for_confirms`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UnconfirmedMessages {
+    /// The delivery tags of the publishes nacked by the server
+    pub nacked: Vec<LongLongUInt>,
+    /// The messages returned by the server, for instance because they were unroutable
+    pub returned: Vec<BasicReturnMessage>,
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct Delivery {
     pub delivery_tag: LongLongUInt,
@@ -17,7 +30,9 @@ pub struct Delivery {
     pub routing_key: ShortString,
     pub redelivered: bool,
     pub properties: BasicProperties,
-    pub data: Vec<u8>,
+    /// The body of the message, sharing the buffer it was received in when it fit in a single
+    /// frame, see `Payload`
+    pub data: Payload,
 }
 
 impl Delivery {
@@ -33,15 +48,38 @@ impl Delivery {
             routing_key,
             redelivered,
             properties: BasicProperties::default(),
-            data: Vec::new(),
+            data: Payload::default(),
+        }
+    }
+
+    pub(crate) fn receive_content(&mut self, data: Payload) {
+        if self.data.is_empty() {
+            // Most messages fit in a single body frame, take it over instead of copying it
+            self.data = data;
+        } else {
+            let mut content = mem::replace(&mut self.data, Payload::default()).into_vec();
+            content.extend_from_slice(&data);
+            self.data = content.into();
         }
     }
 
-    pub(crate) fn receive_content(&mut self, data: Vec<u8>) {
-        self.data.extend(data);
+    /// Acknowledge this delivery on the channel it has been received on
+    pub fn ack(&self, channel: &Channel, options: BasicAckOptions) -> Confirmation<()> {
+        channel.basic_ack(self.delivery_tag, options)
+    }
+
+    /// Negatively acknowledge this delivery on the channel it has been received on
+    pub fn nack(&self, channel: &Channel, options: BasicNackOptions) -> Confirmation<()> {
+        channel.basic_nack(self.delivery_tag, options)
+    }
+
+    /// Reject this delivery on the channel it has been received on
+    pub fn reject(&self, channel: &Channel, options: BasicRejectOptions) -> Confirmation<()> {
+        channel.basic_reject(self.delivery_tag, options)
     }
 }
 
+/// A message pulled with `basic_get`, along with the number of messages left in the queue
 #[derive(Clone, Debug, PartialEq)]
 pub struct BasicGetMessage {
     pub delivery: Delivery,
@@ -63,6 +101,12 @@ impl BasicGetMessage {
     }
 }
 
+impl From<BasicGetMessage> for Delivery {
+    fn from(message: BasicGetMessage) -> Self {
+        message.delivery
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct BasicReturnMessage {
     pub delivery: Delivery,
@@ -0,0 +1,429 @@
+#[cfg(feature = "frame_trace")]
+use crate::frame_trace::FrameTrace;
+use crate::{frames::OutboundFrame, Payload};
+use amq_protocol::{
+    frame::AMQPFrame,
+    protocol::{basic, AMQPClass},
+};
+use parking_lot::{Mutex, RwLock};
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+type ObserverFn = Arc<dyn Fn(FrameDirection, &AMQPFrame) + Send + Sync>;
+
+/// Hooks called by the io loop for each frame and each read or write on the socket, to feed
+/// counters
+///
+/// They are called from the io loop thread and must not block.
+pub trait ConnectionMetrics: Send + Sync {
+    /// A frame was serialized to the send buffer
+    fn on_frame_sent(&self, _frame: &AMQPFrame) {}
+    /// A frame was received and is about to be handled
+    fn on_frame_received(&self, _frame: &AMQPFrame) {}
+    /// Bytes were written to the socket
+    fn on_bytes_written(&self, _bytes: usize) {}
+    /// Bytes were read from the socket
+    fn on_bytes_read(&self, _bytes: usize) {}
+}
+
+/// Whether a frame was received from or sent to the server
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameDirection {
+    Inbound,
+    Outbound,
+}
+
+/// A callback seeing every frame going through the connection, for debugging protocol issues
+/// or recording conversations
+///
+/// Outbound frames are seen when they are taken from the send queue, which may happen twice
+/// for a frame which had to be retried.
+#[derive(Clone)]
+pub struct FrameObserver(ObserverFn);
+
+impl FrameObserver {
+    pub fn new<F: Fn(FrameDirection, &AMQPFrame) + Send + Sync + 'static>(observer: F) -> Self {
+        Self(Arc::new(observer))
+    }
+}
+
+impl fmt::Debug for FrameObserver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "FrameObserver")
+    }
+}
+
+/// A snapshot of the traffic counters of a connection, since it was created
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConnectionStats {
+    pub frames_sent: u64,
+    pub frames_received: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub heartbeats_sent: u64,
+    pub heartbeats_received: u64,
+    /// The number of basic.publish sent
+    pub publishes: u64,
+    /// The number of basic.deliver received
+    pub deliveries: u64,
+    /// The number of basic.ack sent to acknowledge deliveries
+    pub acks: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    frames_sent: AtomicU64,
+    frames_received: AtomicU64,
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    heartbeats_sent: AtomicU64,
+    heartbeats_received: AtomicU64,
+    publishes: AtomicU64,
+    deliveries: AtomicU64,
+    acks: AtomicU64,
+}
+
+fn incr(counter: &AtomicU64, value: u64) {
+    counter.fetch_add(value, Ordering::Relaxed);
+}
+
+#[derive(Clone)]
+pub(crate) struct Metrics {
+    counters: Arc<Counters>,
+    last_progress: Arc<Mutex<Instant>>,
+    /* Checked first not to take the lock when no metrics are registered */
+    enabled: Arc<AtomicBool>,
+    metrics: Arc<RwLock<Option<Box<dyn ConnectionMetrics>>>>,
+    /* Same for the frame observer */
+    observing: Arc<AtomicBool>,
+    observer: Arc<RwLock<Option<FrameObserver>>>,
+    #[cfg(feature = "frame_trace")]
+    trace: FrameTrace,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            counters: Arc::default(),
+            last_progress: Arc::new(Mutex::new(Instant::now())),
+            enabled: Arc::default(),
+            metrics: Arc::default(),
+            observing: Arc::default(),
+            observer: Arc::default(),
+            #[cfg(feature = "frame_trace")]
+            trace: FrameTrace::default(),
+        }
+    }
+}
+
+impl Metrics {
+    pub(crate) fn set_metrics(&self, metrics: Box<dyn ConnectionMetrics>) {
+        *self.metrics.write() = Some(metrics);
+        self.enabled.store(true, Ordering::SeqCst);
+    }
+
+    pub(crate) fn set_frame_observer(&self, observer: FrameObserver) {
+        *self.observer.write() = Some(observer);
+        self.observing.store(true, Ordering::SeqCst);
+    }
+
+    pub(crate) fn observe(&self, direction: FrameDirection, frame: &AMQPFrame) {
+        #[cfg(feature = "frame_trace")]
+        self.trace.record(direction, frame);
+        if self.observing.load(Ordering::Relaxed) {
+            if let Some(observer) = self.observer.read().as_ref() {
+                (observer.0)(direction, frame);
+            }
+        }
+    }
+
+    pub(crate) fn observe_outbound(&self, frame: &OutboundFrame) {
+        match frame {
+            OutboundFrame::Frame(frame) => self.observe(FrameDirection::Outbound, frame),
+            OutboundFrame::Body(channel_id, payload) => {
+                self.observe_body(FrameDirection::Outbound, *channel_id, payload)
+            }
+        }
+    }
+
+    /// Same as observe for a body frame, only copied to an AMQPFrame if something looks at it
+    pub(crate) fn observe_body(
+        &self,
+        direction: FrameDirection,
+        channel_id: u16,
+        payload: &Payload,
+    ) {
+        if self.observing.load(Ordering::Relaxed) || self.tracing() {
+            self.observe(direction, &AMQPFrame::Body(channel_id, payload.to_vec()));
+        }
+    }
+
+    #[cfg(feature = "frame_trace")]
+    fn tracing(&self) -> bool {
+        self.trace.recording()
+    }
+
+    #[cfg(not(feature = "frame_trace"))]
+    fn tracing(&self) -> bool {
+        false
+    }
+
+    #[cfg(feature = "frame_trace")]
+    pub(crate) fn frame_trace(&self) -> &FrameTrace {
+        &self.trace
+    }
+
+    fn with_metrics<F: FnOnce(&dyn ConnectionMetrics)>(&self, f: F) {
+        if self.enabled.load(Ordering::Relaxed) {
+            if let Some(metrics) = self.metrics.read().as_ref() {
+                f(metrics.as_ref());
+            }
+        }
+    }
+
+    pub(crate) fn stats(&self) -> ConnectionStats {
+        let counters = &self.counters;
+        ConnectionStats {
+            frames_sent: counters.frames_sent.load(Ordering::Relaxed),
+            frames_received: counters.frames_received.load(Ordering::Relaxed),
+            bytes_sent: counters.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: counters.bytes_received.load(Ordering::Relaxed),
+            heartbeats_sent: counters.heartbeats_sent.load(Ordering::Relaxed),
+            heartbeats_received: counters.heartbeats_received.load(Ordering::Relaxed),
+            publishes: counters.publishes.load(Ordering::Relaxed),
+            deliveries: counters.deliveries.load(Ordering::Relaxed),
+            acks: counters.acks.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn last_progress(&self) -> Instant {
+        *self.last_progress.lock()
+    }
+
+    /// Some frames were parsed, or the handshake started
+    pub(crate) fn on_progress(&self) {
+        *self.last_progress.lock() = Instant::now();
+    }
+
+    pub(crate) fn on_frame_sent(&self, frame: &AMQPFrame) {
+        let counters = &self.counters;
+        incr(&counters.frames_sent, 1);
+        match frame {
+            AMQPFrame::Heartbeat(_) => incr(&counters.heartbeats_sent, 1),
+            AMQPFrame::Method(_, AMQPClass::Basic(basic::AMQPMethod::Publish(_))) => {
+                incr(&counters.publishes, 1)
+            }
+            AMQPFrame::Method(_, AMQPClass::Basic(basic::AMQPMethod::Ack(_))) => {
+                incr(&counters.acks, 1)
+            }
+            _ => {}
+        }
+        self.with_metrics(|metrics| metrics.on_frame_sent(frame));
+    }
+
+    pub(crate) fn on_outbound_frame_sent(&self, frame: &OutboundFrame) {
+        match frame {
+            OutboundFrame::Frame(frame) => self.on_frame_sent(frame),
+            OutboundFrame::Body(channel_id, payload) => {
+                incr(&self.counters.frames_sent, 1);
+                self.with_metrics(|metrics| {
+                    metrics.on_frame_sent(&AMQPFrame::Body(*channel_id, payload.to_vec()))
+                });
+            }
+        }
+    }
+
+    pub(crate) fn on_frame_received(&self, frame: &AMQPFrame) {
+        let counters = &self.counters;
+        incr(&counters.frames_received, 1);
+        match frame {
+            AMQPFrame::Heartbeat(_) => incr(&counters.heartbeats_received, 1),
+            AMQPFrame::Method(_, AMQPClass::Basic(basic::AMQPMethod::Deliver(_))) => {
+                incr(&counters.deliveries, 1)
+            }
+            _ => {}
+        }
+        self.with_metrics(|metrics| metrics.on_frame_received(frame));
+    }
+
+    /// Same as on_frame_received for a body frame, only copied to an AMQPFrame for the metrics
+    pub(crate) fn on_body_received(&self, channel_id: u16, payload: &Payload) {
+        incr(&self.counters.frames_received, 1);
+        self.with_metrics(|metrics| {
+            metrics.on_frame_received(&AMQPFrame::Body(channel_id, payload.to_vec()))
+        });
+    }
+
+    pub(crate) fn on_bytes_written(&self, bytes: usize) {
+        incr(&self.counters.bytes_sent, bytes as u64);
+        self.with_metrics(|metrics| metrics.on_bytes_written(bytes));
+    }
+
+    pub(crate) fn on_bytes_read(&self, bytes: usize) {
+        incr(&self.counters.bytes_received, bytes as u64);
+        self.with_metrics(|metrics| metrics.on_bytes_read(bytes));
+    }
+}
+
+impl fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Metrics({})", self.enabled.load(Ordering::Relaxed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        connection::Connection, connection_status::ConnectionState, BasicProperties,
+        ConnectionProperties,
+    };
+    use amq_protocol::frame::{gen_frame, AMQPFrame};
+
+    #[test]
+    fn metrics() {
+        let _ = env_logger::try_init();
+
+        use crate::options::BasicQosOptions;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        #[derive(Clone, Default)]
+        struct Counters {
+            sent: Arc<AtomicUsize>,
+            received: Arc<AtomicUsize>,
+        }
+
+        impl ConnectionMetrics for Counters {
+            fn on_frame_sent(&self, _frame: &AMQPFrame) {
+                self.sent.fetch_add(1, Ordering::SeqCst);
+            }
+
+            fn on_frame_received(&self, _frame: &AMQPFrame) {
+                self.received.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let (conn, channel) = Connection::connected_channel();
+        let counters = Counters::default();
+        conn.set_metrics(counters.clone());
+
+        let _ = channel.basic_qos(10, BasicQosOptions::default());
+        let mut batch = conn.next_frames(0).unwrap();
+        let size = batch.remaining();
+        conn.frames_written(&mut batch, size);
+        assert_eq!(counters.sent.load(Ordering::SeqCst), 1);
+
+        let mut data = Vec::new();
+        for _ in 0..3 {
+            data = gen_frame(&AMQPFrame::Heartbeat(0))(data.into())
+                .unwrap()
+                .into_inner()
+                .0;
+        }
+        let (_, res) = conn.parse_all(&data);
+        assert!(res.is_ok());
+        assert_eq!(counters.received.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn stats() {
+        let _ = env_logger::try_init();
+
+        use crate::options::{
+            BasicAckOptions, BasicConsumeOptions, BasicPublishOptions, QueueDeclareOptions,
+        };
+        use crate::scenario::Scenario;
+        use crate::types::FieldTable;
+
+        let scripted = Scenario::new()
+            .expect_queue_declare("jobs")
+            .reply_ok(0, 0)
+            .expect_publish()
+            .sent()
+            .expect_basic_consume("jobs")
+            .deliver(b"job")
+            .expect_basic_ack(1)
+            .run();
+        let channel = scripted.channel();
+        let queue = channel
+            .queue_declare(
+                "jobs",
+                QueueDeclareOptions::default(),
+                FieldTable::default(),
+            )
+            .wait()
+            .unwrap();
+        channel
+            .basic_publish(
+                "",
+                "jobs",
+                BasicPublishOptions::default(),
+                b"job".to_vec(),
+                BasicProperties::default(),
+            )
+            .wait()
+            .unwrap();
+        let consumer = channel
+            .basic_consume(
+                &queue,
+                "",
+                BasicConsumeOptions::default(),
+                FieldTable::default(),
+            )
+            .wait()
+            .unwrap();
+        let delivery = consumer.into_iter().next().unwrap().unwrap();
+        delivery
+            .ack(channel, BasicAckOptions::default())
+            .wait()
+            .unwrap();
+        let stats = scripted.connection().stats();
+        scripted.finish();
+        assert_eq!(stats.publishes, 1);
+        assert_eq!(stats.deliveries, 1);
+        assert_eq!(stats.acks, 1);
+        // queue.declare, publish (method, header, body), consume and ack
+        assert_eq!(stats.frames_sent, 6);
+        // declare-ok, consume-ok and the delivery (method, header, body)
+        assert_eq!(stats.frames_received, 5);
+        assert_eq!(stats.heartbeats_sent, 0);
+        // The scripted server doesn't go through the socket
+        assert_eq!(stats.bytes_sent, 0);
+    }
+
+    #[test]
+    fn frame_observer() {
+        let _ = env_logger::try_init();
+
+        use parking_lot::Mutex;
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let observed = seen.clone();
+        let options = ConnectionProperties {
+            frame_observer: Some(FrameObserver::new(move |direction, frame| {
+                observed.lock().push((direction, format!("{:?}", frame)))
+            })),
+            ..ConnectionProperties::default()
+        };
+        let uri = Connection::validate_uri("amqp://127.0.0.1:5672/%2f").unwrap();
+        let (conn, _wait) = Connection::handshake(uri, options).unwrap();
+        let (send_id, _) = conn.next_frame().unwrap();
+        conn.mark_sent(send_id);
+        conn.set_state(ConnectionState::Connected);
+        conn.handle_frame(AMQPFrame::Heartbeat(0)).unwrap();
+
+        assert_eq!(
+            *seen.lock(),
+            vec![
+                (FrameDirection::Outbound, "ProtocolHeader".to_string()),
+                (FrameDirection::Inbound, "Heartbeat(0)".to_string()),
+            ]
+        );
+    }
+}
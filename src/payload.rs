@@ -0,0 +1,218 @@
+use std::{cmp, fmt, hash, ops::Deref, sync::Arc};
+
+/// The body of a message, a cheaply cloneable view on a shared buffer
+///
+/// Publishing splits it in frame_max sized slices which share its buffer instead of copying it,
+/// and the deliveries received by the io loop in a single body frame share the buffer they were
+/// read into. A payload keeps the whole buffer it is a view on allocated: copy the ones kept
+/// around for long with `to_vec`.
+#[derive(Clone, Default)]
+pub struct Payload {
+    /* None for an empty payload, not to allocate for it */
+    buffer: Option<Arc<Vec<u8>>>,
+    start: usize,
+    end: usize,
+}
+
+impl Payload {
+    /// A view on `buffer[start..end]`
+    pub(crate) fn shared(buffer: Arc<Vec<u8>>, start: usize, end: usize) -> Self {
+        assert!(
+            start <= end && end <= buffer.len(),
+            "payload range {}..{} out of bounds",
+            start,
+            end
+        );
+        Self {
+            buffer: Some(buffer),
+            start,
+            end,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        match self.buffer.as_ref() {
+            Some(buffer) => &buffer[self.start..self.end],
+            None => &[],
+        }
+    }
+
+    /// A view on `self[start..end]`, sharing the same buffer
+    ///
+    /// Panics if the range is out of bounds, like slicing would.
+    pub fn slice(&self, start: usize, end: usize) -> Self {
+        assert!(
+            start <= end && end <= self.len(),
+            "payload range {}..{} out of bounds",
+            start,
+            end
+        );
+        Self {
+            buffer: self.buffer.clone(),
+            start: self.start + start,
+            end: self.start + end,
+        }
+    }
+
+    /// Split the payload in slices of at most `size` bytes, sharing the same buffer
+    pub(crate) fn chunks(&self, size: usize) -> impl Iterator<Item = Payload> + '_ {
+        (0..self.len())
+            .step_by(size)
+            .map(move |start| self.slice(start, start + cmp::min(size, self.len() - start)))
+    }
+
+    /// Get the payload as a Vec, only copying it if its buffer is shared or holds more than it
+    pub fn into_vec(self) -> Vec<u8> {
+        let (start, end) = (self.start, self.end);
+        match self.buffer {
+            Some(buffer) => {
+                if start == 0 && end == buffer.len() {
+                    Arc::try_unwrap(buffer).unwrap_or_else(|buffer| buffer.to_vec())
+                } else {
+                    buffer[start..end].to_vec()
+                }
+            }
+            None => Vec::new(),
+        }
+    }
+}
+
+impl Deref for Payload {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl AsRef<[u8]> for Payload {
+    fn as_ref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl From<Vec<u8>> for Payload {
+    fn from(buffer: Vec<u8>) -> Self {
+        let end = buffer.len();
+        Self::shared(Arc::new(buffer), 0, end)
+    }
+}
+
+impl From<&[u8]> for Payload {
+    fn from(data: &[u8]) -> Self {
+        data.to_vec().into()
+    }
+}
+
+impl From<Payload> for Vec<u8> {
+    fn from(payload: Payload) -> Self {
+        payload.into_vec()
+    }
+}
+
+impl fmt::Debug for Payload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_slice(), f)
+    }
+}
+
+impl hash::Hash for Payload {
+    fn hash<H: hash::Hasher>(&self, state: &mut H) {
+        hash::Hash::hash(self.as_slice(), state);
+    }
+}
+
+impl PartialEq for Payload {
+    fn eq(&self, other: &Payload) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl Eq for Payload {}
+
+impl PartialEq<[u8]> for Payload {
+    fn eq(&self, other: &[u8]) -> bool {
+        self.as_slice() == other
+    }
+}
+
+impl PartialEq<&[u8]> for Payload {
+    fn eq(&self, other: &&[u8]) -> bool {
+        self.as_slice() == *other
+    }
+}
+
+impl PartialEq<Vec<u8>> for Payload {
+    fn eq(&self, other: &Vec<u8>) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl PartialEq<Payload> for Vec<u8> {
+    fn eq(&self, other: &Payload) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+macro_rules! array_eq {
+    ($($size:expr),*) => {
+        $(
+            impl PartialEq<[u8; $size]> for Payload {
+                fn eq(&self, other: &[u8; $size]) -> bool {
+                    self.as_slice() == &other[..]
+                }
+            }
+
+            impl PartialEq<&[u8; $size]> for Payload {
+                fn eq(&self, other: &&[u8; $size]) -> bool {
+                    self.as_slice() == &other[..]
+                }
+            }
+        )*
+    };
+}
+
+array_eq!(
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25,
+    26, 27, 28, 29, 30, 31, 32
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slices_share_the_buffer() {
+        let payload = Payload::from(b"hello world".to_vec());
+        let world = payload.slice(6, 11);
+        assert_eq!(world, b"world");
+        assert_eq!(world.as_ptr(), payload[6..].as_ptr());
+        let chunks = payload.chunks(4).collect::<Vec<_>>();
+        assert_eq!(chunks, vec![&b"hell"[..], b"o wo", b"rld"]);
+        assert_eq!(chunks[2].as_ptr(), world[2..].as_ptr());
+        assert_eq!(Payload::default().chunks(4).count(), 0);
+    }
+
+    #[test]
+    fn into_vec() {
+        let data = b"payload".to_vec();
+        let data_ptr = data.as_ptr();
+        // The only view on the whole buffer takes it back
+        let payload = Payload::from(data);
+        assert_eq!(payload.into_vec().as_ptr(), data_ptr);
+
+        let payload = Payload::from(b"payload".to_vec());
+        let shared = payload.clone();
+        assert_eq!(payload.into_vec(), b"payload".to_vec());
+        assert_eq!(shared.slice(3, 7).into_vec(), b"load".to_vec());
+        assert!(Payload::default().into_vec().is_empty());
+    }
+}
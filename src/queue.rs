@@ -1,6 +1,11 @@
 use crate::{
-    connection_status::ConnectionState, consumer::Consumer, message::BasicGetMessage,
-    types::ShortString, wait::WaitHandle, BasicProperties, Error, Result,
+    connection_status::ConnectionState,
+    consumer::{CancelledBy, Consumer},
+    message::BasicGetMessage,
+    state_dump::ConsumerDump,
+    types::{QueueType, ShortString},
+    wait::WaitHandle,
+    BasicProperties, Error, Payload, Result,
 };
 use std::{borrow::Borrow, collections::HashMap, hash::Hash};
 
@@ -9,6 +14,7 @@ pub struct Queue {
     name: ShortString,
     message_count: u32,
     consumer_count: u32,
+    queue_type: Option<QueueType>,
 }
 
 impl Queue {
@@ -16,13 +22,23 @@ impl Queue {
         &self.name
     }
 
+    /// The number of messages in the queue, as reported by the server when it got declared
     pub fn message_count(&self) -> u32 {
         self.message_count
     }
 
+    /// The number of consumers of the queue, as reported by the server when it got declared
     pub fn consumer_count(&self) -> u32 {
         self.consumer_count
     }
+
+    /// The `x-queue-type` argument it got declared with, if any
+    ///
+    /// The server doesn't tell the type of a queue when declaring it, so this is `None` for a
+    /// passive declaration.
+    pub fn queue_type(&self) -> Option<QueueType> {
+        self.queue_type
+    }
 }
 
 #[derive(Debug)]
@@ -38,8 +54,13 @@ impl Queue {
             name,
             message_count,
             consumer_count,
+            queue_type: None,
         }
     }
+
+    pub(crate) fn set_queue_type(&mut self, queue_type: Option<QueueType>) {
+        self.queue_type = queue_type;
+    }
 }
 
 impl Borrow<str> for Queue {
@@ -56,12 +77,13 @@ impl QueueState {
     pub(crate) fn deregister_consumer<S: Hash + Eq + ?Sized>(
         &mut self,
         consumer_tag: &S,
+        cancelled_by: CancelledBy,
     ) -> Result<()>
     where
         ShortString: Borrow<S>,
     {
         if let Some(consumer) = self.consumers.remove(consumer_tag) {
-            consumer.cancel()?;
+            consumer.cancel(cancelled_by)?;
         }
         Ok(())
     }
@@ -79,7 +101,7 @@ impl QueueState {
     pub(crate) fn cancel_consumers(&mut self) -> Result<()> {
         self.consumers
             .drain()
-            .map(|(_, consumer)| consumer.cancel())
+            .map(|(_, consumer)| consumer.cancel(CancelledBy::Client))
             .fold(Ok(()), Result::and)
     }
 
@@ -92,6 +114,32 @@ impl QueueState {
             .fold(Ok(()), Result::and)
     }
 
+    /// Fail the consumer, which won't receive anything anymore
+    pub(crate) fn fail_consumer(&mut self, consumer_tag: &str, error: Error) -> Result<()> {
+        if let Some(consumer) = self.consumers.remove(consumer_tag) {
+            consumer.set_error(error)?;
+        }
+        Ok(())
+    }
+
+    /// Fail the basic_get waiting for the content of its message
+    pub(crate) fn fail_get_message(&mut self, error: Error) {
+        if let Some((_, wait_handle)) = self.current_get_message.take() {
+            wait_handle.error(error);
+        }
+    }
+
+    pub(crate) fn dump_consumers(&self) -> Vec<ConsumerDump> {
+        self.consumers
+            .iter()
+            .map(|(tag, consumer)| ConsumerDump {
+                queue: self.name.to_string(),
+                tag: tag.to_string(),
+                buffered_deliveries: consumer.inner().buffered_deliveries(),
+            })
+            .collect()
+    }
+
     pub(crate) fn name(&self) -> ShortString {
         self.name.clone()
     }
@@ -103,6 +151,13 @@ impl QueueState {
             .fold(Ok(()), Result::and)
     }
 
+    pub(crate) fn recover_consumers(&mut self) -> Result<()> {
+        self.consumers
+            .values()
+            .map(Consumer::recover)
+            .fold(Ok(()), Result::and)
+    }
+
     pub(crate) fn start_new_delivery(
         &mut self,
         delivery: BasicGetMessage,
@@ -117,7 +172,7 @@ impl QueueState {
         }
     }
 
-    pub(crate) fn receive_delivery_content(&mut self, payload: Vec<u8>) {
+    pub(crate) fn receive_delivery_content(&mut self, payload: Payload) {
         if let Some(delivery) = self.current_get_message.as_mut() {
             delivery.0.delivery.receive_content(payload);
         }
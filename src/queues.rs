@@ -1,10 +1,11 @@
 use crate::{
-    consumer::Consumer,
+    consumer::{CancelledBy, Consumer},
     message::{BasicGetMessage, Delivery},
-    queue::QueueState,
+    queue::{Queue, QueueState},
+    state_dump::ConsumerDump,
     types::ShortString,
     wait::WaitHandle,
-    BasicProperties, Result,
+    BasicProperties, Error, Payload, Result,
 };
 use parking_lot::Mutex;
 use std::{collections::HashMap, sync::Arc};
@@ -16,7 +17,8 @@ pub(crate) struct Queues {
 
 impl Queues {
     pub(crate) fn register(&self, queue: QueueState) {
-        self.queues.lock().insert(queue.name(), queue);
+        // Keep the consumers of a queue which gets declared again, passively or not
+        self.queues.lock().entry(queue.name()).or_insert(queue);
     }
 
     pub(crate) fn deregister(&self, queue: &str) {
@@ -34,14 +36,26 @@ impl Queues {
         }
     }
 
-    pub(crate) fn deregister_consumer(&self, consumer_tag: &str) -> Result<()> {
+    pub(crate) fn deregister_consumer(
+        &self,
+        consumer_tag: &str,
+        cancelled_by: CancelledBy,
+    ) -> Result<()> {
         self.queues
             .lock()
             .values_mut()
-            .map(|queue| queue.deregister_consumer(consumer_tag))
+            .map(|queue| queue.deregister_consumer(consumer_tag, cancelled_by))
             .fold(Ok(()), Result::and)
     }
 
+    pub(crate) fn dump_consumers(&self) -> Vec<ConsumerDump> {
+        self.queues
+            .lock()
+            .values()
+            .flat_map(QueueState::dump_consumers)
+            .collect()
+    }
+
     pub(crate) fn drop_prefetched_messages(&self) -> Result<()> {
         self.queues
             .lock()
@@ -50,6 +64,14 @@ impl Queues {
             .fold(Ok(()), Result::and)
     }
 
+    pub(crate) fn recover_consumers(&self) -> Result<()> {
+        self.queues
+            .lock()
+            .values_mut()
+            .map(QueueState::recover_consumers)
+            .fold(Ok(()), Result::and)
+    }
+
     pub(crate) fn cancel_consumers(&self) -> Result<()> {
         self.queues
             .lock()
@@ -66,6 +88,12 @@ impl Queues {
             .fold(Ok(()), Result::and)
     }
 
+    pub(crate) fn fail_get_messages(&self, error: &dyn Fn() -> Error) {
+        for queue in self.queues.lock().values_mut() {
+            queue.fail_get_message(error());
+        }
+    }
+
     pub(crate) fn start_consumer_delivery(
         &self,
         consumer_tag: &str,
@@ -86,22 +114,41 @@ impl Queues {
         message: BasicGetMessage,
         wait_handle: WaitHandle<Option<BasicGetMessage>>,
     ) {
+        // basic.get doesn't require the queue to be declared on this channel
+        self.queues
+            .lock()
+            .entry(queue.into())
+            .or_insert_with(|| Queue::new(queue.into(), 0, 0).into())
+            .start_new_delivery(message, wait_handle);
+    }
+
+    /// Fail the consumer or the basic_get the delivery in progress is for
+    pub(crate) fn fail_delivery(
+        &self,
+        queue: &str,
+        consumer_tag: Option<&str>,
+        error: Error,
+    ) -> Result<()> {
         if let Some(queue) = self.queues.lock().get_mut(queue) {
-            queue.start_new_delivery(message, wait_handle);
+            match consumer_tag {
+                Some(consumer_tag) => return queue.fail_consumer(consumer_tag, error),
+                None => queue.fail_get_message(error),
+            }
         }
+        Ok(())
     }
 
     pub(crate) fn handle_content_header_frame(
         &self,
         queue: &str,
-        consumer_tag: Option<ShortString>,
+        consumer_tag: Option<&str>,
         size: u64,
         properties: BasicProperties,
     ) -> Result<()> {
         if let Some(queue) = self.queues.lock().get_mut(queue) {
             match consumer_tag {
                 Some(consumer_tag) => {
-                    if let Some(consumer) = queue.get_consumer(&consumer_tag) {
+                    if let Some(consumer) = queue.get_consumer(consumer_tag) {
                         consumer.set_delivery_properties(properties);
                         if size == 0 {
                             consumer.new_delivery_complete()?;
@@ -122,15 +169,15 @@ impl Queues {
     pub(crate) fn handle_body_frame(
         &self,
         queue: &str,
-        consumer_tag: Option<ShortString>,
+        consumer_tag: Option<&str>,
         remaining_size: usize,
         payload_size: usize,
-        payload: Vec<u8>,
+        payload: Payload,
     ) -> Result<()> {
         if let Some(queue) = self.queues.lock().get_mut(queue) {
             match consumer_tag {
                 Some(consumer_tag) => {
-                    if let Some(consumer) = queue.get_consumer(&consumer_tag) {
+                    if let Some(consumer) = queue.get_consumer(consumer_tag) {
                         consumer.receive_delivery_content(payload);
                         if remaining_size == payload_size {
                             consumer.new_delivery_complete()?;
@@ -5,12 +5,20 @@ use std::{fmt, io, sync::Arc};
 #[derive(Clone)]
 pub(crate) struct Registration {
     registration: Arc<Mutex<mio::Registration>>,
-    set_readiness: SetReadiness,
+    set_readiness: Arc<Mutex<SetReadiness>>,
 }
 
 impl Registration {
     pub(crate) fn set_readiness(&self, ready: Ready) -> io::Result<()> {
-        self.set_readiness.set_readiness(ready)
+        self.set_readiness.lock().set_readiness(ready)
+    }
+
+    /// Replace the registration, a mio registration being tied to the first Poll it was
+    /// registered with
+    pub(crate) fn reset(&self) {
+        let (registration, set_readiness) = mio::Registration::new2();
+        *self.registration.lock() = registration;
+        *self.set_readiness.lock() = set_readiness;
     }
 }
 
@@ -19,7 +27,7 @@ impl Default for Registration {
         let (registration, set_readiness) = mio::Registration::new2();
         Self {
             registration: Arc::new(Mutex::new(registration)),
-            set_readiness,
+            set_readiness: Arc::new(Mutex::new(set_readiness)),
         }
     }
 }
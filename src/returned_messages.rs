@@ -1,7 +1,9 @@
-use crate::{message::BasicReturnMessage, wait::WaitHandle, BasicProperties};
+use crate::{message::BasicReturnMessage, BasicProperties, Payload};
 use log::error;
 use parking_lot::Mutex;
-use std::{collections::VecDeque, sync::Arc};
+use std::{collections::VecDeque, fmt, sync::Arc};
+
+pub(crate) type ReturnHandler = Arc<dyn Fn(BasicReturnMessage) + Send + Sync + 'static>;
 
 #[derive(Clone, Debug, Default)]
 pub(crate) struct ReturnedMessages {
@@ -19,13 +21,16 @@ impl ReturnedMessages {
         }
     }
 
-    pub(crate) fn new_delivery_complete(&self) {
-        self.inner.lock().new_delivery_complete();
+    pub(crate) fn new_delivery_complete(&self, confirm_mode: bool) {
+        let returned = self.inner.lock().new_delivery_complete(confirm_mode);
+        if let Some((message, handler)) = returned {
+            handler(message);
+        }
     }
 
-    pub(crate) fn receive_delivery_content(&self, data: Vec<u8>) {
+    pub(crate) fn receive_delivery_content(&self, data: Payload) {
         if let Some(message) = self.inner.lock().current_message.as_mut() {
-            message.delivery.data.extend(data);
+            message.delivery.receive_content(data);
         }
     }
 
@@ -33,8 +38,13 @@ impl ReturnedMessages {
         self.inner.lock().messages.drain(..).collect()
     }
 
-    pub(crate) fn register_waiter(&self, waiter: WaitHandle<()>) {
-        self.inner.lock().waiters.push_back(waiter);
+    pub(crate) fn set_handler(&self, handler: ReturnHandler) {
+        self.inner.lock().handler = Some(Handler(handler));
+    }
+
+    /// The oldest returned message which wasn't matched with a publisher confirm yet
+    pub(crate) fn next_unconfirmed(&self) -> Option<BasicReturnMessage> {
+        self.inner.lock().unconfirmed.pop_front()
     }
 }
 
@@ -42,17 +52,35 @@ impl ReturnedMessages {
 pub struct Inner {
     current_message: Option<BasicReturnMessage>,
     messages: Vec<BasicReturnMessage>,
-    waiters: VecDeque<WaitHandle<()>>,
+    unconfirmed: VecDeque<BasicReturnMessage>,
+    handler: Option<Handler>,
 }
 
 impl Inner {
-    fn new_delivery_complete(&mut self) {
+    fn new_delivery_complete(
+        &mut self,
+        confirm_mode: bool,
+    ) -> Option<(BasicReturnMessage, ReturnHandler)> {
         if let Some(message) = self.current_message.take() {
             error!("Server returned us a message: {:?}", message);
-            self.messages.push(message);
-            if let Some(wait_handle) = self.waiters.pop_front() {
-                wait_handle.finish(());
+            if confirm_mode {
+                // The server sends basic.return before the basic.ack of the same message
+                self.unconfirmed.push_back(message.clone());
             }
+            self.messages.push(message.clone());
+            self.handler
+                .as_ref()
+                .map(|handler| (message, handler.0.clone()))
+        } else {
+            None
         }
     }
 }
+
+struct Handler(ReturnHandler);
+
+impl fmt::Debug for Handler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ReturnHandler")
+    }
+}
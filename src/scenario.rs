@@ -0,0 +1,607 @@
+//! Scripted AMQP conversations, to test code using lapin without a server
+//!
+//! A `Scenario` lists the operations the code under test is expected to perform on a channel,
+//! along with the server replies. Running it gives a `ScriptedConnection` whose channel answers
+//! according to the script, and which panics with the expected and actual operations side by
+//! side if the code deviated from it.
+//!
+//! ```rust,no_run
+//! use lapin::scenario::Scenario;
+//!
+//! let scripted = Scenario::new()
+//!     .expect_queue_declare("jobs")
+//!     .reply_ok(5, 1)
+//!     .expect_confirm_select()
+//!     .expect_publish()
+//!     .nack()
+//!     .expect_publish()
+//!     .ack()
+//!     .run();
+//! let channel = scripted.channel();
+//! // ... run the code under test against channel
+//! scripted.finish();
+//! ```
+//!
+//! This module is only available with the `scenario` feature.
+
+use crate::{
+    connection_status::ConnectionState,
+    protocol::{basic, confirm, queue, AMQPClass},
+    types::{LongLongUInt, ShortString, ShortUInt},
+    BasicProperties, Channel, Connection,
+};
+use amq_protocol::frame::{AMQPContentHeader, AMQPFrame};
+use log::{error, trace};
+use parking_lot::Mutex;
+use std::{
+    fmt::Write,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// The consumer tag given by the server to consumers which didn't specify one
+const GENERATED_CONSUMER_TAG: &str = "amq.ctag-scenario";
+
+/// The script of an AMQP conversation on a single channel
+#[derive(Clone, Debug, Default)]
+pub struct Scenario {
+    steps: Vec<Step>,
+}
+
+#[derive(Clone, Debug)]
+enum Step {
+    QueueDeclare(ShortString, u32, u32),
+    BasicQos(ShortUInt),
+    BasicConsume(ShortString),
+    ConfirmSelect,
+    Publish(Option<bool>),
+    BasicAck(LongLongUInt),
+    Deliver(Vec<u8>),
+}
+
+impl Step {
+    fn label(&self) -> String {
+        match self {
+            Step::QueueDeclare(queue, ..) => queue_declare_label(queue),
+            Step::BasicQos(prefetch_count) => basic_qos_label(*prefetch_count),
+            Step::BasicConsume(queue) => basic_consume_label(queue),
+            Step::ConfirmSelect => "confirm.select".into(),
+            Step::Publish(_) => "basic.publish".into(),
+            Step::BasicAck(delivery_tag) => basic_ack_label(*delivery_tag),
+            Step::Deliver(_) => "basic.deliver (from the server)".into(),
+        }
+    }
+}
+
+fn queue_declare_label(queue: &ShortString) -> String {
+    format!("queue.declare({})", queue)
+}
+
+fn basic_qos_label(prefetch_count: ShortUInt) -> String {
+    format!("basic.qos({})", prefetch_count)
+}
+
+fn basic_consume_label(queue: &ShortString) -> String {
+    format!("basic.consume({})", queue)
+}
+
+fn basic_ack_label(delivery_tag: LongLongUInt) -> String {
+    format!("basic.ack({})", delivery_tag)
+}
+
+fn method_label(method: &AMQPClass) -> String {
+    match method {
+        AMQPClass::Queue(queue::AMQPMethod::Declare(method)) => queue_declare_label(&method.queue),
+        AMQPClass::Basic(basic::AMQPMethod::Qos(method)) => basic_qos_label(method.prefetch_count),
+        AMQPClass::Basic(basic::AMQPMethod::Consume(method)) => basic_consume_label(&method.queue),
+        AMQPClass::Confirm(confirm::AMQPMethod::Select(_)) => "confirm.select".into(),
+        AMQPClass::Basic(basic::AMQPMethod::Publish(_)) => "basic.publish".into(),
+        AMQPClass::Basic(basic::AMQPMethod::Ack(method)) => basic_ack_label(method.delivery_tag),
+        method => format!("{:?}", method),
+    }
+}
+
+impl Scenario {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Expect a `queue.declare` of the given queue
+    pub fn expect_queue_declare(self, queue: &str) -> QueueDeclareExpectation {
+        QueueDeclareExpectation {
+            scenario: self,
+            queue: queue.into(),
+        }
+    }
+
+    /// Expect a `basic.qos` with the given prefetch count, answered with `basic.qos-ok`
+    pub fn expect_basic_qos(self, prefetch_count: ShortUInt) -> Self {
+        self.step(Step::BasicQos(prefetch_count))
+    }
+
+    /// Expect a `basic.consume` on the given queue, answered with `basic.consume-ok`
+    ///
+    /// As with a real server, the queue must have been declared on the channel beforehand for the
+    /// consumer to receive deliveries.
+    pub fn expect_basic_consume(self, queue: &str) -> Self {
+        self.step(Step::BasicConsume(queue.into()))
+    }
+
+    /// Expect a `confirm.select`, answered with `confirm.select-ok`
+    pub fn expect_confirm_select(self) -> Self {
+        self.step(Step::ConfirmSelect)
+    }
+
+    /// Expect a `basic.publish`, on any exchange and routing key
+    pub fn expect_publish(self) -> PublishExpectation {
+        PublishExpectation { scenario: self }
+    }
+
+    /// Expect a `basic.ack` of the given delivery
+    pub fn expect_basic_ack(self, delivery_tag: LongLongUInt) -> Self {
+        self.step(Step::BasicAck(delivery_tag))
+    }
+
+    /// Deliver a message to the last consumer created by `basic.consume`
+    ///
+    /// Delivery tags start at 1 and are incremented for each delivery.
+    pub fn deliver(self, payload: &[u8]) -> Self {
+        self.step(Step::Deliver(payload.to_vec()))
+    }
+
+    /// Create a connection and a channel following this scenario
+    pub fn run(self) -> ScriptedConnection {
+        let connection = Connection::default();
+        connection.set_state(ConnectionState::Connected);
+        connection.configuration().set_channel_max(2047);
+        let channel = connection
+            .create_open_channel()
+            .expect("failed to create channel");
+        let transcript = Arc::new(Mutex::new(Transcript {
+            expected: self.steps.iter().map(Step::label).collect(),
+            actual: Vec::new(),
+            deviated: false,
+        }));
+        let stop = Arc::new(AtomicBool::new(false));
+        let server = ScriptedServer {
+            connection: connection.clone(),
+            channel_id: channel.id(),
+            steps: self.steps,
+            next_step: 0,
+            transcript: transcript.clone(),
+            consumer_tag: None,
+            delivery_tag: 0,
+            publish_tag: 0,
+            confirm: false,
+        };
+        let stopped = stop.clone();
+        let handle = thread::Builder::new()
+            .name("lapin-scenario".into())
+            .spawn(move || server.run(&stopped))
+            .expect("failed to spawn scenario thread");
+        ScriptedConnection {
+            connection,
+            channel,
+            transcript,
+            stop,
+            handle: Some(handle),
+        }
+    }
+
+    fn step(mut self, step: Step) -> Self {
+        self.steps.push(step);
+        self
+    }
+}
+
+/// A `queue.declare` waiting for its reply
+#[derive(Debug)]
+pub struct QueueDeclareExpectation {
+    scenario: Scenario,
+    queue: ShortString,
+}
+
+impl QueueDeclareExpectation {
+    /// Answer with a `queue.declare-ok` holding the given counts
+    pub fn reply_ok(self, message_count: u32, consumer_count: u32) -> Scenario {
+        self.scenario.step(Step::QueueDeclare(
+            self.queue,
+            message_count,
+            consumer_count,
+        ))
+    }
+}
+
+/// A `basic.publish` waiting for its outcome
+#[derive(Debug)]
+pub struct PublishExpectation {
+    scenario: Scenario,
+}
+
+impl PublishExpectation {
+    /// Confirm the message with a `basic.ack`
+    pub fn ack(self) -> Scenario {
+        self.scenario.step(Step::Publish(Some(true)))
+    }
+
+    /// Reject the message with a `basic.nack`
+    pub fn nack(self) -> Scenario {
+        self.scenario.step(Step::Publish(Some(false)))
+    }
+
+    /// Don't answer, for channels without publisher confirms
+    pub fn sent(self) -> Scenario {
+        self.scenario.step(Step::Publish(None))
+    }
+}
+
+#[derive(Debug)]
+struct Transcript {
+    expected: Vec<String>,
+    actual: Vec<String>,
+    deviated: bool,
+}
+
+impl Transcript {
+    fn matches(&self) -> bool {
+        !self.deviated && self.expected == self.actual
+    }
+
+    fn diff(&self) -> String {
+        let width = self
+            .expected
+            .iter()
+            .map(String::len)
+            .max()
+            .unwrap_or_default()
+            .max("expected".len());
+        let mut diff = String::from("the AMQP conversation did not follow the scenario\n");
+        let _ = writeln!(diff, "   {:width$}   actual", "expected", width = width);
+        for idx in 0..self.expected.len().max(self.actual.len()) {
+            let expected = self.expected.get(idx).map(String::as_str);
+            let actual = self.actual.get(idx).map(String::as_str);
+            let marker = match (expected, actual) {
+                (Some(expected), Some(actual)) if expected == actual => ' ',
+                (Some(_), Some(_)) => '!',
+                (Some(_), None) => '-',
+                (None, _) => '+',
+            };
+            let _ = writeln!(
+                diff,
+                " {} {:width$}   {}",
+                marker,
+                expected.unwrap_or(""),
+                actual.unwrap_or(""),
+                width = width
+            );
+        }
+        diff
+    }
+}
+
+/// Answers the frames sent on the connection according to the scenario
+struct ScriptedServer {
+    connection: Connection,
+    channel_id: u16,
+    steps: Vec<Step>,
+    next_step: usize,
+    transcript: Arc<Mutex<Transcript>>,
+    consumer_tag: Option<ShortString>,
+    delivery_tag: LongLongUInt,
+    publish_tag: LongLongUInt,
+    confirm: bool,
+}
+
+impl ScriptedServer {
+    fn run(mut self, stop: &AtomicBool) {
+        loop {
+            self.deliver();
+            // Check stop before looking for frames, not to miss the ones sent before finishing
+            let stopping = stop.load(Ordering::SeqCst);
+            match self.connection.next_frame() {
+                Some((send_id, frame)) => {
+                    self.connection.metrics().on_frame_sent(&frame);
+                    self.connection.mark_sent(send_id);
+                    if let AMQPFrame::Method(channel_id, method) = frame {
+                        if channel_id == self.channel_id && !self.handle_method(method) {
+                            let _ = self.connection.set_error();
+                            return;
+                        }
+                    }
+                }
+                None if stopping => return,
+                None => thread::sleep(Duration::from_millis(1)),
+            }
+        }
+    }
+
+    /// Send the deliveries scripted before the next expected operation
+    fn deliver(&mut self) {
+        while let Some(Step::Deliver(payload)) = self.steps.get(self.next_step).cloned() {
+            self.next_step += 1;
+            self.transcript
+                .lock()
+                .actual
+                .push(Step::Deliver(Vec::new()).label());
+            self.delivery_tag += 1;
+            let consumer_tag = self.consumer_tag.clone().unwrap_or_default();
+            self.reply(AMQPClass::Basic(basic::AMQPMethod::Deliver(
+                basic::Deliver {
+                    consumer_tag,
+                    delivery_tag: self.delivery_tag,
+                    redelivered: false,
+                    exchange: "".into(),
+                    routing_key: "".into(),
+                },
+            )));
+            self.send(AMQPFrame::Header(
+                self.channel_id,
+                60,
+                Box::new(AMQPContentHeader {
+                    class_id: 60,
+                    weight: 0,
+                    body_size: payload.len() as u64,
+                    properties: BasicProperties::default(),
+                }),
+            ));
+            self.send(AMQPFrame::Body(self.channel_id, payload));
+        }
+    }
+
+    /// Check the method against the scenario and answer it, returning false on deviation
+    fn handle_method(&mut self, method: AMQPClass) -> bool {
+        let label = method_label(&method);
+        trace!("scenario received {}", label);
+        let step = self.steps.get(self.next_step).cloned();
+        {
+            let mut transcript = self.transcript.lock();
+            transcript.actual.push(label.clone());
+            if step.as_ref().map(Step::label).as_ref() != Some(&label) {
+                error!("scenario deviation: unexpected {}", label);
+                transcript.deviated = true;
+                return false;
+            }
+        }
+        self.next_step += 1;
+        match (step, method) {
+            (Some(Step::QueueDeclare(queue, message_count, consumer_count)), _) => self.reply(
+                AMQPClass::Queue(queue::AMQPMethod::DeclareOk(queue::DeclareOk {
+                    queue,
+                    message_count,
+                    consumer_count,
+                })),
+            ),
+            (Some(Step::BasicQos(_)), _) => {
+                self.reply(AMQPClass::Basic(basic::AMQPMethod::QosOk(basic::QosOk {})))
+            }
+            (Some(Step::BasicConsume(_)), AMQPClass::Basic(basic::AMQPMethod::Consume(method))) => {
+                let consumer_tag = if method.consumer_tag.as_str().is_empty() {
+                    GENERATED_CONSUMER_TAG.into()
+                } else {
+                    method.consumer_tag
+                };
+                self.consumer_tag = Some(consumer_tag.clone());
+                self.reply(AMQPClass::Basic(basic::AMQPMethod::ConsumeOk(
+                    basic::ConsumeOk { consumer_tag },
+                )))
+            }
+            (Some(Step::ConfirmSelect), _) => {
+                self.confirm = true;
+                self.reply(AMQPClass::Confirm(confirm::AMQPMethod::SelectOk(
+                    confirm::SelectOk {},
+                )))
+            }
+            (Some(Step::Publish(outcome)), _) => {
+                if self.confirm {
+                    self.publish_tag += 1;
+                }
+                match outcome {
+                    Some(true) => {
+                        self.reply(AMQPClass::Basic(basic::AMQPMethod::Ack(basic::Ack {
+                            delivery_tag: self.publish_tag,
+                            multiple: false,
+                        })))
+                    }
+                    Some(false) => {
+                        self.reply(AMQPClass::Basic(basic::AMQPMethod::Nack(basic::Nack {
+                            delivery_tag: self.publish_tag,
+                            multiple: false,
+                            requeue: false,
+                        })))
+                    }
+                    None => true,
+                }
+            }
+            _ => true,
+        }
+    }
+
+    fn reply(&self, method: AMQPClass) -> bool {
+        self.send(AMQPFrame::Method(self.channel_id, method))
+    }
+
+    fn send(&self, frame: AMQPFrame) -> bool {
+        if let Err(err) = self.connection.handle_frame(frame) {
+            error!("scenario failed to handle its own reply: {:?}", err);
+            self.transcript.lock().deviated = true;
+            return false;
+        }
+        true
+    }
+}
+
+/// A connection driven by a `Scenario`
+///
+/// Dropping it checks the conversation like `finish` does.
+pub struct ScriptedConnection {
+    connection: Connection,
+    channel: Channel,
+    transcript: Arc<Mutex<Transcript>>,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl ScriptedConnection {
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    /// The channel following the scenario
+    pub fn channel(&self) -> &Channel {
+        &self.channel
+    }
+
+    /// Whether the operations performed so far followed the scenario
+    pub fn deviated(&self) -> bool {
+        self.transcript.lock().deviated
+    }
+
+    /// Wait for the sent frames to be handled, then check that all the scripted operations were
+    /// performed, panicking with the expected and actual operations otherwise
+    pub fn finish(mut self) {
+        self.check();
+    }
+
+    fn check(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            self.stop.store(true, Ordering::SeqCst);
+            let _ = handle.join();
+            let transcript = self.transcript.lock();
+            if !transcript.matches() {
+                panic!("{}", transcript.diff());
+            }
+        }
+    }
+}
+
+impl Drop for ScriptedConnection {
+    fn drop(&mut self) {
+        if !thread::panicking() {
+            self.check();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BasicProperties, Error};
+
+    #[test]
+    fn scenario_publish_retry() {
+        let _ = env_logger::try_init();
+
+        use crate::options::{BasicPublishOptions, ConfirmSelectOptions, QueueDeclareOptions};
+        use crate::types::FieldTable;
+
+        let scripted = Scenario::new()
+            .expect_queue_declare("jobs")
+            .reply_ok(5, 1)
+            .expect_confirm_select()
+            .expect_publish()
+            .nack()
+            .expect_publish()
+            .ack()
+            .run();
+        let channel = scripted.channel();
+        let queue = channel
+            .queue_declare(
+                "jobs",
+                QueueDeclareOptions::default(),
+                FieldTable::default(),
+            )
+            .wait()
+            .unwrap();
+        assert_eq!(queue.message_count(), 5);
+        channel
+            .confirm_select(ConfirmSelectOptions::default())
+            .wait()
+            .unwrap();
+        // Retry until the server acks the message
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let res = channel
+                .basic_publish(
+                    "",
+                    "jobs",
+                    BasicPublishOptions::default(),
+                    b"job".to_vec(),
+                    BasicProperties::default(),
+                )
+                .wait();
+            match res {
+                Ok(()) => break,
+                Err(Error::PublishNacked(_)) => continue,
+                Err(err) => panic!("unexpected publish error: {:?}", err),
+            }
+        }
+        assert_eq!(attempts, 2);
+        scripted.finish();
+    }
+
+    #[test]
+    fn scenario_delivery_ack() {
+        let _ = env_logger::try_init();
+
+        use crate::options::{BasicAckOptions, BasicConsumeOptions, QueueDeclareOptions};
+        use crate::types::FieldTable;
+
+        let scripted = Scenario::new()
+            .expect_queue_declare("jobs")
+            .reply_ok(0, 0)
+            .expect_basic_consume("jobs")
+            .deliver(b"job")
+            .expect_basic_ack(1)
+            .run();
+        let channel = scripted.channel();
+        let queue = channel
+            .queue_declare(
+                "jobs",
+                QueueDeclareOptions::default(),
+                FieldTable::default(),
+            )
+            .wait()
+            .unwrap();
+        let consumer = channel
+            .basic_consume(
+                &queue,
+                "",
+                BasicConsumeOptions::default(),
+                FieldTable::default(),
+            )
+            .wait()
+            .unwrap();
+        let delivery = consumer.into_iter().next().unwrap().unwrap();
+        assert_eq!(delivery.data, b"job");
+        delivery
+            .ack(channel, BasicAckOptions::default())
+            .wait()
+            .unwrap();
+        scripted.finish();
+    }
+
+    #[test]
+    #[should_panic(expected = "did not follow the scenario")]
+    fn scenario_deviation() {
+        let _ = env_logger::try_init();
+
+        use crate::options::BasicQosOptions;
+
+        let scripted = Scenario::new()
+            .expect_queue_declare("jobs")
+            .reply_ok(0, 0)
+            .run();
+        let res = scripted
+            .channel()
+            .basic_qos(10, BasicQosOptions::default())
+            .wait();
+        assert!(res.is_err());
+        assert!(scripted.deviated());
+        scripted.finish();
+    }
+}
@@ -0,0 +1,136 @@
+use crate::{connection_status::ConnectionState, ChannelState};
+use std::fmt;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// A snapshot of the state of a `Connection`, meant to be attached to bug reports
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct StateDump {
+    pub connection: ConnectionDump,
+    pub configuration: ConfigurationDump,
+    pub channels: Vec<ChannelDump>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ConnectionDump {
+    pub state: String,
+    pub vhost: String,
+    pub username: String,
+    /// Only known while the connection is being established
+    pub credentials: Option<CredentialsDump>,
+    pub blocked: bool,
+    pub pending_frames: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CredentialsDump {
+    pub username: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ConfigurationDump {
+    pub channel_max: u16,
+    pub frame_max: u32,
+    pub heartbeat: u16,
+    pub send_queue_capacity: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ChannelDump {
+    pub id: u16,
+    pub state: String,
+    pub confirm: bool,
+    pub send_flow: bool,
+    /// The replies we're waiting for from the server
+    pub pending_operations: Vec<String>,
+    pub consumers: Vec<ConsumerDump>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ConsumerDump {
+    pub queue: String,
+    pub tag: String,
+    pub buffered_deliveries: usize,
+}
+
+pub(crate) fn connection_state_label(state: &ConnectionState) -> String {
+    match state {
+        ConnectionState::Initial => "Initial",
+        ConnectionState::SentProtocolHeader(..) => "SentProtocolHeader",
+        ConnectionState::SentStartOk(..) => "SentStartOk",
+        ConnectionState::SentOpen(..) => "SentOpen",
+        ConnectionState::Connected => "Connected",
+        ConnectionState::Closing => "Closing",
+        ConnectionState::Closed => "Closed",
+        ConnectionState::Error => "Error",
+    }
+    .into()
+}
+
+pub(crate) fn channel_state_label(state: &ChannelState) -> String {
+    format!("{:?}", state)
+}
+
+pub(crate) fn credentials_dump(state: &ConnectionState) -> Option<CredentialsDump> {
+    match state {
+        ConnectionState::SentProtocolHeader(_, credentials, _)
+        | ConnectionState::SentStartOk(_, credentials) => Some(CredentialsDump {
+            username: credentials.username().into(),
+        }),
+        _ => None,
+    }
+}
+
+/// Keep only the name of the reply, not its content
+pub(crate) fn operation_label<T: fmt::Debug>(operation: &T) -> String {
+    let mut label = format!("{:?}", operation);
+    if let Some(idx) = label.find('(') {
+        label.truncate(idx);
+    }
+    label
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+    use crate::{connection::Connection, connection_status::ConnectionState, wait::Wait};
+
+    #[test]
+    fn dump_state_json() {
+        let _ = env_logger::try_init();
+
+        use crate::auth::Credentials;
+        use crate::options::BasicQosOptions;
+
+        let (conn, channel, _) = Connection::channel_with_consumer("consumer-tag");
+        let _ = channel.basic_qos(10, BasicQosOptions::default());
+        let (_, wait_handle) = Wait::new();
+        conn.set_state(ConnectionState::SentStartOk(
+            wait_handle,
+            Credentials::new("user".into(), "s3cr3t-t0ken".into()),
+        ));
+
+        assert!(!format!("{:?}", conn.dump_state()).contains("s3cr3t-t0ken"));
+        let json = serde_json::to_string(&conn.dump_state()).unwrap();
+        assert!(!json.contains("s3cr3t-t0ken"));
+        let dump: StateDump = serde_json::from_str(&json).unwrap();
+        assert_eq!(dump.connection.state, "SentStartOk");
+        assert_eq!(
+            dump.connection.credentials.as_ref().unwrap().username,
+            "user"
+        );
+        assert_eq!(dump.configuration.channel_max, 2047);
+        assert_eq!(dump.channels.len(), 2);
+        assert_eq!(dump.channels[1].state, "Connected");
+        assert_eq!(dump.channels[1].pending_operations, vec!["BasicQosOk"]);
+        assert_eq!(dump.channels[1].consumers[0].tag, "consumer-tag");
+        assert_eq!(serde_json::to_string(&dump).unwrap(), json);
+    }
+}
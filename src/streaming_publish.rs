@@ -0,0 +1,262 @@
+use crate::{
+    channel::Channel,
+    confirmation::Confirmation,
+    wait::{NotifyReady, Wait},
+    Error, Result,
+};
+use log::{error, trace};
+use std::io::{self, Read};
+
+/// How many frames a streaming publish can queue when the send queue is unbounded
+const STREAMING_WINDOW: usize = 32;
+
+/// A message being published chunk by chunk
+///
+/// The content header has already been sent with the announced body size, so the whole body
+/// must be sent before calling `finish`. If anything goes wrong in between, the server is left
+/// with a partial message: the channel is then marked as errored and cannot be used anymore.
+#[derive(Debug)]
+pub struct StreamingPublish {
+    channel: Channel,
+    body_size: u64,
+    sent: u64,
+    chunk_size: usize,
+    publisher_confirm: Option<Wait<()>>,
+    last_send: Option<Wait<()>>,
+    done: bool,
+}
+
+impl StreamingPublish {
+    pub(crate) fn new(
+        channel: Channel,
+        body_size: u64,
+        chunk_size: usize,
+        publisher_confirm: Option<Wait<()>>,
+    ) -> Self {
+        Self {
+            channel,
+            body_size,
+            sent: 0,
+            chunk_size,
+            publisher_confirm,
+            last_send: None,
+            done: false,
+        }
+    }
+
+    /// The number of bytes still expected
+    pub fn remaining(&self) -> u64 {
+        self.body_size - self.sent
+    }
+
+    /// The maximum size of a chunk, as limited by the negotiated frame_max
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    /// Whether a chunk can be queued without exceeding the send queue capacity
+    pub fn can_send(&self) -> bool {
+        !self.channel.send_queue_saturated(STREAMING_WINDOW)
+    }
+
+    /// Get notified once some space got freed in the outgoing frames queue
+    pub fn subscribe(&self, task: Box<dyn NotifyReady + Send>) {
+        self.channel.subscribe_send_queue(task);
+    }
+
+    /// Queue a chunk of the body, split in several frames if larger than `chunk_size`
+    pub fn send_chunk(&mut self, chunk: &[u8]) -> Result<()> {
+        if chunk.len() as u64 > self.remaining() {
+            let error = Error::BodySizeMismatch(self.body_size, self.sent + chunk.len() as u64);
+            return Err(self.abort(error));
+        }
+        for frame in chunk.chunks(self.chunk_size) {
+            match self.channel.send_body_frame(frame) {
+                Ok(wait) => {
+                    self.sent += frame.len() as u64;
+                    self.last_send = Some(wait);
+                }
+                Err(error) => return Err(self.abort(error)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Make sure the whole body has been sent
+    ///
+    /// The returned `Confirmation` behaves like the one of `Channel::basic_publish`.
+    pub fn finish(mut self) -> Confirmation<()> {
+        if self.done {
+            return Confirmation::new_error(Error::InvalidChannelState(
+                self.channel.status().state(),
+            ));
+        }
+        if self.remaining() != 0 {
+            let error = Error::BodySizeMismatch(self.body_size, self.sent);
+            return Confirmation::new_error(self.abort(error));
+        }
+        self.done = true;
+        self.channel.end_streaming_publish();
+        match self
+            .publisher_confirm
+            .take()
+            .or_else(|| self.last_send.take())
+        {
+            Some(wait) => Confirmation::new(wait),
+            None => {
+                let (wait, wait_handle) = Wait::new();
+                wait_handle.finish(());
+                Confirmation::new(wait)
+            }
+        }
+    }
+
+    /// Give up on this message, leaving the channel in an errored state
+    pub fn abort(&mut self, error: Error) -> Error {
+        if !self.done {
+            error!(
+                "streaming publish aborted with {} bytes remaining: {}",
+                self.remaining(),
+                error
+            );
+            self.done = true;
+            self.channel.end_streaming_publish();
+            self.channel.poison();
+        }
+        error
+    }
+
+    pub(crate) fn wait_for_capacity(&self) -> Result<()> {
+        self.channel.park_while_send_queue_full(|| !self.can_send());
+        if self.can_send() {
+            Ok(())
+        } else {
+            Err(Error::InvalidChannelState(self.channel.status().state()))
+        }
+    }
+
+    pub(crate) fn send_reader<R: Read>(mut self, mut body: R) -> Confirmation<()> {
+        let mut chunk = vec![0; self.chunk_size];
+        while self.remaining() > 0 {
+            let size = std::cmp::min(self.remaining(), self.chunk_size as u64) as usize;
+            let res = body.read(&mut chunk[..size]);
+            match res {
+                Ok(0) => break,
+                Ok(size) => {
+                    if let Err(error) = self
+                        .wait_for_capacity()
+                        .and_then(|_| self.send_chunk(&chunk[..size]))
+                    {
+                        return Confirmation::new_error(self.abort(error));
+                    }
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Confirmation::new_error(self.abort(Error::IOError(e))),
+            }
+        }
+        if self.remaining() == 0 {
+            trace!("streaming publish: checking the reader is exhausted");
+            match body.read(&mut chunk[..1]) {
+                Ok(0) => {}
+                Ok(size) => {
+                    let error = Error::BodySizeMismatch(self.body_size, self.sent + size as u64);
+                    return Confirmation::new_error(self.abort(error));
+                }
+                Err(e) => return Confirmation::new_error(self.abort(Error::IOError(e))),
+            }
+        }
+        self.finish()
+    }
+}
+
+impl Drop for StreamingPublish {
+    fn drop(&mut self) {
+        if !self.done {
+            self.abort(Error::BodySizeMismatch(self.body_size, self.sent));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{channel_status::ChannelState, connection::Connection, BasicProperties, Error};
+    use amq_protocol::frame::AMQPFrame;
+
+    #[test]
+    fn basic_publish_streaming() {
+        let _ = env_logger::try_init();
+
+        use crate::options::BasicPublishOptions;
+        use std::{io::Read, thread};
+
+        const BODY_SIZE: u64 = 10 * 1024 * 1024;
+
+        let (conn, channel) = Connection::connected_channel();
+        conn.configuration().set_frame_max(8192);
+        // Play the role of the io loop, the publish blocks while too many frames are queued
+        let drain = {
+            let conn = conn.clone();
+            thread::spawn(move || {
+                let mut body_size = 0;
+                let mut received = 0;
+                let mut frames = 0;
+                while received < body_size || body_size == 0 {
+                    match conn.next_frame() {
+                        Some((send_id, frame)) => {
+                            match frame {
+                                AMQPFrame::Header(_, _, header) => body_size = header.body_size,
+                                AMQPFrame::Body(_, payload) => {
+                                    assert!(payload.iter().all(|byte| *byte == 42));
+                                    received += payload.len() as u64;
+                                    frames += 1;
+                                }
+                                _ => {}
+                            }
+                            conn.mark_sent(send_id);
+                        }
+                        None => thread::yield_now(),
+                    }
+                }
+                (body_size, received, frames)
+            })
+        };
+        let confirmation = channel.basic_publish_streaming(
+            "",
+            "queue",
+            BasicPublishOptions::default(),
+            io::repeat(42).take(BODY_SIZE),
+            BODY_SIZE,
+            BasicProperties::default(),
+        );
+        let (body_size, received, frames) = drain.join().unwrap();
+        assert_eq!(body_size, BODY_SIZE);
+        assert_eq!(received, BODY_SIZE);
+        assert_eq!(frames, (BODY_SIZE + 8183) / 8184);
+        assert!(confirmation.wait().is_ok());
+        assert_eq!(channel.status().state(), ChannelState::Connected);
+    }
+
+    #[test]
+    fn basic_publish_streaming_short_read() {
+        let _ = env_logger::try_init();
+
+        use crate::options::BasicPublishOptions;
+
+        let (conn, channel) = Connection::connected_channel();
+        conn.configuration().set_frame_max(8192);
+        let confirmation = channel.basic_publish_streaming(
+            "",
+            "queue",
+            BasicPublishOptions::default(),
+            &b"too short"[..],
+            1024,
+            BasicProperties::default(),
+        );
+        match confirmation.wait() {
+            Err(Error::BodySizeMismatch(1024, 9)) => {}
+            res => panic!("unexpected publish result: {:?}", res),
+        }
+        assert_eq!(channel.status().state(), ChannelState::Error);
+    }
+}
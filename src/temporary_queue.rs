@@ -0,0 +1,127 @@
+use crate::{
+    channel::Channel, confirmation::Confirmation, options::QueueDeleteOptions, queue::Queue,
+    types::LongUInt,
+};
+use std::ops::Deref;
+
+/// An exclusive, server-named queue deleted once dropped, see `Channel::declare_temporary_queue`
+#[derive(Debug)]
+pub struct TemporaryQueue {
+    queue: Queue,
+    channel: Channel,
+    deleted: bool,
+}
+
+impl TemporaryQueue {
+    pub(crate) fn new(queue: Queue, channel: Channel) -> Self {
+        Self {
+            queue,
+            channel,
+            deleted: false,
+        }
+    }
+
+    /// Delete the queue now, resolving to the number of messages deleted along with it
+    pub fn delete(mut self) -> Confirmation<LongUInt> {
+        self.deleted = true;
+        self.channel
+            .queue_delete(self.queue.name().as_str(), QueueDeleteOptions::default())
+    }
+}
+
+impl Deref for TemporaryQueue {
+    type Target = Queue;
+
+    fn deref(&self) -> &Queue {
+        &self.queue
+    }
+}
+
+impl Drop for TemporaryQueue {
+    fn drop(&mut self) {
+        // Nothing to delete once the channel is gone, the server deleting exclusive queues along
+        // with their connection anyway
+        if self.deleted || !self.channel.status().is_connected() {
+            return;
+        }
+        // Drop cannot wait for the server, any error being left to the channel
+        let _ = self.channel.queue_delete(
+            self.queue.name().as_str(),
+            QueueDeleteOptions {
+                nowait: true,
+                ..QueueDeleteOptions::default()
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{channel_status::ChannelState, connection::Connection};
+    use amq_protocol::{frame::AMQPFrame, protocol::AMQPClass};
+
+    #[test]
+    fn temporary_queue() {
+        let _ = env_logger::try_init();
+
+        use amq_protocol::protocol::queue;
+
+        let (conn, channel) = Connection::connected_channel();
+        let declare_temporary_queue = |name: &str| {
+            let declared = channel.declare_temporary_queue();
+            match conn.peek_outgoing().as_slice() {
+                [AMQPFrame::Method(_, AMQPClass::Queue(queue::AMQPMethod::Declare(declare)))] => {
+                    assert!(declare.queue.as_str().is_empty());
+                    assert!(declare.exclusive);
+                    assert!(declare.auto_delete);
+                }
+                frames => panic!("unexpected frames: {:?}", frames),
+            }
+            conn.handle_frame(AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Queue(queue::AMQPMethod::DeclareOk(queue::DeclareOk {
+                    queue: name.into(),
+                    message_count: 0,
+                    consumer_count: 0,
+                })),
+            ))
+            .unwrap();
+            declared.wait().unwrap()
+        };
+
+        let temporary = declare_temporary_queue("amq.gen-dropped");
+        assert_eq!(temporary.name().as_str(), "amq.gen-dropped");
+        drop(temporary);
+        match conn.peek_outgoing().as_slice() {
+            [AMQPFrame::Method(_, AMQPClass::Queue(queue::AMQPMethod::Delete(delete)))] => {
+                assert_eq!(delete.queue.as_str(), "amq.gen-dropped");
+                assert!(delete.nowait);
+            }
+            frames => panic!("unexpected frames: {:?}", frames),
+        }
+
+        let temporary = declare_temporary_queue("amq.gen-deleted");
+        let deleted = temporary.delete();
+        match conn.peek_outgoing().as_slice() {
+            [AMQPFrame::Method(_, AMQPClass::Queue(queue::AMQPMethod::Delete(delete)))] => {
+                assert_eq!(delete.queue.as_str(), "amq.gen-deleted");
+                assert!(!delete.nowait);
+            }
+            frames => panic!("unexpected frames: {:?}", frames),
+        }
+        conn.handle_frame(AMQPFrame::Method(
+            channel.id(),
+            AMQPClass::Queue(queue::AMQPMethod::DeleteOk(queue::DeleteOk {
+                message_count: 3,
+            })),
+        ))
+        .unwrap();
+        assert_eq!(deleted.wait().unwrap(), 3);
+
+        // Nothing is sent on a closed channel
+        let temporary = declare_temporary_queue("amq.gen-closed");
+        channel.set_state(ChannelState::Closed);
+        drop(temporary);
+        assert!(conn.peek_outgoing().is_empty());
+    }
+}
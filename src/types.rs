@@ -0,0 +1,318 @@
+//! The AMQP types, and builders for the `x-arguments` understood by RabbitMQ
+
+pub use amq_protocol::types::*;
+
+/// The integers are sent as signed 32 bits ones when they fit, as most clients do, RabbitMQ
+/// refusing to redeclare a queue with arguments of a different type
+fn integer(value: u64) -> AMQPValue {
+    if value <= i32::max_value() as u64 {
+        AMQPValue::LongInt(value as LongInt)
+    } else {
+        AMQPValue::LongLongInt(value as LongLongInt)
+    }
+}
+
+fn string(value: &str) -> AMQPValue {
+    AMQPValue::LongString(value.into())
+}
+
+fn string_argument<'a>(arguments: &'a FieldTable, name: &str) -> Option<&'a str> {
+    match arguments.inner().get(name) {
+        Some(AMQPValue::LongString(value)) => Some(value.as_str()),
+        Some(AMQPValue::ShortString(value)) => Some(value.as_str()),
+        _ => None,
+    }
+}
+
+/// Check that `pattern` is a valid binding pattern for a topic exchange
+///
+/// The words, separated by dots, are either plain ones or one of the `*` (exactly one word) and
+/// `#` (zero or more words) wildcards: `logs.*.error` and `logs.#` are valid, `logs.err*` or
+/// `logs#` are not.
+pub fn validate_topic_pattern(pattern: &str) -> crate::Result<()> {
+    if pattern.len() > u8::max_value() as usize {
+        return Err(crate::Error::InvalidRoutingKey(format!(
+            "{} is longer than 255 bytes",
+            pattern
+        )));
+    }
+    match pattern
+        .split('.')
+        .find(|word| *word != "*" && *word != "#" && word.contains(|c| c == '*' || c == '#'))
+    {
+        Some(word) => Err(crate::Error::InvalidRoutingKey(format!(
+            "wildcard in word {} of {}",
+            word, pattern
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// What happens to the messages published once a queue reached its maximum length
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueueOverflow {
+    DropHead,
+    RejectPublish,
+    RejectPublishDlx,
+}
+
+impl QueueOverflow {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::DropHead => "drop-head",
+            Self::RejectPublish => "reject-publish",
+            Self::RejectPublishDlx => "reject-publish-dlx",
+        }
+    }
+}
+
+/// The `x-queue-type` of a queue
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueueType {
+    Classic,
+    Quorum,
+}
+
+impl QueueType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Classic => "classic",
+            Self::Quorum => "quorum",
+        }
+    }
+
+    /// The type requested by the `x-queue-type` argument, if any and known
+    pub(crate) fn from_arguments(arguments: &FieldTable) -> Option<Self> {
+        match string_argument(arguments, "x-queue-type") {
+            Some("classic") => Some(Self::Classic),
+            Some("quorum") => Some(Self::Quorum),
+            _ => None,
+        }
+    }
+}
+
+/// The `x-queue-mode` of a classic queue
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueueMode {
+    Default,
+    Lazy,
+}
+
+impl QueueMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Default => "default",
+            Self::Lazy => "lazy",
+        }
+    }
+
+    /// The mode requested by the `x-queue-mode` argument, if any and known
+    pub(crate) fn from_arguments(arguments: &FieldTable) -> Option<Self> {
+        match string_argument(arguments, "x-queue-mode") {
+            Some("default") => Some(Self::Default),
+            Some("lazy") => Some(Self::Lazy),
+            _ => None,
+        }
+    }
+}
+
+/// The arguments of `queue_declare`, with the value types RabbitMQ expects
+///
+/// ```rust
+/// use lapin::types::{FieldTable, QueueArguments};
+///
+/// let arguments: FieldTable = QueueArguments::default()
+///     .with_message_ttl(60_000)
+///     .with_dead_letter_exchange("dead-letters")
+///     .into();
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct QueueArguments(FieldTable);
+
+impl QueueArguments {
+    /// The arguments of a quorum queue, to be declared durable, and neither exclusive nor
+    /// auto-delete, see `Channel::queue_declare`
+    pub fn quorum() -> Self {
+        Self::default().with_queue_type(QueueType::Quorum)
+    }
+
+    /// The arguments of a lazy classic queue, keeping its messages on disk
+    pub fn lazy() -> Self {
+        Self::default().with_queue_mode(QueueMode::Lazy)
+    }
+
+    /// `x-message-ttl`, in milliseconds
+    pub fn with_message_ttl(self, ttl: u32) -> Self {
+        self.with_argument("x-message-ttl", integer(ttl.into()))
+    }
+
+    /// `x-expires`: how long the queue can stay unused before being deleted, in milliseconds
+    pub fn with_expires(self, expires: u32) -> Self {
+        self.with_argument("x-expires", integer(expires.into()))
+    }
+
+    /// `x-dead-letter-exchange`
+    pub fn with_dead_letter_exchange(self, exchange: &str) -> Self {
+        self.with_argument("x-dead-letter-exchange", string(exchange))
+    }
+
+    /// `x-dead-letter-routing-key`, the routing key of the message being kept otherwise
+    pub fn with_dead_letter_routing_key(self, routing_key: &str) -> Self {
+        self.with_argument("x-dead-letter-routing-key", string(routing_key))
+    }
+
+    /// `x-max-length`, in messages
+    pub fn with_max_length(self, max_length: u32) -> Self {
+        self.with_argument("x-max-length", integer(max_length.into()))
+    }
+
+    /// `x-max-length-bytes`, counting the message bodies only
+    pub fn with_max_length_bytes(self, max_length_bytes: u64) -> Self {
+        self.with_argument("x-max-length-bytes", integer(max_length_bytes))
+    }
+
+    /// `x-overflow`
+    pub fn with_overflow(self, overflow: QueueOverflow) -> Self {
+        self.with_argument("x-overflow", string(overflow.as_str()))
+    }
+
+    /// `x-queue-mode`
+    pub fn with_queue_mode(self, mode: QueueMode) -> Self {
+        self.with_argument("x-queue-mode", string(mode.as_str()))
+    }
+
+    /// `x-queue-type`
+    pub fn with_queue_type(self, queue_type: QueueType) -> Self {
+        self.with_argument("x-queue-type", string(queue_type.as_str()))
+    }
+
+    /// `x-max-priority`, RabbitMQ supporting up to 255 priorities
+    pub fn with_max_priority(self, max_priority: u8) -> Self {
+        self.with_argument("x-max-priority", integer(max_priority.into()))
+    }
+
+    /// Any other argument
+    pub fn with_argument(mut self, name: &str, value: AMQPValue) -> Self {
+        self.0.insert(name.into(), value);
+        self
+    }
+}
+
+impl From<QueueArguments> for FieldTable {
+    fn from(arguments: QueueArguments) -> Self {
+        arguments.0
+    }
+}
+
+/// The arguments of `exchange_declare`, with the value types RabbitMQ expects
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ExchangeArguments(FieldTable);
+
+impl ExchangeArguments {
+    /// `alternate-exchange`: where the messages which cannot be routed go
+    pub fn with_alternate_exchange(self, exchange: &str) -> Self {
+        self.with_argument("alternate-exchange", string(exchange))
+    }
+
+    /// `x-delayed-type`: the type of routing of the `x-delayed-message` plugin exchanges
+    pub fn with_delayed_type(self, kind: &crate::ExchangeKind) -> Self {
+        self.with_argument("x-delayed-type", string(kind.as_str()))
+    }
+
+    /// Any other argument
+    pub fn with_argument(mut self, name: &str, value: AMQPValue) -> Self {
+        self.0.insert(name.into(), value);
+        self
+    }
+}
+
+impl From<ExchangeArguments> for FieldTable {
+    fn from(arguments: ExchangeArguments) -> Self {
+        arguments.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(arguments: Vec<(&str, AMQPValue)>) -> FieldTable {
+        let mut table = FieldTable::default();
+        for (name, value) in arguments {
+            table.insert(name.into(), value);
+        }
+        table
+    }
+
+    #[test]
+    fn queue_arguments() {
+        let arguments: FieldTable = QueueArguments::default()
+            .with_message_ttl(60_000)
+            .with_expires(3_000_000_000)
+            .with_dead_letter_exchange("dead-letters")
+            .with_dead_letter_routing_key("expired")
+            .with_max_length(1_000)
+            .with_max_length_bytes(1 << 40)
+            .with_overflow(QueueOverflow::RejectPublishDlx)
+            .with_queue_mode(QueueMode::Lazy)
+            .with_queue_type(QueueType::Classic)
+            .with_max_priority(10)
+            .into();
+        assert_eq!(
+            arguments,
+            table(vec![
+                ("x-message-ttl", AMQPValue::LongInt(60_000)),
+                ("x-expires", AMQPValue::LongLongInt(3_000_000_000)),
+                (
+                    "x-dead-letter-exchange",
+                    AMQPValue::LongString("dead-letters".into())
+                ),
+                (
+                    "x-dead-letter-routing-key",
+                    AMQPValue::LongString("expired".into())
+                ),
+                ("x-max-length", AMQPValue::LongInt(1_000)),
+                ("x-max-length-bytes", AMQPValue::LongLongInt(1 << 40)),
+                (
+                    "x-overflow",
+                    AMQPValue::LongString("reject-publish-dlx".into())
+                ),
+                ("x-queue-mode", AMQPValue::LongString("lazy".into())),
+                ("x-queue-type", AMQPValue::LongString("classic".into())),
+                ("x-max-priority", AMQPValue::LongInt(10)),
+            ])
+        );
+    }
+
+    #[test]
+    fn exchange_arguments() {
+        let arguments: FieldTable = ExchangeArguments::default()
+            .with_alternate_exchange("unroutable")
+            .with_delayed_type(&crate::ExchangeKind::Topic)
+            .into();
+        assert_eq!(
+            arguments,
+            table(vec![
+                (
+                    "alternate-exchange",
+                    AMQPValue::LongString("unroutable".into())
+                ),
+                ("x-delayed-type", AMQPValue::LongString("topic".into())),
+            ])
+        );
+    }
+
+    #[test]
+    fn topic_patterns() {
+        for pattern in &["", "logs", "logs.*.error", "logs.#", "#", "*.*", "a..b"] {
+            assert!(validate_topic_pattern(pattern).is_ok(), "{}", pattern);
+        }
+        for pattern in &["logs#", "logs.err*", "*logs", "#.a*b", "logs.**"] {
+            match validate_topic_pattern(pattern) {
+                Err(crate::Error::InvalidRoutingKey(_)) => {}
+                res => panic!("unexpected result for {}: {:?}", pattern, res),
+            }
+        }
+        assert!(validate_topic_pattern(&"a".repeat(256)).is_err());
+    }
+}
@@ -0,0 +1,67 @@
+use crate::{acknowledgement::DeliveryTag, types::ShortString};
+use parking_lot::Mutex;
+use std::{
+    collections::{BTreeSet, HashSet},
+    sync::Arc,
+};
+
+/// The deliveries received on a channel which we still have to acknowledge
+#[derive(Clone, Debug, Default)]
+pub(crate) struct UnackedDeliveries {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl UnackedDeliveries {
+    pub(crate) fn register_no_ack_consumer(&self, consumer_tag: ShortString) {
+        self.inner.lock().no_ack_consumers.insert(consumer_tag);
+    }
+
+    pub(crate) fn deregister_consumer(&self, consumer_tag: &str) {
+        self.inner.lock().no_ack_consumers.remove(consumer_tag);
+    }
+
+    pub(crate) fn register_consumer_delivery(&self, consumer_tag: &str, delivery_tag: DeliveryTag) {
+        let mut inner = self.inner.lock();
+        if !inner.no_ack_consumers.contains(consumer_tag) {
+            inner.delivery_tags.insert(delivery_tag);
+        }
+    }
+
+    pub(crate) fn register(&self, delivery_tag: DeliveryTag) {
+        self.inner.lock().delivery_tags.insert(delivery_tag);
+    }
+
+    /// Forget about the deliveries we acked, nacked or rejected, delivery_tag 0 with multiple
+    /// meaning all of them
+    pub(crate) fn settle(&self, delivery_tag: DeliveryTag, multiple: bool) {
+        let mut inner = self.inner.lock();
+        if multiple {
+            if delivery_tag == 0 {
+                inner.delivery_tags.clear();
+            } else {
+                inner.delivery_tags = inner.delivery_tags.split_off(&(delivery_tag + 1));
+            }
+        } else {
+            inner.delivery_tags.remove(&delivery_tag);
+        }
+    }
+
+    pub(crate) fn clear(&self) {
+        self.inner.lock().delivery_tags.clear();
+    }
+
+    pub(crate) fn count(&self) -> usize {
+        self.inner.lock().delivery_tags.len()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn delivery_tags(&self) -> Vec<DeliveryTag> {
+        self.inner.lock().delivery_tags.iter().cloned().collect()
+    }
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    delivery_tags: BTreeSet<DeliveryTag>,
+    no_ack_consumers: HashSet<ShortString>,
+}
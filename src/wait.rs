@@ -6,24 +6,43 @@ use std::{
         mpsc::{sync_channel, Receiver, SyncSender},
         Arc,
     },
+    thread::Thread,
+    time::Duration,
 };
 
 pub struct Wait<T> {
     recv: Receiver<Result<T>>,
     send: SyncSender<Result<T>>,
-    task: Arc<Mutex<Option<Box<dyn NotifyReady + Send>>>>,
+    subscription: Arc<Mutex<Subscription>>,
 }
 
 #[derive(Clone)]
 pub struct WaitHandle<T> {
     send: SyncSender<Result<T>>,
-    task: Arc<Mutex<Option<Box<dyn NotifyReady + Send>>>>,
+    subscription: Arc<Mutex<Subscription>>,
+}
+
+/// The task to notify on completion, both being set under the same lock so that a task
+/// subscribing right after completion still gets notified
+#[derive(Default)]
+struct Subscription {
+    task: Option<Box<dyn NotifyReady + Send>>,
+    completed: bool,
 }
 
 pub trait NotifyReady {
     fn notify(&self);
 }
 
+/// Wakes up a thread parked waiting for a notification
+pub(crate) struct ThreadNotifier(pub(crate) Thread);
+
+impl NotifyReady for ThreadNotifier {
+    fn notify(&self) {
+        self.0.unpark();
+    }
+}
+
 pub(crate) trait Cancellable: fmt::Debug {
     fn cancel(&self, error: Error);
 }
@@ -40,7 +59,7 @@ impl<T> Wait<T> {
         let wait = Self {
             recv,
             send,
-            task: Arc::new(Mutex::new(None)),
+            subscription: Arc::new(Mutex::new(Subscription::default())),
         };
         let wait_handle = wait.handle();
         (wait, wait_handle)
@@ -49,7 +68,7 @@ impl<T> Wait<T> {
     fn handle(&self) -> WaitHandle<T> {
         WaitHandle {
             send: self.send.clone(),
-            task: self.task.clone(),
+            subscription: self.subscription.clone(),
         }
     }
 
@@ -61,12 +80,22 @@ impl<T> Wait<T> {
         self.recv.recv().unwrap()
     }
 
+    pub(crate) fn wait_timeout(&self, timeout: Duration) -> Option<Result<T>> {
+        // We keep a sender around, the receiver can only time out
+        self.recv.recv_timeout(timeout).ok()
+    }
+
     pub(crate) fn subscribe(&self, task: Box<dyn NotifyReady + Send>) {
-        *self.task.lock() = Some(task);
+        let mut subscription = self.subscription.lock();
+        if subscription.completed {
+            task.notify();
+        } else {
+            subscription.task = Some(task);
+        }
     }
 
     pub(crate) fn has_subscriber(&self) -> bool {
-        self.task.lock().is_some()
+        self.subscription.lock().task.is_some()
     }
 }
 
@@ -82,7 +111,9 @@ impl<T> WaitHandle<T> {
     }
 
     fn notify(&self) {
-        if let Some(task) = self.task.lock().take() {
+        let mut subscription = self.subscription.lock();
+        subscription.completed = true;
+        if let Some(task) = subscription.task.take() {
             task.notify();
         }
     }
@@ -99,3 +130,46 @@ impl<T> fmt::Debug for WaitHandle<T> {
         write!(f, "WaitHandle")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Barrier,
+        },
+        thread,
+    };
+
+    struct Counter(Arc<AtomicUsize>);
+
+    impl NotifyReady for Counter {
+        fn notify(&self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn subscribe_while_completing() {
+        for _ in 0..1000 {
+            let (wait, wait_handle) = Wait::new();
+            let notified = Arc::new(AtomicUsize::new(0));
+            let barrier = Arc::new(Barrier::new(2));
+            let completer = {
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    barrier.wait();
+                    wait_handle.finish(());
+                })
+            };
+            barrier.wait();
+            // Whether we subscribe before or after completion, we get notified exactly once
+            wait.subscribe(Box::new(Counter(notified.clone())));
+            completer.join().unwrap();
+            assert_eq!(notified.load(Ordering::SeqCst), 1);
+            assert!(wait.try_wait().unwrap().is_ok());
+        }
+    }
+}
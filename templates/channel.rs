@@ -71,7 +71,7 @@ impl Channel {
     if !self.status.is_connected() {
     {{/if ~}}
     {{/if ~}}
-      return Confirmation::new_error(Error::NotConnected);
+      return Confirmation::new_error(self.not_connected_error());
     }
 
     {{#if method.metadata.start_hook ~}}
@@ -114,9 +114,9 @@ impl Channel {
     let (wait, {{#if method.metadata.bypass_wait_handle ~}}_{{/if ~}}wait_handle) = Wait::new();
     {{/if ~}}
     {{#if method.metadata.carry_headers ~}}
-    let send_res = self.send_method_frame_with_body(method, payload, properties);
+    let send_res = self.send_method_frame_with_body(method{{#each method.metadata.extra_args as |arg| ~}}, {{arg.name}}{{/each ~}});
     {{else}}
-    let send_res = self.send_method_frame(method, {{#if method.synchronous ~}}Some((Reply::{{camel class.name}}{{camel method.name}}Ok({{#if method.metadata.bypass_wait_handle ~}}_{{/if ~}}wait_handle.clone(){{#each method.metadata.state as |state| ~}}, {{state.name}}{{#if state.use_str_ref ~}}.into(){{/if ~}}{{/each ~}}), Box::new({{#if method.metadata.bypass_wait_handle ~}}_{{/if ~}}wait_handle))){{else}}None{{/if ~}});
+    let send_res = self.send_method_frame{{#if method.metadata.after_content ~}}_after_content{{/if ~}}(method, {{#if method.synchronous ~}}Some((Reply::{{camel class.name}}{{camel method.name}}Ok({{#if method.metadata.bypass_wait_handle ~}}_{{/if ~}}wait_handle.clone(){{#each method.metadata.state as |state| ~}}, {{state.name}}{{#if state.use_str_ref ~}}.into(){{/if ~}}{{/each ~}}), Box::new({{#if method.metadata.bypass_wait_handle ~}}_{{/if ~}}wait_handle))){{else}}None{{/if ~}});
     {{/if ~}}
     if let Err(err) = send_res {
       return Confirmation::new_error(err);
@@ -140,8 +140,13 @@ impl Channel {
     {{/if ~}}
     Confirmation::new(wait)
     {{else}}
+    {{#if method.metadata.confirm_on_queue ~}}
+    // The end hook tears down what the frame was sent on, there is nothing left to wait for
+    Confirmation::new_ok(())
+    {{else}}
     Confirmation::new(send_res.unwrap())
     {{/if ~}}
+    {{/if ~}}
   }
   {{/if ~}}
 
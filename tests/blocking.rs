@@ -0,0 +1,39 @@
+use lapin::{BasicProperties, BlockingClient, ConnectionProperties};
+
+mod mock_broker;
+
+use mock_broker::{MockBroker, Summary};
+
+const PAYLOAD: &[u8] = b"hello from the blocking client";
+
+#[test]
+fn publish_consume() {
+    let _ = env_logger::try_init();
+
+    let broker = MockBroker::start();
+    let mut client =
+        BlockingClient::connect(broker.uri(), ConnectionProperties::default()).expect("connect");
+    let queue = client.declare("blocking").expect("declare");
+    client
+        .publish("", "blocking", PAYLOAD.to_vec(), BasicProperties::default())
+        .expect("publish");
+    let consumer = client
+        .consume(&queue, "blocking-consumer")
+        .expect("consume");
+    let delivery = client
+        .next_delivery(&consumer)
+        .expect("next_delivery")
+        .expect("consumer cancelled");
+    assert_eq!(delivery.data, PAYLOAD);
+    client.ack(&delivery).expect("ack");
+    client.close().expect("close");
+
+    assert_eq!(
+        broker.finish(),
+        Summary {
+            published: vec![PAYLOAD.to_vec()],
+            acked: vec![delivery.delivery_tag],
+            closed: true,
+        }
+    );
+}
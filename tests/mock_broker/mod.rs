@@ -0,0 +1,242 @@
+//! A broker serving a single connection over TCP, answering the connection, channel, queue
+//! declare, publish, consume and ack methods, to run the client without a server
+//!
+//! It only depends on amq-protocol, to be shared by the tests of lapin and lapin-futures.
+
+use amq_protocol::frame::{gen_frame, parse_frame, AMQPContentHeader, AMQPFrame};
+use amq_protocol::protocol::{basic, channel, connection, queue, AMQPClass, BasicProperties};
+use amq_protocol::types::FieldTable;
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream},
+    thread::{self, JoinHandle},
+};
+
+/// What the client did, as seen by the broker
+#[derive(Debug, Default, PartialEq)]
+pub struct Summary {
+    pub published: Vec<Vec<u8>>,
+    pub acked: Vec<u64>,
+    pub closed: bool,
+}
+
+pub struct MockBroker {
+    uri: String,
+    handle: JoinHandle<io::Result<Summary>>,
+}
+
+impl MockBroker {
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let uri = format!("amqp://{}/%2f", listener.local_addr().unwrap());
+        let handle = thread::Builder::new()
+            .name("mock-broker".into())
+            .spawn(move || {
+                let (stream, _) = listener.accept()?;
+                Session::new(stream).run()
+            })
+            .unwrap();
+        Self { uri, handle }
+    }
+
+    pub fn uri(&self) -> &str {
+        &self.uri
+    }
+
+    /// Wait for the client to close the connection
+    pub fn finish(self) -> Summary {
+        self.handle.join().unwrap().unwrap()
+    }
+}
+
+struct Session {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+    queues: HashMap<String, VecDeque<Vec<u8>>>,
+    consumers: HashMap<String, (u16, String)>,
+    publishing: Option<String>,
+    delivery_tag: u64,
+    summary: Summary,
+}
+
+impl Session {
+    fn new(stream: TcpStream) -> Self {
+        Self {
+            stream,
+            buffer: Vec::new(),
+            queues: HashMap::new(),
+            consumers: HashMap::new(),
+            publishing: None,
+            delivery_tag: 0,
+            summary: Summary::default(),
+        }
+    }
+
+    fn run(mut self) -> io::Result<Summary> {
+        while !self.summary.closed {
+            let frame = self.read_frame()?;
+            self.handle(frame)?;
+        }
+        Ok(self.summary)
+    }
+
+    fn read_frame(&mut self) -> io::Result<AMQPFrame> {
+        loop {
+            if let Ok((rest, frame)) = parse_frame(&self.buffer) {
+                let consumed = self.buffer.len() - rest.len();
+                self.buffer.drain(..consumed);
+                return Ok(frame);
+            }
+            let mut chunk = [0; 4096];
+            let read = self.stream.read(&mut chunk)?;
+            if read == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            self.buffer.extend_from_slice(&chunk[..read]);
+        }
+    }
+
+    fn send(&mut self, frame: AMQPFrame) -> io::Result<()> {
+        let buffer = gen_frame(&frame)(Vec::new().into())
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{:?}", e)))?
+            .into_inner()
+            .0;
+        self.stream.write_all(&buffer)
+    }
+
+    fn handle(&mut self, frame: AMQPFrame) -> io::Result<()> {
+        match frame {
+            AMQPFrame::ProtocolHeader => self.send(AMQPFrame::Method(
+                0,
+                AMQPClass::Connection(connection::AMQPMethod::Start(connection::Start {
+                    version_major: 0,
+                    version_minor: 9,
+                    server_properties: FieldTable::default(),
+                    mechanisms: "PLAIN".into(),
+                    locales: "en_US".into(),
+                })),
+            )),
+            AMQPFrame::Method(0, AMQPClass::Connection(method)) => match method {
+                connection::AMQPMethod::StartOk(_) => self.send(AMQPFrame::Method(
+                    0,
+                    AMQPClass::Connection(connection::AMQPMethod::Tune(connection::Tune {
+                        channel_max: 2047,
+                        frame_max: 131_072,
+                        heartbeat: 0,
+                    })),
+                )),
+                connection::AMQPMethod::Open(_) => self.send(AMQPFrame::Method(
+                    0,
+                    AMQPClass::Connection(connection::AMQPMethod::OpenOk(connection::OpenOk {})),
+                )),
+                connection::AMQPMethod::Close(_) => {
+                    self.summary.closed = true;
+                    self.send(AMQPFrame::Method(
+                        0,
+                        AMQPClass::Connection(connection::AMQPMethod::CloseOk(
+                            connection::CloseOk {},
+                        )),
+                    ))
+                }
+                _ => Ok(()),
+            },
+            AMQPFrame::Method(channel_id, AMQPClass::Channel(method)) => match method {
+                channel::AMQPMethod::Open(_) => self.send(AMQPFrame::Method(
+                    channel_id,
+                    AMQPClass::Channel(channel::AMQPMethod::OpenOk(channel::OpenOk {})),
+                )),
+                channel::AMQPMethod::Close(_) => self.send(AMQPFrame::Method(
+                    channel_id,
+                    AMQPClass::Channel(channel::AMQPMethod::CloseOk(channel::CloseOk {})),
+                )),
+                _ => Ok(()),
+            },
+            AMQPFrame::Method(
+                channel_id,
+                AMQPClass::Queue(queue::AMQPMethod::Declare(declare)),
+            ) => {
+                let name = declare.queue.as_str().to_string();
+                let message_count = self.queues.entry(name.clone()).or_default().len();
+                self.send(AMQPFrame::Method(
+                    channel_id,
+                    AMQPClass::Queue(queue::AMQPMethod::DeclareOk(queue::DeclareOk {
+                        queue: name.into(),
+                        message_count: message_count as u32,
+                        consumer_count: 0,
+                    })),
+                ))
+            }
+            AMQPFrame::Method(channel_id, AMQPClass::Basic(method)) => match method {
+                basic::AMQPMethod::Publish(publish) => {
+                    self.publishing = Some(publish.routing_key.as_str().to_string());
+                    Ok(())
+                }
+                basic::AMQPMethod::Consume(consume) => {
+                    let queue = consume.queue.as_str().to_string();
+                    let consumer_tag = consume.consumer_tag.as_str().to_string();
+                    self.send(AMQPFrame::Method(
+                        channel_id,
+                        AMQPClass::Basic(basic::AMQPMethod::ConsumeOk(basic::ConsumeOk {
+                            consumer_tag: consumer_tag.as_str().into(),
+                        })),
+                    ))?;
+                    self.consumers
+                        .insert(queue.clone(), (channel_id, consumer_tag));
+                    self.deliver(&queue)
+                }
+                basic::AMQPMethod::Ack(ack) => {
+                    self.summary.acked.push(ack.delivery_tag);
+                    Ok(())
+                }
+                _ => Ok(()),
+            },
+            // The messages of the tests fit in a single body frame
+            AMQPFrame::Body(_, body) => match self.publishing.take() {
+                Some(queue) => {
+                    self.summary.published.push(body.clone());
+                    self.queues
+                        .entry(queue.clone())
+                        .or_default()
+                        .push_back(body);
+                    self.deliver(&queue)
+                }
+                None => Ok(()),
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// Send the messages of `queue` to its consumer, if it has one
+    fn deliver(&mut self, queue: &str) -> io::Result<()> {
+        let (channel_id, consumer_tag) = match self.consumers.get(queue) {
+            Some(consumer) => consumer.clone(),
+            None => return Ok(()),
+        };
+        while let Some(body) = self.queues.get_mut(queue).and_then(VecDeque::pop_front) {
+            self.delivery_tag += 1;
+            self.send(AMQPFrame::Method(
+                channel_id,
+                AMQPClass::Basic(basic::AMQPMethod::Deliver(basic::Deliver {
+                    consumer_tag: consumer_tag.as_str().into(),
+                    delivery_tag: self.delivery_tag,
+                    redelivered: false,
+                    exchange: "".into(),
+                    routing_key: queue.into(),
+                })),
+            ))?;
+            self.send(AMQPFrame::Header(
+                channel_id,
+                60,
+                Box::new(AMQPContentHeader {
+                    class_id: 60,
+                    weight: 0,
+                    body_size: body.len() as u64,
+                    properties: BasicProperties::default(),
+                }),
+            ))?;
+            self.send(AMQPFrame::Body(channel_id, body))?;
+        }
+        Ok(())
+    }
+}